@@ -0,0 +1,108 @@
+//! Micro-benchmarks of the mining hot path: `cargo bench`.
+
+use bitcoin::Network;
+use bitcoin::consensus::Encodable;
+use criterion::{BatchSize, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use solo_lottery_miner::hasher::{self, Hasher};
+use solo_lottery_miner::{miner, pow};
+use solo_lottery_miner::template::TemplateTransaction;
+
+const REWARD_ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+const NBITS: u32 = 0x1702_3a04;
+
+/// A one-input, one-output transaction spending a made-up outpoint, as a template entry.
+fn template_transaction(i: u32) -> TemplateTransaction {
+    let tx = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint { txid: fake_txid(i), vout: 0 },
+            ..Default::default()
+        }],
+        output: vec![bitcoin::TxOut { value: bitcoin::Amount::from_sat(10_000), script_pubkey: bitcoin::ScriptBuf::new() }],
+    };
+    let mut raw = Vec::new();
+    tx.consensus_encode(&mut raw).unwrap();
+    TemplateTransaction {
+        data: hex::encode(raw),
+        txid: tx.compute_txid().to_string(),
+        hash: None,
+        depends: Vec::new(),
+        fee: 1_000,
+        sigops: Some(0),
+        weight: None,
+    }
+}
+
+/// A made-up txid whose first bytes are the little-endian index.
+fn fake_txid(i: u32) -> bitcoin::Txid {
+    use bitcoin::hashes::Hash;
+    let mut bytes = [0u8; 32];
+    bytes[..4].copy_from_slice(&i.to_le_bytes());
+    bitcoin::Txid::from_byte_array(bytes)
+}
+
+fn header() -> [u8; 80] {
+    miner::build_header(0x2000_0000, &[0x11; 32], &[0x22; 32], 1_700_000_000, NBITS, 0)
+}
+
+fn bench_sha256d(c: &mut Criterion) {
+    let header = header();
+    c.bench_function("sha256d/80-byte header", |b| b.iter(|| miner::sha256d(black_box(&header))));
+}
+
+fn bench_header(c: &mut Criterion) {
+    c.bench_function("header/build", |b| {
+        b.iter(|| miner::build_header(black_box(0x2000_0000), &[0x11; 32], &[0x22; 32], black_box(1_700_000_000), NBITS, black_box(7)))
+    });
+}
+
+fn bench_target(c: &mut Criterion) {
+    let target = miner::compact_to_target(NBITS);
+    let hash = miner::sha256d(&header());
+    c.bench_function("target/compact_to_target", |b| b.iter(|| miner::compact_to_target(black_box(NBITS))));
+    c.bench_function("target/meets_target", |b| b.iter(|| pow::meets_target(black_box(&hash), black_box(&target))));
+}
+
+fn bench_merkle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merkle_root");
+    for count in [0u32, 100, 3000] {
+        let transactions: Vec<TemplateTransaction> = (0..count).map(template_transaction).collect();
+        group.throughput(Throughput::Elements(count as u64 + 1));
+        group.bench_function(format!("{} transactions", count), |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |txs| miner::calculate_merkle_root(REWARD_ADDRESS, &[], &[], b"/solo-miner/rust-pi-edu/", Network::Bitcoin, 840_000, 312_500_000 + count as u64 * 1_000, &txs, &[], &Default::default()).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Full scan throughput of every hashing backend compiled in: `cargo bench --all-features`.
+fn bench_scan(c: &mut Criterion) {
+    const BATCH: u32 = 65_536;
+    let target = miner::compact_to_target(NBITS);
+    fn scan<H: Hasher>(b: &mut criterion::Bencher, target: &[u8; 32]) {
+        let mut header = header();
+        b.iter(|| black_box(H::scan(&mut header, 0..=BATCH - 1, target)))
+    }
+    let mut group = c.benchmark_group("scan");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    group.bench_function(hasher::Sha2::BACKEND.name(), |b| scan::<hasher::Sha2>(b, &target));
+    group.bench_function(hasher::Simd::BACKEND.name(), |b| scan::<hasher::Simd>(b, &target));
+    // Every nonce hashed in full, as the backends without midstate support do
+    group.bench_function("sha2 (full header)", |b| {
+        let mut header = header();
+        b.iter(|| black_box(pow::scan(&mut header, 0..=BATCH - 1, &target, hasher::Sha2::sha256d)))
+    });
+    #[cfg(feature = "ring")]
+    group.bench_function(hasher::Ring::BACKEND.name(), |b| scan::<hasher::Ring>(b, &target));
+    #[cfg(feature = "openssl")]
+    group.bench_function(hasher::Openssl::BACKEND.name(), |b| scan::<hasher::Openssl>(b, &target));
+    group.finish();
+}
+
+criterion_group!(benches, bench_sha256d, bench_header, bench_target, bench_merkle, bench_scan);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+//! A worker speaking the worker protocol over TCP, to try `workers` without a board
+//! and as a reference for firmware: `cargo run --release --example worker_sim -- 0.0.0.0:4028`,
+//! then add "127.0.0.1:4028" to `workers` in miner_config.json.
+//!
+//! It hashes in chunks and checks for new messages between them, the way a
+//! microcontroller would poll its UART.
+
+use solo_lottery_miner::pow;
+use solo_lottery_miner::worker::{Message, PROTOCOL_VERSION};
+use std::io::{self, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+/// Nonces hashed between two looks at the connection.
+const CHUNK: u32 = 4096;
+
+/// The range being hashed and what is needed to hash it.
+struct Job {
+    id: u8,
+    next: u32,
+    last: u32,
+    midstate: [u32; 8],
+    tail: [u8; 12],
+    target: [u8; 32],
+    hashes: u32,
+}
+
+fn main() -> io::Result<()> {
+    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:4028".to_string());
+    let listener = TcpListener::bind(&address)?;
+    println!("Worker simulator listening on {}", address);
+    for stream in listener.incoming() {
+        if let Err(e) = serve(stream?) {
+            println!("Host disconnected: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn serve(stream: TcpStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    // Read frames on their own thread so the hashing loop only has to poll a channel
+    let (tx, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        while let Ok(message) = Message::read(&mut reader) {
+            if tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let started = Instant::now();
+    let mut hashes_total: u64 = 0;
+    let mut job = None;
+    loop {
+        let message = if job.is_some() { messages.try_recv().ok() } else { messages.recv().ok() };
+        match message {
+            Some(Message::Ping) => {
+                let hashrate = (hashes_total as f64 / started.elapsed().as_secs_f64().max(1.0)) as u32;
+                let hello = Message::Hello { version: PROTOCOL_VERSION, hashrate, name: "worker_sim".to_string() };
+                writer.write_all(&hello.encode())?;
+            },
+            Some(Message::Job { job_id, nonces, midstate, tail, target }) => {
+                job = Some(Job { id: job_id, next: *nonces.start(), last: *nonces.end(), midstate, tail, target, hashes: 0 });
+            },
+            Some(Message::Stop) => job = None,
+            Some(_) => {},
+            None if job.is_none() => return Err(io::ErrorKind::UnexpectedEof.into()),
+            None => {},
+        }
+
+        let Some(current) = &mut job else { continue };
+        let chunk_end = current.last.min(current.next.saturating_add(CHUNK - 1));
+        let mut start = current.next;
+        while let Some((nonce, _)) = pow::scan_midstate(&current.midstate, &current.tail, start..=chunk_end, &current.target) {
+            writer.write_all(&Message::Nonce { job_id: current.id, nonce }.encode())?;
+            match nonce.checked_add(1) {
+                Some(n) if n <= chunk_end => start = n,
+                _ => break,
+            }
+        }
+        current.hashes += chunk_end - current.next + 1;
+        hashes_total += u64::from(chunk_end - current.next) + 1;
+        if chunk_end == current.last {
+            writer.write_all(&Message::Done { job_id: current.id, hashes: current.hashes }.encode())?;
+            job = None;
+        } else {
+            current.next = chunk_end + 1;
+        }
+    }
+}
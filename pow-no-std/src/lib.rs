@@ -0,0 +1,8 @@
+//! The miner's proof-of-work core, `src/pow.rs`, built without `std`. Nothing else
+//! lives here: the crate only exists so that `cargo check -p pow-no-std` (and every
+//! workspace build) proves the module still compiles for embedded and WASM targets.
+
+#![no_std]
+
+#[path = "../../src/pow.rs"]
+pub mod pow;
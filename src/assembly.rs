@@ -0,0 +1,417 @@
+use crate::merkle;
+use crate::template::{BlockTemplate, TemplateTransaction};
+use std::io;
+use bitcoin::consensus::Decodable;
+
+/// Consensus limit on block weight (BIP141).
+pub const MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+
+/// Consensus limit on the total sigops cost of a block (BIP141).
+pub const MAX_BLOCK_SIGOPS_COST: u64 = 80_000;
+
+/// Consensus limit on a block's serialized size, witness included.
+pub const MAX_BLOCK_SERIALIZED_SIZE: u64 = 4_000_000;
+
+/// Legacy (non-witness) sigops count four times towards the sigops cost.
+const WITNESS_SCALE_FACTOR: u64 = 4;
+
+/// The 80-byte header is non-witness data, so it counts four times.
+const HEADER_WEIGHT: u64 = 80 * 4;
+
+/// Prefix of the witness commitment output script: OP_RETURN, push 36 bytes, 0xaa21a9ed.
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// The transactions chosen for a block, plus running totals gathered while assembling it.
+#[derive(Debug, Clone)]
+pub struct AssembledBlock {
+    /// Coinbase first, then the selected template transactions.
+    pub txdata: Vec<bitcoin::Transaction>,
+    /// Total block weight, including the header.
+    pub weight: u64,
+    /// Total serialized block size in bytes, including the header.
+    pub size: u64,
+    /// Fees collected by the included transactions.
+    pub fees: u64,
+    /// Total sigops cost of the coinbase and included transactions.
+    pub sigops_cost: u64,
+}
+
+/// The limits a block is assembled within: the template's (`weightlimit`, `sigoplimit`
+/// and `sizelimit`), which a server may set below the consensus limits, but never above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    pub weight: u64,
+    pub sigops_cost: u64,
+    pub size: u64,
+}
+
+impl BlockLimits {
+    pub const CONSENSUS: BlockLimits = BlockLimits {
+        weight: MAX_BLOCK_WEIGHT,
+        sigops_cost: MAX_BLOCK_SIGOPS_COST,
+        size: MAX_BLOCK_SERIALIZED_SIZE,
+    };
+
+    /// The limits a template sets. A missing limit (0) is the consensus one. Without a
+    /// `weightlimit` the template is pre-segwit, and its `sigoplimit` counts legacy
+    /// sigops, each costing 4.
+    pub fn from_template(template: &BlockTemplate) -> BlockLimits {
+        let given = |limit: u64, consensus: u64| if limit == 0 { consensus } else { limit.min(consensus) };
+        let size = given(template.sizelimit, MAX_BLOCK_SERIALIZED_SIZE);
+        let (weight, sigops_cost) = match template.weightlimit {
+            Some(weight) => (weight, template.sigoplimit),
+            None => (template.sizelimit.saturating_mul(WITNESS_SCALE_FACTOR), template.sigoplimit.saturating_mul(WITNESS_SCALE_FACTOR)),
+        };
+        BlockLimits {
+            weight: given(weight, MAX_BLOCK_WEIGHT),
+            sigops_cost: given(sigops_cost, MAX_BLOCK_SIGOPS_COST),
+            size,
+        }
+    }
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits::CONSENSUS
+    }
+}
+
+/// A template transaction decoded and measured for assembly.
+struct Candidate {
+    tx: bitcoin::Transaction,
+    fee: u64,
+    weight: u64,
+    size: u64,
+    sigops_cost: u64,
+    depends: Vec<usize>,
+    included: bool,
+    /// Priority transactions (and their ancestors) are never dropped.
+    pinned: bool,
+}
+
+/// Assembles the block from our coinbase and the template transactions.
+///
+/// The node sized the template for its own coinbase; ours may be larger or carry
+/// more sigops, so cumulative weight, sigops cost and size are tracked and, if any
+/// exceeds its limit in `limits`, the lowest-feerate transactions (and anything that
+/// spends from them) are dropped.
+/// Transactions listed in `priority_txids` are never dropped.
+/// Fees of dropped transactions are deducted from the coinbase payout, in output order.
+pub fn assemble_block(
+    mut coinbase: bitcoin::Transaction,
+    template_txs: &[TemplateTransaction],
+    priority_txids: &[String],
+    limits: &BlockLimits,
+) -> io::Result<AssembledBlock> {
+    let mut candidates = Vec::with_capacity(template_txs.len());
+    for (i, t) in template_txs.iter().enumerate() {
+        let raw = hex::decode(&t.data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Template transaction {} is not valid hex: {}", i + 1, e)))?;
+        let tx = bitcoin::Transaction::consensus_decode(&mut raw.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Template transaction {} failed to decode: {}", i + 1, e)))?;
+        if tx.compute_txid().to_string() != t.txid {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Template transaction {} decodes to txid {} but the node listed {}", i + 1, tx.compute_txid(), t.txid
+            )));
+        }
+        // Prefer the node's figure: it includes P2SH and witness sigops, which need the
+        // spent outputs to count. Without it, fall back to the legacy count as a lower bound.
+        let sigops_cost = t.sigops.unwrap_or_else(|| legacy_sigops_cost(&tx));
+        candidates.push(Candidate {
+            weight: tx.weight().to_wu(),
+            sigops_cost,
+            size: raw.len() as u64,
+            fee: t.fee,
+            depends: t.depends.clone(),
+            included: true,
+            pinned: false,
+            tx,
+        });
+    }
+
+    for txid in priority_txids {
+        match template_txs.iter().position(|t| &t.txid == txid) {
+            Some(index) => pin_with_ancestors(&mut candidates, index),
+            None => println!("[Assembly] Warning: priority transaction {} is not in the template; it can't be included in this block.", txid),
+        }
+    }
+
+    // Any witness data in the block requires a commitment in the coinbase (BIP141).
+    // Add it up front so its weight is accounted for; the real value is filled in below.
+    let needs_commitment = candidates.iter().any(|c| c.tx.input.iter().any(|i| !i.witness.is_empty()));
+    if needs_commitment {
+        coinbase.input[0].witness = bitcoin::Witness::from_slice(&[[0u8; 32]]);
+        coinbase.output.push(bitcoin::TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: witness_commitment_script(&[0u8; 32]),
+        });
+    }
+
+    let coinbase_weight = coinbase.weight().to_wu();
+    let block_weight = |candidates: &[Candidate]| {
+        let count = 1 + candidates.iter().filter(|c| c.included).count() as u64;
+        HEADER_WEIGHT
+            + varint_len(count) * 4
+            + coinbase_weight
+            + candidates.iter().filter(|c| c.included).map(|c| c.weight).sum::<u64>()
+    };
+
+    let coinbase_sigops_cost = legacy_sigops_cost(&coinbase);
+    let block_sigops_cost = |candidates: &[Candidate]| {
+        coinbase_sigops_cost + candidates.iter().filter(|c| c.included).map(|c| c.sigops_cost).sum::<u64>()
+    };
+
+    let coinbase_size = coinbase.total_size() as u64;
+    let block_size = |candidates: &[Candidate]| {
+        let count = 1 + candidates.iter().filter(|c| c.included).count() as u64;
+        80 + varint_len(count) + coinbase_size + candidates.iter().filter(|c| c.included).map(|c| c.size).sum::<u64>()
+    };
+
+    let mut weight = block_weight(&candidates);
+    let mut sigops_cost = block_sigops_cost(&candidates);
+    let mut size = block_size(&candidates);
+    let mut dropped_fees = 0u64;
+    while weight > limits.weight || sigops_cost > limits.sigops_cost || size > limits.size {
+        // Lowest feerate first; compare fee_a/weight_a < fee_b/weight_b without division.
+        let victim = candidates.iter().enumerate()
+            .filter(|(_, c)| c.included && !c.pinned)
+            .min_by(|(_, a), (_, b)| ((a.fee as u128) * (b.weight as u128)).cmp(&((b.fee as u128) * (a.weight as u128))))
+            .map(|(i, _)| i);
+        let Some(victim) = victim else { break };
+        dropped_fees += drop_with_descendants(&mut candidates, victim);
+        weight = block_weight(&candidates);
+        sigops_cost = block_sigops_cost(&candidates);
+        size = block_size(&candidates);
+    }
+
+    if weight > limits.weight {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "Block weight {} exceeds the {} limit even with only priority transactions left", weight, limits.weight
+        )));
+    }
+    if sigops_cost > limits.sigops_cost {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "Block sigops cost {} exceeds the {} limit even with only priority transactions left", sigops_cost, limits.sigops_cost
+        )));
+    }
+    if size > limits.size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "Block size {} exceeds the {} limit even with only priority transactions left", size, limits.size
+        )));
+    }
+
+    if dropped_fees > 0 {
+        let dropped = candidates.iter().filter(|c| !c.included).count();
+        println!("[Assembly] Dropped {} lowest-feerate transaction(s) ({} sats in fees) to stay within weight/sigops/size limits.", dropped, dropped_fees);
+        // A single output may not cover it when the reward is split
+        let mut owed = dropped_fees;
+        for output in &mut coinbase.output {
+            let taken = owed.min(output.value.to_sat());
+            output.value -= bitcoin::Amount::from_sat(taken);
+            owed -= taken;
+        }
+        if owed > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Dropped fees exceed the coinbase payout"));
+        }
+    }
+
+    let fees = candidates.iter().filter(|c| c.included).map(|c| c.fee).sum();
+
+    let mut txdata = vec![coinbase];
+    txdata.extend(candidates.into_iter().filter(|c| c.included).map(|c| c.tx));
+
+    if needs_commitment {
+        fill_witness_commitment(&mut txdata)?;
+    }
+
+    Ok(AssembledBlock { txdata, weight, size, fees, sigops_cost })
+}
+
+/// Marks a transaction and, transitively, every transaction it depends on as pinned.
+fn pin_with_ancestors(candidates: &mut [Candidate], index: usize) {
+    if candidates[index].pinned {
+        return;
+    }
+    candidates[index].pinned = true;
+    // `depends` uses 1-based indexes into the template's transaction list.
+    for parent in candidates[index].depends.clone() {
+        if (1..=candidates.len()).contains(&parent) {
+            pin_with_ancestors(candidates, parent - 1);
+        }
+    }
+}
+
+/// Marks a transaction and, transitively, every transaction depending on it as excluded.
+/// Returns the total fees removed.
+fn drop_with_descendants(candidates: &mut [Candidate], index: usize) -> u64 {
+    if !candidates[index].included {
+        return 0;
+    }
+    candidates[index].included = false;
+    let mut fees = candidates[index].fee;
+
+    // `depends` uses 1-based indexes into the template's transaction list.
+    let dependents: Vec<usize> = candidates.iter().enumerate()
+        .filter(|(_, c)| c.included && c.depends.contains(&(index + 1)))
+        .map(|(i, _)| i)
+        .collect();
+    for dependent in dependents {
+        fees += drop_with_descendants(candidates, dependent);
+    }
+    fees
+}
+
+/// Computes the witness commitment over the final transaction list and writes it
+/// into the placeholder output of the coinbase.
+fn fill_witness_commitment(txdata: &mut [bitcoin::Transaction]) -> io::Result<()> {
+    let witness_root = merkle::witness_root(txdata)?;
+
+    // commitment = SHA256d(witness root || witness reserved value), reserved value = 32 zero bytes.
+    let commitment = crate::miner::sha256d(&[witness_root, [0u8; 32]].concat());
+
+    let coinbase = &mut txdata[0];
+    if let Some(output) = coinbase.output.iter_mut().rev().find(|o| o.script_pubkey.as_bytes().starts_with(&WITNESS_COMMITMENT_HEADER)) {
+        output.script_pubkey = witness_commitment_script(&commitment);
+    }
+    Ok(())
+}
+
+/// Sigops cost of the signature operations visible without the spent outputs:
+/// those in every scriptSig and scriptPubKey, scaled by the witness factor.
+fn legacy_sigops_cost(tx: &bitcoin::Transaction) -> u64 {
+    let inputs = tx.input.iter().map(|i| i.script_sig.count_sigops_legacy());
+    let outputs = tx.output.iter().map(|o| o.script_pubkey.count_sigops_legacy());
+    inputs.chain(outputs).sum::<usize>() as u64 * WITNESS_SCALE_FACTOR
+}
+
+/// Builds the OP_RETURN output script carrying a witness commitment.
+fn witness_commitment_script(commitment: &[u8; 32]) -> bitcoin::ScriptBuf {
+    let mut script = WITNESS_COMMITMENT_HEADER.to_vec();
+    script.extend_from_slice(commitment);
+    bitcoin::ScriptBuf::from_bytes(script)
+}
+
+/// Length in bytes of Bitcoin's CompactSize encoding of `n`.
+fn varint_len(n: u64) -> u64 {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+
+    fn transaction(seed: u8, witness: bool) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid: bitcoin::Txid::from_byte_array([seed; 32]), vout: 0 },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: if witness { Witness::from_slice(&[[seed; 72]]) } else { Witness::new() },
+            }],
+            output: vec![TxOut { value: Amount::from_sat(1000), script_pubkey: ScriptBuf::new() }],
+        }
+    }
+
+    fn template_transaction(tx: &Transaction) -> TemplateTransaction {
+        TemplateTransaction {
+            data: bitcoin::consensus::encode::serialize_hex(tx),
+            txid: tx.compute_txid().to_string(),
+            hash: Some(tx.compute_wtxid().to_string()),
+            depends: Vec::new(),
+            fee: 100,
+            sigops: Some(0),
+            weight: Some(tx.weight().to_wu()),
+        }
+    }
+
+    fn coinbase() -> Transaction {
+        let mut coinbase = transaction(0, false);
+        coinbase.input[0].previous_output = OutPoint::null();
+        coinbase.input[0].script_sig = ScriptBuf::from_bytes(vec![1, 1]);
+        coinbase.output[0].value = Amount::from_sat(5_000_000_000);
+        coinbase
+    }
+
+    fn block(txdata: Vec<Transaction>) -> bitcoin::Block {
+        bitcoin::Block { header: bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header, txdata }
+    }
+
+    #[test]
+    fn witness_commitment_matches_the_block() {
+        let txs = [transaction(1, false), transaction(2, true), transaction(3, true)];
+        let template: Vec<_> = txs.iter().map(template_transaction).collect();
+        let assembled = assemble_block(coinbase(), &template, &[], &BlockLimits::default()).unwrap();
+        let commitments = assembled.txdata[0].output.iter()
+            .filter(|o| o.script_pubkey.as_bytes().starts_with(&WITNESS_COMMITMENT_HEADER))
+            .count();
+        assert_eq!(commitments, 1);
+        assert!(block(assembled.txdata).check_witness_commitment());
+    }
+
+    #[test]
+    fn blocks_without_witness_data_get_no_commitment() {
+        let template = [template_transaction(&transaction(1, false))];
+        let assembled = assemble_block(coinbase(), &template, &[], &BlockLimits::default()).unwrap();
+        assert_eq!(assembled.txdata[0].output.len(), 1);
+        assert!(assembled.txdata[0].input[0].witness.is_empty());
+    }
+
+    #[test]
+    fn merkle_root_matches_the_block() {
+        let txs = [transaction(1, false), transaction(2, true)];
+        let template: Vec<_> = txs.iter().map(template_transaction).collect();
+        let assembled = assemble_block(coinbase(), &template, &[], &BlockLimits::default()).unwrap();
+        let root = merkle::txid_root(&assembled.txdata).unwrap();
+        let mut block = block(assembled.txdata);
+        block.header.merkle_root = bitcoin::TxMerkleNode::from_byte_array(root);
+        assert!(block.check_merkle_root());
+    }
+
+    #[test]
+    fn the_lowest_feerate_transactions_make_way_for_the_limits() {
+        let txs = [transaction(1, false), transaction(2, false), transaction(3, false)];
+        let mut template: Vec<_> = txs.iter().map(template_transaction).collect();
+        template[1].fee = 10;
+        let full = assemble_block(coinbase(), &template, &[], &BlockLimits::default()).unwrap();
+
+        // One byte short of room for everything: the cheapest transaction goes, with its fee
+        for limits in [
+            BlockLimits { weight: full.weight - 1, ..BlockLimits::CONSENSUS },
+            BlockLimits { size: full.size - 1, ..BlockLimits::CONSENSUS },
+        ] {
+            let block = assemble_block(coinbase(), &template, &[], &limits).unwrap();
+            let txids: Vec<_> = block.txdata.iter().skip(1).map(|tx| tx.compute_txid()).collect();
+            assert_eq!(txids, [txs[0].compute_txid(), txs[2].compute_txid()]);
+            assert!(block.weight <= limits.weight && block.size <= limits.size);
+            assert_eq!(block.fees, 200);
+            assert_eq!(block.txdata[0].output[0].value, Amount::from_sat(5_000_000_000 - 10));
+        }
+    }
+
+    #[test]
+    fn priority_transactions_are_never_dropped() {
+        let txs = [transaction(1, false), transaction(2, false)];
+        let mut template: Vec<_> = txs.iter().map(template_transaction).collect();
+        template[1].fee = 10;
+        let full = assemble_block(coinbase(), &template, &[], &BlockLimits::default()).unwrap();
+        let limits = BlockLimits { weight: full.weight - 1, ..BlockLimits::CONSENSUS };
+        let block = assemble_block(coinbase(), &template, std::slice::from_ref(&template[1].txid), &limits).unwrap();
+        let txids: Vec<_> = block.txdata.iter().skip(1).map(|tx| tx.compute_txid()).collect();
+        assert_eq!(txids, [txs[1].compute_txid()]);
+
+        // With nothing left to drop, the block can't be built
+        let limits = BlockLimits { weight: block.weight - 1, ..BlockLimits::CONSENSUS };
+        assert!(assemble_block(coinbase(), &template, std::slice::from_ref(&template[1].txid), &limits).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Best-share attestations (the `attestation_key` setting): the session's best share,
+//! signed as a Bitcoin message with the participant's key, so a classroom or community
+//! can rank everyone's best hash without trusting anyone's screenshots. The signature
+//! is the one `signmessage` makes, so `bitcoin-cli verifymessage` checks it too.
+//!
+//! A signature alone would let anyone claim a header someone else published, so the
+//! miner also names the key in an OP_RETURN output of its coinbase. The attestation
+//! carries that coinbase and its merkle branch, which lead to the header's merkle root:
+//! a header can only be claimed by the one key its coinbase names.
+
+use crate::merkle;
+use crate::pow;
+use crate::settings::MinerSettings;
+use crate::stats::{self, BestShare, StatsHandle};
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::sign_message::{MessageSignature, signed_msg_hash};
+use bitcoin::consensus::encode;
+use bitcoin::{Address, PrivateKey, Transaction};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::{fs, io};
+
+/// Where the attestation of the session's best share is written.
+pub const ATTESTATION_FILE: &str = "best_share_attestation.json";
+
+/// Starts the coinbase OP_RETURN data naming the attestation key; its pubkey hash follows.
+const COMMITMENT_PREFIX: &[u8] = b"best-share:";
+
+/// A signed claim to a share. `message` is what was signed; the other fields repeat
+/// its contents for convenience and are checked against it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attestation {
+    pub name: Option<String>,
+    /// The 80-byte header, in hex.
+    pub header: String,
+    /// Double SHA-256 of the header, in display order.
+    pub hash: String,
+    pub difficulty: f64,
+    /// Unix time the share was found.
+    pub found_at: u64,
+    /// P2PKH address of the signing key.
+    pub address: String,
+    pub message: String,
+    /// Base64 message signature, as `signmessage` produces.
+    pub signature: String,
+    /// The share's coinbase transaction, in hex, naming the signing key.
+    pub coinbase: String,
+    /// Hashes combining the coinbase txid into the header's merkle root, in hex
+    /// (internal byte order).
+    pub merkle_branch: Vec<String>,
+}
+
+/// The text that gets signed.
+fn message(name: Option<&str>, header: &str, hash: &str, found_at: u64) -> String {
+    format!(
+        "Solo Lottery Miner best share\nName: {}\nHeader: {}\nHash: {}\nFound at: {}",
+        name.unwrap_or(""), header, hash, found_at
+    )
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_key(key: &str) -> io::Result<PrivateKey> {
+    PrivateKey::from_wif(key).map_err(|e| invalid(format!("Invalid attestation_key: {}", e)))
+}
+
+/// The OP_RETURN data a coinbase carries to name the WIF private key `key`.
+pub fn coinbase_commitment(key: &str) -> io::Result<Vec<u8>> {
+    let key = parse_key(key)?;
+    let pubkey_hash = key.public_key(&Secp256k1::signing_only()).pubkey_hash();
+    Ok([COMMITMENT_PREFIX, pubkey_hash.as_byte_array()].concat())
+}
+
+/// The pubkey hashes the outputs of `coinbase` name as attesters.
+fn committed_keys(coinbase: &Transaction) -> Vec<Vec<u8>> {
+    coinbase.output.iter()
+        .filter(|output| output.script_pubkey.is_op_return())
+        .filter_map(|output| output.script_pubkey.instructions().nth(1)?.ok()?.push_bytes().map(|data| data.as_bytes().to_vec()))
+        .filter_map(|data| data.strip_prefix(COMMITMENT_PREFIX).map(<[u8]>::to_vec))
+        .collect()
+}
+
+/// Signs `share`, found on the block with transactions `txdata`, with the WIF private
+/// key `key`. The block's coinbase must name the key.
+pub fn sign(key: &str, name: Option<&str>, share: &BestShare, txdata: &[Transaction]) -> io::Result<Attestation> {
+    let key = parse_key(key)?;
+    let secp = Secp256k1::new();
+    let coinbase = txdata.first().ok_or_else(|| invalid("The share's block has no coinbase".to_string()))?;
+    if committed_keys(coinbase) != [key.public_key(&secp).pubkey_hash().as_byte_array().to_vec()] {
+        return Err(invalid("The share's coinbase doesn't name the attestation key".to_string()));
+    }
+    let message = message(name, &share.header, &share.hash, share.found_at);
+    let digest = Message::from_digest(signed_msg_hash(&message).to_byte_array());
+    let signature = MessageSignature::new(secp.sign_ecdsa_recoverable(&digest, &key.inner), key.compressed);
+    Ok(Attestation {
+        name: name.map(str::to_string),
+        header: share.header.clone(),
+        hash: share.hash.clone(),
+        difficulty: share.difficulty,
+        found_at: share.found_at,
+        address: Address::p2pkh(key.public_key(&secp), key.network).to_string(),
+        message,
+        signature: signature.to_base64(),
+        coinbase: encode::serialize_hex(coinbase),
+        merkle_branch: merkle::coinbase_branch(txdata).iter().map(hex::encode).collect(),
+    })
+}
+
+/// Checks that `attestation` is signed by its address, that its header really hashes
+/// to its hash, and that the header commits to a coinbase naming that address's key
+/// and no other. Returns the difficulty, recomputed from the header.
+pub fn verify(attestation: &Attestation) -> io::Result<f64> {
+    let mut header = [0u8; 80];
+    hex::decode_to_slice(&attestation.header, &mut header).map_err(|e| invalid(format!("Invalid header: {}", e)))?;
+    let mut hash = pow::sha256d(&header);
+    hash.reverse();
+    if hex::encode(hash) != attestation.hash {
+        return Err(invalid(format!("The header hashes to {}, not {}", hex::encode(hash), attestation.hash)));
+    }
+    if attestation.message != message(attestation.name.as_deref(), &attestation.header, &attestation.hash, attestation.found_at) {
+        return Err(invalid("The signed message doesn't match the attested share".to_string()));
+    }
+    let address = Address::from_str(&attestation.address)
+        .map_err(|e| invalid(format!("Invalid address: {}", e)))?
+        .assume_checked();
+    let signature = MessageSignature::from_base64(&attestation.signature).map_err(|e| invalid(format!("Invalid signature: {}", e)))?;
+    let signed = signature
+        .is_signed_by_address(&Secp256k1::verification_only(), &address, signed_msg_hash(&attestation.message))
+        .map_err(|e| invalid(format!("Invalid signature: {}", e)))?;
+    if !signed {
+        return Err(invalid(format!("Not signed by {}", attestation.address)));
+    }
+
+    let coinbase: Transaction = encode::deserialize_hex(&attestation.coinbase).map_err(|e| invalid(format!("Invalid coinbase: {}", e)))?;
+    if !coinbase.is_coinbase() {
+        return Err(invalid("The coinbase is not a coinbase transaction".to_string()));
+    }
+    let branch = attestation.merkle_branch.iter()
+        .map(|node| {
+            let mut hash = [0u8; 32];
+            hex::decode_to_slice(node, &mut hash).map(|_| hash)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(format!("Invalid merkle branch: {}", e)))?;
+    if merkle::root_from_branch(coinbase.compute_txid().to_byte_array(), &branch) != header[36..68] {
+        return Err(invalid("The coinbase and merkle branch don't lead to the header's merkle root".to_string()));
+    }
+    let pubkey_hash = address.pubkey_hash().ok_or_else(|| invalid(format!("{} is not a P2PKH address", attestation.address)))?;
+    if committed_keys(&coinbase) != [pubkey_hash.as_byte_array().to_vec()] {
+        return Err(invalid(format!("The share's coinbase doesn't name {} as its only attester", attestation.address)));
+    }
+    Ok(stats::share_difficulty(&hash))
+}
+
+/// Signs the session's best share, found on the job in `settings` with the block
+/// transactions `txdata`, and writes it to the attestation file, if an
+/// `attestation_key` is configured. Pool work has no `txdata`: the pool's coinbase
+/// doesn't name the key, so its shares can't be attested.
+pub fn save_best(settings: &MinerSettings, stats: &StatsHandle, txdata: Option<&[Transaction]>) -> io::Result<()> {
+    let (Some(key), Some(share)) = (&settings.attestation_key, stats.snapshot().best_share) else {
+        return Ok(());
+    };
+    let txdata = txdata.ok_or_else(|| invalid("Shares on pool work can't be attested: the pool's coinbase doesn't name the key".to_string()))?;
+    let attestation = sign(key, settings.attestation_name.as_deref(), &share, txdata)?;
+    fs::write(ATTESTATION_FILE, serde_json::to_string_pretty(&attestation)?)?;
+    println!(
+        "[Attestation] New best share, difficulty {:.1}, signed by {} in {}.",
+        attestation.difficulty, attestation.address, ATTESTATION_FILE
+    );
+    Ok(())
+}
+
+/// Verifies attestation files and prints them ranked by difficulty, the best first.
+/// A header can only be attested by the key its coinbase names, so a header in more
+/// than one file is the same attester's, and counted once.
+pub fn print_leaderboard(paths: &[String]) {
+    let mut valid: Vec<(f64, Attestation)> = Vec::new();
+    for path in paths {
+        let result = fs::read_to_string(path)
+            .and_then(|data| serde_json::from_str::<Attestation>(&data).map_err(io::Error::from))
+            .and_then(|attestation| verify(&attestation).map(|difficulty| (difficulty, attestation)));
+        match result {
+            Ok(entry) => valid.push(entry),
+            Err(e) => println!("[Attestation] {}: rejected: {}", path, e),
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    valid.retain(|(_, attestation)| {
+        let first = seen.insert(attestation.header.clone());
+        if !first {
+            println!("[Attestation] {} attested the same header twice; counted once.", attestation.address);
+        }
+        first
+    });
+    valid.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    println!("\n--- Best Share Leaderboard ---");
+    for (rank, (difficulty, attestation)) in valid.iter().enumerate() {
+        println!(
+            "{:>3}. {:>16.1}  {}  {}",
+            rank + 1, difficulty, attestation.name.as_deref().unwrap_or("(unnamed)"), attestation.address
+        );
+    }
+    if valid.is_empty() {
+        println!("No valid attestations.");
+    }
+}
@@ -0,0 +1,110 @@
+//! Template audits (the `audit_blocks` setting): when the network finds the block we
+//! were working on, compare it with the template we assembled — which transactions
+//! both picked, the fee totals and the timestamps. Large differences point at
+//! assembly bugs, or at a node whose mempool lags the miners'; small ones show how
+//! real pools fill their blocks.
+
+use crate::assembly::AssembledBlock;
+use crate::rest;
+use crate::settings::MinerSettings;
+use crate::units::format_btc;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io;
+
+/// How many txids to list on each side of the difference.
+const LISTED_TXIDS: usize = 5;
+
+/// What we were mining, kept to compare with the block that replaces it.
+#[derive(Debug, Clone)]
+pub struct TemplateSnapshot {
+    /// ID of the job built from the template.
+    pub job_id: String,
+    /// Height of the block the template was for.
+    pub height: u64,
+    /// Txids of the transactions we included, without the coinbase.
+    pub txids: Vec<String>,
+    pub fees_sats: u64,
+    /// Header timestamp we started with.
+    pub time: u32,
+}
+
+impl TemplateSnapshot {
+    pub fn new(settings: &MinerSettings, block: &AssembledBlock) -> Self {
+        TemplateSnapshot {
+            job_id: settings.job_id.clone(),
+            height: settings.height,
+            txids: block.txdata.iter().skip(1).map(|tx| tx.compute_txid().to_string()).collect(),
+            fees_sats: block.fees,
+            time: settings.timestamp,
+        }
+    }
+}
+
+/// The subset of getblock (verbosity 1) the audit needs.
+#[derive(Deserialize)]
+struct BlockInfo {
+    height: u64,
+    time: u32,
+    tx: Vec<String>,
+}
+
+/// Prints how the block the network found at our template's height differs from it.
+pub fn audit_height(settings: &MinerSettings, client: &reqwest::blocking::Client, ours: &TemplateSnapshot) -> io::Result<()> {
+    let hash = rest::chain_query(settings, client, "getblockhash", serde_json::json!([ours.height]))?;
+    let hash = hash.as_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unexpected getblockhash result"))?;
+    audit_block(settings, client, hash, ours)
+}
+
+/// Fetches `block_hash` and prints how it differs from `ours`.
+pub fn audit_block(settings: &MinerSettings, client: &reqwest::blocking::Client, block_hash: &str, ours: &TemplateSnapshot) -> io::Result<()> {
+    let block: BlockInfo = serde_json::from_value(settings.rpc_call(client, "getblock", serde_json::json!([block_hash, 1]))?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected getblock result: {}", e)))?;
+    // Fee totals need the block's undo data, which pruned nodes may have dropped
+    let their_fees = settings.rpc_call(client, "getblockstats", serde_json::json!([block_hash, ["totalfee"]]))
+        .ok()
+        .and_then(|stats| stats["totalfee"].as_u64());
+
+    let theirs: HashSet<&str> = block.tx.iter().skip(1).map(String::as_str).collect();
+    let ours_set: HashSet<&str> = ours.txids.iter().map(String::as_str).collect();
+    let only_ours: Vec<&str> = ours.txids.iter().map(String::as_str).filter(|txid| !theirs.contains(txid)).collect();
+    let only_theirs: Vec<&str> = block.tx.iter().skip(1).map(String::as_str).filter(|txid| !ours_set.contains(txid)).collect();
+
+    println!("\n[Audit] Block {} at height {} vs our template for height {} (job {}):", block_hash, block.height, ours.height, ours.job_id);
+    println!(
+        "[Audit]   Transactions: theirs {}, ours {}; {} in both, {} only in ours, {} only in theirs.",
+        theirs.len(), ours.txids.len(), ours.txids.len() - only_ours.len(), only_ours.len(), only_theirs.len()
+    );
+    match their_fees {
+        Some(their_fees) => {
+            let difference = ours.fees_sats as i64 - their_fees as i64;
+            let percent = if their_fees > 0 { format!(", {:+.1}%", difference as f64 / their_fees as f64 * 100.0) } else { String::new() };
+            println!(
+                "[Audit]   Fees: theirs {} BTC, ours {} BTC ({:+} sats{}).",
+                format_btc(their_fees), format_btc(ours.fees_sats), difference, percent
+            );
+        },
+        None => println!("[Audit]   Fees: ours {} BTC; theirs unknown (getblockstats failed).", format_btc(ours.fees_sats)),
+    }
+    println!(
+        "[Audit]   Timestamp: theirs {}, ours {} ({:+}s).",
+        block.time, ours.time, i64::from(block.time) - i64::from(ours.time)
+    );
+    // Transactions only we had were usually mined earlier by others or replaced;
+    // transactions only they had arrived after our template, or were sent to the pool directly
+    if !only_ours.is_empty() {
+        println!("[Audit]   Only in ours: {}", list(&only_ours));
+    }
+    if !only_theirs.is_empty() {
+        println!("[Audit]   Only in theirs: {}", list(&only_theirs));
+    }
+    Ok(())
+}
+
+fn list(txids: &[&str]) -> String {
+    let shown = txids[..txids.len().min(LISTED_TXIDS)].join(", ");
+    match txids.len().checked_sub(LISTED_TXIDS) {
+        Some(more) if more > 0 => format!("{} and {} more", shown, more),
+        _ => shown,
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// RPC connection details recovered from a node's configuration.
+#[derive(Debug, Clone)]
+pub struct NodeRpcConfig {
+    pub url: String,
+    pub user: String,
+    pub pass: String,
+    /// The network the configuration selects ("main", "test", "signet" or "regtest").
+    pub network: String,
+}
+
+/// Reads `bitcoin.conf` at `path` ("auto" for the platform's default location)
+/// and works out how to reach the node's RPC server, the same way bitcoind does:
+/// network-specific `[section]` options override top-level ones, `rpcport`
+/// defaults per network, and without `rpcuser`/`rpcpassword` the node's
+/// `.cookie` file is used.
+pub fn read(path: &str) -> io::Result<NodeRpcConfig> {
+    let path = if path == "auto" {
+        default_datadir()?.join("bitcoin.conf")
+    } else {
+        PathBuf::from(path)
+    };
+    let data = fs::read_to_string(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Could not read {}: {}", path.display(), e)))?;
+    let sections = parse(&data);
+    let top = sections.get("").cloned().unwrap_or_default();
+
+    let network = match top.get("chain").map(String::as_str) {
+        Some("main") | None if flag(&top, "regtest") => "regtest",
+        Some("main") | None if flag(&top, "signet") => "signet",
+        Some("main") | None if flag(&top, "testnet") => "test",
+        Some(chain) => chain,
+        None => "main",
+    }.to_string();
+
+    // Like bitcoind, options in a network section win over top-level ones, except
+    // that top-level rpcport/rpcbind only ever apply to mainnet.
+    let section = sections.get(network.as_str());
+    let get = |key: &str| -> Option<String> {
+        let network_only = matches!(key, "rpcport" | "rpcbind") && network != "main";
+        section.and_then(|s| s.get(key))
+            .or_else(|| if network_only { None } else { top.get(key) })
+            .cloned()
+    };
+
+    let default_port = match network.as_str() {
+        "test" => 18332,
+        "testnet4" => 48332,
+        "signet" => 38332,
+        "regtest" => 18443,
+        _ => 8332,
+    };
+    let (bind_host, bind_port) = match get("rpcbind") {
+        Some(bind) => split_host_port(&bind),
+        None => ("127.0.0.1".to_string(), None),
+    };
+    // A wildcard bind accepts connections on every interface, including loopback.
+    let host = match bind_host.as_str() {
+        "0.0.0.0" | "::" | "[::]" | "" => "127.0.0.1".to_string(),
+        _ => bind_host,
+    };
+    let port = get("rpcport").and_then(|p| p.parse().ok()).or(bind_port).unwrap_or(default_port);
+
+    let (user, pass) = match (get("rpcuser"), get("rpcpassword")) {
+        (Some(user), Some(pass)) => (user, pass),
+        _ => {
+            let datadir = get("datadir").map(PathBuf::from).map_or_else(default_datadir, Ok)?;
+            read_cookie(&datadir, &network)?
+        },
+    };
+
+    // IPv6 literals need brackets in a URL.
+    let host = if host.contains(':') { format!("[{}]", host) } else { host };
+    Ok(NodeRpcConfig { url: format!("http://{}:{}", host, port), user, pass, network })
+}
+
+/// Parses `key=value` lines into sections; top-level options live under "".
+fn parse(data: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = String::new();
+    for line in data.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.trim().to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            // The first occurrence of an option wins, as in bitcoind.
+            sections.entry(current.clone()).or_default()
+                .entry(key.trim().to_string()).or_insert_with(|| value.trim().to_string());
+        }
+    }
+    sections
+}
+
+fn flag(options: &HashMap<String, String>, key: &str) -> bool {
+    options.get(key).is_some_and(|v| v != "0")
+}
+
+/// Splits "host:port", "[v6]:port", or a bare host.
+fn split_host_port(addr: &str) -> (String, Option<u16>) {
+    if let Some(rest) = addr.strip_prefix('[')
+        && let Some((host, port)) = rest.split_once(']')
+    {
+        return (host.to_string(), port.strip_prefix(':').and_then(|p| p.parse().ok()));
+    }
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => (host.to_string(), port.parse().ok()),
+        _ => (addr.to_string(), None),
+    }
+}
+
+/// Reads the RPC cookie bitcoind writes when no rpcuser/rpcpassword is configured.
+fn read_cookie(datadir: &Path, network: &str) -> io::Result<(String, String)> {
+    let subdir = match network {
+        "test" => "testnet3",
+        "main" => "",
+        other => other,
+    };
+    let path = datadir.join(subdir).join(".cookie");
+    let cookie = fs::read_to_string(&path).map_err(|e| io::Error::new(e.kind(), format!(
+        "No rpcuser/rpcpassword in bitcoin.conf and no cookie at {} ({}). Is the node running?", path.display(), e
+    )))?;
+    let (user, pass) = cookie.trim().split_once(':')
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed cookie file {}", path.display())))?;
+    Ok((user.to_string(), pass.to_string()))
+}
+
+/// Bitcoin Core's default data directory on this platform.
+fn default_datadir() -> io::Result<PathBuf> {
+    let missing = |var: &str| io::Error::new(io::ErrorKind::NotFound, format!("{} is not set; give the bitcoin.conf path explicitly", var));
+    if cfg!(windows) {
+        let appdata = env::var_os("APPDATA").ok_or_else(|| missing("APPDATA"))?;
+        Ok(PathBuf::from(appdata).join("Bitcoin"))
+    } else {
+        let home = PathBuf::from(env::var_os("HOME").ok_or_else(|| missing("HOME"))?);
+        if cfg!(target_os = "macos") {
+            Ok(home.join("Library/Application Support/Bitcoin"))
+        } else {
+            Ok(home.join(".bitcoin"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads `conf` through a temporary bitcoin.conf.
+    fn read_conf(name: &str, conf: &str) -> NodeRpcConfig {
+        let path = env::temp_dir().join(format!("solo-miner-test-{}-{}.conf", name, std::process::id()));
+        fs::write(&path, conf).unwrap();
+        let config = read(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        config.unwrap()
+    }
+
+    #[test]
+    fn parse_splits_sections_and_keeps_the_first_occurrence() {
+        let sections = parse("rpcuser=a # comment\nrpcuser=b\n\n[test]\n  rpcport = 1 \n[ main ]\nrpcport=2\n# rpcport=3\n");
+        assert_eq!(sections[""]["rpcuser"], "a");
+        assert_eq!(sections["test"]["rpcport"], "1");
+        assert_eq!(sections["main"]["rpcport"], "2");
+        assert_eq!(sections.len(), 3);
+    }
+
+    #[test]
+    fn network_sections_override_top_level_options() {
+        let conf = "regtest=1\nrpcuser=top\nrpcpassword=top\n[regtest]\nrpcuser=section\n[main]\nrpcuser=main\n";
+        let config = read_conf("override", conf);
+        assert_eq!(config.network, "regtest");
+        assert_eq!((config.user.as_str(), config.pass.as_str()), ("section", "top"));
+        assert_eq!(config.url, "http://127.0.0.1:18443");
+    }
+
+    #[test]
+    fn top_level_rpcport_and_rpcbind_only_apply_to_mainnet() {
+        let conf = "rpcport=9000\nrpcbind=10.0.0.1\nrpcuser=u\nrpcpassword=p\n";
+        assert_eq!(read_conf("main", conf).url, "http://10.0.0.1:9000");
+        let config = read_conf("signet", &format!("chain=signet\n{}", conf));
+        assert_eq!(config.url, "http://127.0.0.1:38332");
+        let config = read_conf("signet-section", &format!("chain=signet\n{}[signet]\nrpcport=9001\n", conf));
+        assert_eq!(config.url, "http://127.0.0.1:9001");
+    }
+
+    #[test]
+    fn rpcbind_ports_and_wildcards() {
+        let conf = "testnet=1\nrpcuser=u\nrpcpassword=p\n[test]\nrpcbind=[::1]:9002\n";
+        assert_eq!(read_conf("v6", conf).url, "http://[::1]:9002");
+        let conf = "rpcuser=u\nrpcpassword=p\nrpcbind=0.0.0.0\n";
+        assert_eq!(read_conf("wildcard", conf).url, "http://127.0.0.1:8332");
+    }
+}
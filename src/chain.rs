@@ -0,0 +1,208 @@
+use crate::audit::{self, TemplateSnapshot};
+use crate::rest;
+use crate::settings::MinerSettings;
+use crate::zmq;
+use serde::Deserialize;
+use std::{fs, io};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How often a submitted block is checked for still being in the best chain.
+const ORPHAN_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of confirmations after which a found block is considered safe from orphaning.
+const SAFE_CONFIRMATIONS: i64 = 6;
+
+/// The subset of getblockheader we need to follow the chain.
+#[derive(Deserialize)]
+struct BlockHeaderInfo {
+    /// -1 when the block is not in the node's best chain.
+    confirmations: i64,
+    height: u64,
+    time: u32,
+    previousblockhash: Option<String>,
+}
+
+/// Background thread that follows the node's chain tip while a job is mined.
+/// When the tip moves, it raises the `abandon` flag so the mining loop drops its
+/// stale job and starts on a fresh template; a prefetched job is left to the
+/// prefetcher. Given the job's template, it also audits the block the network finds
+/// in its place. Dropping the watcher stops the thread.
+pub struct TipWatcher {
+    stop: Arc<AtomicBool>,
+}
+
+impl TipWatcher {
+    /// Starts watching from the tip the current template builds on.
+    pub fn spawn(settings: &MinerSettings, abandon: Arc<AtomicBool>, mut audit: Option<TemplateSnapshot>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let settings = settings.clone();
+
+        thread::spawn(move || {
+            let client = match settings.http_client() {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("[Chain] Could not start watching the chain tip: {}", e);
+                    return;
+                },
+            };
+            let mut tip = settings.prev_block_hash.clone();
+            // A prefetched job is left to the prefetcher, which listens to ZMQ itself
+            if let Some(endpoint) = settings.zmq_endpoint.as_ref().filter(|_| settings.prepared.is_none()) {
+                let (abandon, tip) = (Arc::clone(&abandon), tip.clone());
+                let result = zmq::watch_blocks(endpoint, Arc::clone(&thread_stop), move |hash| {
+                    if hash == tip {
+                        return true;
+                    }
+                    println!("[ZMQ] New block {}. Abandoning current job for a fresh template.", hash);
+                    abandon.store(true, Ordering::Relaxed);
+                    false
+                });
+                if let Err(e) = result {
+                    eprintln!("[ZMQ] Could not start listening for new blocks: {}", e);
+                }
+            }
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_secs(settings.tip_poll_secs.max(1)));
+                if thread_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                match check_tip(&settings, &client, &tip) {
+                    Ok(None) => {},
+                    Ok(Some(TipChange::Extended { new_tip, height })) => {
+                        println!("[Chain] New block {} at height {} extends the chain.", new_tip, height);
+                        tip = new_tip;
+                        if let Some(ours) = audit.take()
+                            && let Err(e) = audit::audit_height(&settings, &client, &ours)
+                        {
+                            eprintln!("[Audit] Could not audit the block at height {}: {}", ours.height, e);
+                        }
+                        // The prefetcher replaces a prefetched job as soon as the next one is
+                        // ready, unless the server doesn't allow work on an older tip
+                        if settings.prepared.is_none() || !settings.mutations.prevblock {
+                            println!("[Chain] The current job builds on the previous tip. Abandoning it for a fresh template.");
+                            abandon.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    },
+                    Ok(Some(TipChange::Reorg { new_tip, depth })) => {
+                        println!("[Chain] Reorganization detected! {} block(s) disconnected; new tip {}. Abandoning current job.", depth, new_tip);
+                        abandon.store(true, Ordering::Relaxed);
+                        break;
+                    },
+                    Err(e) => eprintln!("[Chain] Could not check the chain tip: {}", e),
+                }
+            }
+        });
+
+        TipWatcher { stop }
+    }
+}
+
+impl Drop for TipWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// How the node's best chain moved relative to the tip we last saw.
+pub enum TipChange {
+    /// The new tip builds directly on our previous tip.
+    Extended { new_tip: String, height: u64 },
+    /// Our previous tip is no longer in the best chain.
+    Reorg { new_tip: String, depth: u64 },
+}
+
+/// Compares the node's best block with `tip` and classifies any change.
+pub fn check_tip(settings: &MinerSettings, client: &reqwest::blocking::Client, tip: &str) -> io::Result<Option<TipChange>> {
+    let best: String = parse(rest::chain_query(settings, client, "getbestblockhash", serde_json::json!([]))?)?;
+    if best == tip {
+        return Ok(None);
+    }
+
+    let header = block_header(settings, client, &best)?;
+    if header.previousblockhash.as_deref() == Some(tip) {
+        return Ok(Some(TipChange::Extended { new_tip: best, height: header.height }));
+    }
+
+    // A new tip that doesn't build on ours may still be several blocks ahead on
+    // the same chain (we polled slowly); only a stale old tip means a reorg.
+    let depth = reorg_depth(settings, client, tip)?;
+    if depth == 0 {
+        return Ok(Some(TipChange::Extended { new_tip: best, height: header.height }));
+    }
+    Ok(Some(TipChange::Reorg { new_tip: best, depth }))
+}
+
+/// Walks back from `old_tip` until reaching a block in the best chain,
+/// returning how many blocks of our branch were disconnected.
+fn reorg_depth(settings: &MinerSettings, client: &reqwest::blocking::Client, old_tip: &str) -> io::Result<u64> {
+    let mut depth = 0;
+    let mut hash = old_tip.to_string();
+    loop {
+        let header = block_header(settings, client, &hash)?;
+        if header.confirmations >= 0 {
+            return Ok(depth);
+        }
+        depth += 1;
+        hash = header.previousblockhash
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Reached genesis while measuring reorg depth"))?;
+    }
+}
+
+/// Follows a block we submitted until it has `SAFE_CONFIRMATIONS` confirmations,
+/// reporting progress. If it drops out of the best chain it's been orphaned: the
+/// competing block at the same height is looked up and both are saved to
+/// `orphaned_block_<hash>.json` for later analysis.
+pub fn monitor_submitted_block(settings: &MinerSettings, block_hash: &str) -> io::Result<()> {
+    println!("\n[Chain] Watching block {} until it has {} confirmations...", block_hash, SAFE_CONFIRMATIONS);
+    let client = settings.http_client()?;
+    let mut last_confirmations = 0;
+
+    loop {
+        let ours = block_header(settings, &client, block_hash)?;
+
+        if ours.confirmations < 0 {
+            let competitor_hash: String = parse(rest::chain_query(settings, &client, "getblockhash", serde_json::json!([ours.height]))?)?;
+            let competitor = block_header(settings, &client, &competitor_hash)?;
+            println!("\n[Chain] ❌ Our block {} at height {} was ORPHANED.", block_hash, ours.height);
+            println!("[Chain] Competing block {} (timestamp {}, ours {}) won the race.", competitor_hash, competitor.time, ours.time);
+
+            let report = serde_json::json!({
+                "job_id": settings.job_id,
+                "height": ours.height,
+                "our_block": { "hash": block_hash, "time": ours.time },
+                "competing_block": { "hash": competitor_hash, "time": competitor.time },
+                "seen_confirmations_before_orphan": last_confirmations,
+            });
+            let path = format!("orphaned_block_{}.json", block_hash);
+            fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+            println!("[Chain] Orphan details saved to {}", path);
+            return Ok(());
+        }
+
+        if ours.confirmations != last_confirmations {
+            println!("[Chain] Block {} has {} confirmation(s).", block_hash, ours.confirmations);
+            last_confirmations = ours.confirmations;
+        }
+        if ours.confirmations >= SAFE_CONFIRMATIONS {
+            println!("[Chain] ✅ Block {} is buried under {} blocks; it is safe from orphaning.", block_hash, SAFE_CONFIRMATIONS);
+            return Ok(());
+        }
+
+        thread::sleep(ORPHAN_POLL_INTERVAL);
+    }
+}
+
+fn block_header(settings: &MinerSettings, client: &reqwest::blocking::Client, hash: &str) -> io::Result<BlockHeaderInfo> {
+    parse(rest::chain_query(settings, client, "getblockheader", serde_json::json!([hash, true]))?)
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(value: serde_json::Value) -> io::Result<T> {
+    serde_json::from_value(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected RPC result: {}", e)))
+}
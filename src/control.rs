@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A named set of runtime settings (an entry of the `profiles` setting) that a script
+/// switches to with `switch_profile(name)`, e.g. a quiet "day" and a full-speed "night".
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// CPU threads to hash on (0 for one per core), from the next job on the main chain.
+    #[serde(default)]
+    pub cpu_threads: Option<usize>,
+    /// Duty cycle in percent, applied at once as with `throttle(percent)`.
+    #[serde(default)]
+    pub throttle: Option<u8>,
+}
+
+/// Runtime controls the mining loop honors between batches of nonces:
+/// pausing, throttling to a percentage duty cycle, stopping, and the active profile.
+/// Cloning the handle shares the same controls.
+#[derive(Debug, Clone)]
+pub struct MinerControl {
+    paused: Arc<AtomicBool>,
+    throttle_percent: Arc<AtomicU8>,
+    stopped: Arc<AtomicBool>,
+    profile: Arc<Mutex<Option<(String, Profile)>>>,
+}
+
+impl MinerControl {
+    /// Controls for an unpaused miner running at full speed.
+    pub fn new() -> Self {
+        MinerControl {
+            paused: Arc::new(AtomicBool::new(false)),
+            throttle_percent: Arc::new(AtomicU8::new(100)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            profile: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Stops hashing at the next batch boundary until `resume` is called.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Lets a paused miner continue.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// True while the miner is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Ends the current job at the next check, even while paused; the mining loop
+    /// then returns `MiningOutcome::Stopped`.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// True once `stop` has been called.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Limits hashing to `percent` of the time (clamped to 1..=100).
+    pub fn set_throttle(&self, percent: u8) {
+        self.throttle_percent.store(percent.clamp(1, 100), Ordering::Relaxed);
+    }
+
+    /// The current duty cycle in percent (100 = full speed).
+    pub fn throttle(&self) -> u8 {
+        self.throttle_percent.load(Ordering::Relaxed)
+    }
+
+    /// Switches to `profile`, called `name`: its throttle applies at once, its thread
+    /// count from the next job.
+    pub fn switch_profile(&self, name: &str, profile: &Profile) {
+        if let Some(percent) = profile.throttle {
+            self.set_throttle(percent);
+        }
+        *self.profile.lock().unwrap_or_else(|e| e.into_inner()) = Some((name.to_string(), profile.clone()));
+    }
+
+    /// Name of the profile last switched to, if any.
+    pub fn profile_name(&self) -> Option<String> {
+        self.profile.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|(name, _)| name.clone())
+    }
+
+    /// The CPU thread count the active profile sets, if any.
+    pub fn profile_cpu_threads(&self) -> Option<usize> {
+        self.profile.lock().unwrap_or_else(|e| e.into_inner()).as_ref().and_then(|(_, profile)| profile.cpu_threads)
+    }
+
+    /// Called by the mining loop after a batch that took `batch_time`:
+    /// blocks while paused, then sleeps long enough to honor the throttle.
+    /// Returns how long it waited while paused.
+    pub fn after_batch(&self, batch_time: Duration) -> Duration {
+        let pause_start = Instant::now();
+        while self.is_paused() && !self.is_stopped() {
+            thread::sleep(Duration::from_millis(200));
+        }
+        let paused = pause_start.elapsed();
+        let percent = self.throttle() as u32;
+        if percent < 100 {
+            thread::sleep(batch_time * (100 - percent) / percent);
+        }
+        paused
+    }
+}
+
+impl Default for MinerControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,318 @@
+//! NVIDIA GPUs as scheduler devices through CUDA (the `gpu-cuda` feature).
+//!
+//! Like the OpenCL backend, the driver is loaded at run time, so the miner builds
+//! without the CUDA toolkit. The kernel is `kernels/sha256d.cl` behind a prelude
+//! of CUDA equivalents for its OpenCL built-ins, compiled for each GPU's
+//! architecture with NVRTC (installed with the driver or the toolkit). Batching,
+//! tuning and the CPU check of candidates are shared with OpenCL in `gpu.rs`.
+
+use crate::gpu::{self, GpuDevice, Kernel, Launch, MAX_RESULTS};
+use libloading::Library;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::sync::Arc;
+use std::{io, ptr};
+
+/// Maps the OpenCL built-ins the kernel uses to CUDA.
+const KERNEL_PRELUDE: &str = "\
+typedef unsigned int uint;
+#define __kernel extern \"C\" __global__
+#define __global
+#define __constant __constant__
+#define FUNC __device__
+#define get_global_id(dim) (blockIdx.x * blockDim.x + threadIdx.x)
+#define rotate(x, n) __funnelshift_l((x), (x), (n))
+#define bitselect(a, b, c) (((a) & ~(c)) | ((b) & (c)))
+#define atomic_inc(p) atomicAdd((p), 1u)
+";
+
+#[cfg(target_os = "windows")]
+const DRIVER_NAMES: &[&str] = &["nvcuda.dll"];
+#[cfg(not(target_os = "windows"))]
+const DRIVER_NAMES: &[&str] = &["libcuda.so.1", "libcuda.so"];
+
+#[cfg(target_os = "windows")]
+const NVRTC_NAMES: &[&str] = &["nvrtc64_120_0.dll", "nvrtc64_112_0.dll"];
+#[cfg(not(target_os = "windows"))]
+const NVRTC_NAMES: &[&str] = &["libnvrtc.so", "libnvrtc.so.13", "libnvrtc.so.12", "libnvrtc.so.11.2"];
+
+type Handle = *mut c_void;
+type DevicePtr = u64;
+
+const CUDA_SUCCESS: i32 = 0;
+const NVRTC_SUCCESS: i32 = 0;
+const CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_BLOCK: i32 = 1;
+const CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR: i32 = 75;
+const CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR: i32 = 76;
+
+/// The driver and NVRTC entry points the miner uses, resolved from the loaded libraries.
+struct Api {
+    driver_get_version: unsafe extern "system" fn(*mut i32) -> i32,
+    device_get_count: unsafe extern "system" fn(*mut i32) -> i32,
+    device_get: unsafe extern "system" fn(*mut i32, i32) -> i32,
+    device_get_name: unsafe extern "system" fn(*mut c_char, i32, i32) -> i32,
+    device_get_attribute: unsafe extern "system" fn(*mut i32, i32, i32) -> i32,
+    ctx_create: unsafe extern "system" fn(*mut Handle, u32, i32) -> i32,
+    ctx_destroy: unsafe extern "system" fn(Handle) -> i32,
+    ctx_set_current: unsafe extern "system" fn(Handle) -> i32,
+    module_load_data: unsafe extern "system" fn(*mut Handle, *const c_void) -> i32,
+    module_unload: unsafe extern "system" fn(Handle) -> i32,
+    module_get_function: unsafe extern "system" fn(*mut Handle, Handle, *const c_char) -> i32,
+    mem_alloc: unsafe extern "system" fn(*mut DevicePtr, usize) -> i32,
+    mem_free: unsafe extern "system" fn(DevicePtr) -> i32,
+    memcpy_htod: unsafe extern "system" fn(DevicePtr, *const c_void, usize) -> i32,
+    memcpy_dtoh: unsafe extern "system" fn(*mut c_void, DevicePtr, usize) -> i32,
+    launch_kernel: unsafe extern "system" fn(Handle, u32, u32, u32, u32, u32, u32, u32, Handle, *mut *mut c_void, *mut *mut c_void) -> i32,
+    nvrtc_create_program: unsafe extern "system" fn(*mut Handle, *const c_char, *const c_char, i32, *const *const c_char, *const *const c_char) -> i32,
+    nvrtc_compile_program: unsafe extern "system" fn(Handle, i32, *const *const c_char) -> i32,
+    nvrtc_get_program_log_size: unsafe extern "system" fn(Handle, *mut usize) -> i32,
+    nvrtc_get_program_log: unsafe extern "system" fn(Handle, *mut c_char) -> i32,
+    nvrtc_get_ptx_size: unsafe extern "system" fn(Handle, *mut usize) -> i32,
+    nvrtc_get_ptx: unsafe extern "system" fn(Handle, *mut c_char) -> i32,
+    nvrtc_destroy_program: unsafe extern "system" fn(*mut Handle) -> i32,
+    /// Keep the entry points above valid.
+    _driver: Library,
+    _nvrtc: Library,
+}
+
+fn open_any(names: &[&str], what: &str) -> io::Result<Library> {
+    // SAFETY: loading NVIDIA's own libraries, which have no unusual initialization
+    names.iter().find_map(|name| unsafe { Library::new(name) }.ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No {} found (tried {})", what, names.join(", "))))
+}
+
+impl Api {
+    fn load() -> io::Result<Api> {
+        let driver = open_any(DRIVER_NAMES, "CUDA driver")?;
+        let nvrtc = open_any(NVRTC_NAMES, "NVRTC library")?;
+        macro_rules! entry {
+            ($library:ident, $name:literal) => {
+                // SAFETY: the signature matches the CUDA 11+ declaration of the function
+                *unsafe { $library.get($name) }
+                    .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("CUDA lacks {}: {}", String::from_utf8_lossy($name), e)))?
+            };
+        }
+        let init: unsafe extern "system" fn(u32) -> i32 = entry!(driver, b"cuInit");
+        // SAFETY: cuInit takes no pointers and may be called more than once
+        check(unsafe { init(0) }, "initializing the driver")?;
+        Ok(Api {
+            driver_get_version: entry!(driver, b"cuDriverGetVersion"),
+            device_get_count: entry!(driver, b"cuDeviceGetCount"),
+            device_get: entry!(driver, b"cuDeviceGet"),
+            device_get_name: entry!(driver, b"cuDeviceGetName"),
+            device_get_attribute: entry!(driver, b"cuDeviceGetAttribute"),
+            ctx_create: entry!(driver, b"cuCtxCreate_v2"),
+            ctx_destroy: entry!(driver, b"cuCtxDestroy_v2"),
+            ctx_set_current: entry!(driver, b"cuCtxSetCurrent"),
+            module_load_data: entry!(driver, b"cuModuleLoadData"),
+            module_unload: entry!(driver, b"cuModuleUnload"),
+            module_get_function: entry!(driver, b"cuModuleGetFunction"),
+            mem_alloc: entry!(driver, b"cuMemAlloc_v2"),
+            mem_free: entry!(driver, b"cuMemFree_v2"),
+            memcpy_htod: entry!(driver, b"cuMemcpyHtoD_v2"),
+            memcpy_dtoh: entry!(driver, b"cuMemcpyDtoH_v2"),
+            launch_kernel: entry!(driver, b"cuLaunchKernel"),
+            nvrtc_create_program: entry!(nvrtc, b"nvrtcCreateProgram"),
+            nvrtc_compile_program: entry!(nvrtc, b"nvrtcCompileProgram"),
+            nvrtc_get_program_log_size: entry!(nvrtc, b"nvrtcGetProgramLogSize"),
+            nvrtc_get_program_log: entry!(nvrtc, b"nvrtcGetProgramLog"),
+            nvrtc_get_ptx_size: entry!(nvrtc, b"nvrtcGetPTXSize"),
+            nvrtc_get_ptx: entry!(nvrtc, b"nvrtcGetPTX"),
+            nvrtc_destroy_program: entry!(nvrtc, b"nvrtcDestroyProgram"),
+            _driver: driver,
+            _nvrtc: nvrtc,
+        })
+    }
+
+    fn attribute(&self, device: i32, attribute: i32) -> io::Result<i32> {
+        let mut value = 0;
+        // SAFETY: writes one int
+        check(unsafe { (self.device_get_attribute)(&mut value, attribute, device) }, "querying a device attribute")?;
+        Ok(value)
+    }
+
+    fn device_name(&self, device: i32) -> io::Result<String> {
+        let mut name = [0u8; 256];
+        // SAFETY: the driver writes a NUL-terminated name of at most the given length
+        check(unsafe { (self.device_get_name)(name.as_mut_ptr().cast(), name.len() as i32, device) }, "querying the device name")?;
+        Ok(CStr::from_bytes_until_nul(&name).map_or_else(|_| String::new(), |s| s.to_string_lossy().into_owned()))
+    }
+
+    /// Compiles the kernel to PTX for compute capability `major.minor`.
+    fn compile(&self, major: i32, minor: i32) -> io::Result<Vec<u8>> {
+        let source = CString::new(format!("{}{}", KERNEL_PRELUDE, gpu::KERNEL_SOURCE)).map_err(io::Error::other)?;
+        let arch = CString::new(format!("--gpu-architecture=compute_{}{}", major, minor)).map_err(io::Error::other)?;
+        let mut program = ptr::null_mut();
+        // SAFETY: NUL-terminated strings in, buffers of the reported sizes out; the program
+        // is destroyed on every path after it's created
+        unsafe {
+            check_nvrtc((self.nvrtc_create_program)(&mut program, source.as_ptr(), c"sha256d.cu".as_ptr(), 0, ptr::null(), ptr::null()), "loading the kernel")?;
+            let compiled = match (self.nvrtc_compile_program)(program, 1, &arch.as_ptr()) {
+                NVRTC_SUCCESS => {
+                    let mut size = 0;
+                    let mut ptx = Vec::new();
+                    check_nvrtc((self.nvrtc_get_ptx_size)(program, &mut size), "reading the PTX").and_then(|()| {
+                        ptx.resize(size, 0u8);
+                        check_nvrtc((self.nvrtc_get_ptx)(program, ptx.as_mut_ptr().cast()), "reading the PTX")
+                    }).map(|()| ptx)
+                },
+                _ => {
+                    let mut size = 0;
+                    (self.nvrtc_get_program_log_size)(program, &mut size);
+                    let mut log = vec![0u8; size];
+                    (self.nvrtc_get_program_log)(program, log.as_mut_ptr().cast());
+                    Err(io::Error::other(format!("The kernel did not compile for compute_{}{}: {}", major, minor, String::from_utf8_lossy(&log))))
+                },
+            };
+            (self.nvrtc_destroy_program)(&mut program);
+            compiled
+        }
+    }
+}
+
+fn check(status: i32, doing: &str) -> io::Result<()> {
+    if status == CUDA_SUCCESS {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("CUDA error {} while {}", status, doing)))
+    }
+}
+
+fn check_nvrtc(status: i32, doing: &str) -> io::Result<()> {
+    if status == NVRTC_SUCCESS {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("NVRTC error {} while {}", status, doing)))
+    }
+}
+
+/// Sets up the GPUs at `indices` (all of them when empty), tuning those not in the
+/// cache yet.
+pub fn devices(indices: &[usize]) -> io::Result<Vec<GpuDevice<CudaKernel>>> {
+    let api = Arc::new(Api::load()?);
+    let mut count = 0;
+    let mut driver_version = 0;
+    // SAFETY: each writes one int
+    check(unsafe { (api.device_get_count)(&mut count) }, "counting devices")?;
+    check(unsafe { (api.driver_get_version)(&mut driver_version) }, "querying the driver version")?;
+    if count == 0 {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "CUDA found no GPU"));
+    }
+    let mut gpus = Vec::new();
+    for ordinal in 0..count {
+        let mut device = 0;
+        // SAFETY: writes one device handle
+        check(unsafe { (api.device_get)(&mut device, ordinal) }, "listing devices")?;
+        println!("[CUDA] GPU {}: {}", ordinal, api.device_name(device)?);
+        gpus.push(device);
+    }
+    let chosen: Vec<usize> = if indices.is_empty() { (0..gpus.len()).collect() } else { indices.to_vec() };
+
+    let mut devices = Vec::new();
+    for index in chosen {
+        let device = *gpus.get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No CUDA GPU {} (found {})", index, gpus.len())))?;
+        let model = api.device_name(device)?;
+        let key = format!("CUDA / {} / driver {}", model, driver_version);
+        let max_threads = api.attribute(device, CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_BLOCK)?;
+        let kernel = CudaKernel::new(Arc::clone(&api), device)?;
+        devices.push(GpuDevice::new(format!("cuda{}", index), &model, &key, max_threads as usize, kernel)?);
+    }
+    Ok(devices)
+}
+
+/// One GPU with its context, the compiled kernel and its buffers.
+pub struct CudaKernel {
+    api: Arc<Api>,
+    context: Handle,
+    module: Handle,
+    function: Handle,
+    midstate: DevicePtr,
+    results: DevicePtr,
+}
+
+// SAFETY: a CUDA context may be made current on any thread, which every call below
+// does first; each kernel is only driven by one thread at a time.
+unsafe impl Send for CudaKernel {}
+
+impl CudaKernel {
+    fn new(api: Arc<Api>, device: i32) -> io::Result<CudaKernel> {
+        let major = api.attribute(device, CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR)?;
+        let minor = api.attribute(device, CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR)?;
+        let mut ptx = api.compile(major, minor)?;
+        if ptx.last() != Some(&0) {
+            ptx.push(0);
+        }
+        // Everything is released by Drop once assigned, so `?` below leaks nothing
+        let mut gpu = CudaKernel {
+            api: Arc::clone(&api),
+            context: ptr::null_mut(),
+            module: ptr::null_mut(),
+            function: ptr::null_mut(),
+            midstate: 0,
+            results: 0,
+        };
+        // SAFETY: each call gets handles created above and checks its status; creating
+        // the context makes it current on this thread
+        unsafe {
+            check((api.ctx_create)(&mut gpu.context, 0, device), "creating a context")?;
+            check((api.module_load_data)(&mut gpu.module, ptx.as_ptr().cast()), "loading the kernel")?;
+            check((api.module_get_function)(&mut gpu.function, gpu.module, c"sha256d_scan".as_ptr()), "finding the kernel")?;
+            check((api.mem_alloc)(&mut gpu.midstate, size_of::<[u32; 8]>()), "allocating the midstate buffer")?;
+            check((api.mem_alloc)(&mut gpu.results, size_of::<[u32; MAX_RESULTS + 1]>()), "allocating the result buffer")?;
+        }
+        Ok(gpu)
+    }
+}
+
+impl Kernel for CudaKernel {
+    fn launch(&mut self, launch: &Launch) -> io::Result<Vec<u32>> {
+        let api = &self.api;
+        let mut results = [0u32; MAX_RESULTS + 1];
+        let blocks = (launch.count as usize).div_ceil(launch.work_group_size) as u32;
+        let [tail0, tail1, tail2] = launch.tail;
+        let mut params: [*mut c_void; 8] = [
+            (&self.midstate as *const DevicePtr).cast_mut().cast(),
+            (&tail0 as *const u32).cast_mut().cast(),
+            (&tail1 as *const u32).cast_mut().cast(),
+            (&tail2 as *const u32).cast_mut().cast(),
+            (&launch.first_nonce as *const u32).cast_mut().cast(),
+            (&launch.count as *const u32).cast_mut().cast(),
+            (&launch.target_top as *const u32).cast_mut().cast(),
+            (&self.results as *const DevicePtr).cast_mut().cast(),
+        ];
+        // SAFETY: the parameters match the kernel's declaration and outlive the launch;
+        // copies on the default stream wait for the kernel, so the read sees its results
+        unsafe {
+            check((api.ctx_set_current)(self.context), "selecting the context")?;
+            check((api.memcpy_htod)(self.midstate, launch.midstate.as_ptr().cast(), size_of_val(&launch.midstate)), "writing the midstate")?;
+            check((api.memcpy_htod)(self.results, results.as_ptr().cast(), size_of::<u32>()), "clearing the results")?;
+            check((api.launch_kernel)(
+                self.function, blocks, 1, 1, launch.work_group_size as u32, 1, 1, 0, ptr::null_mut(), params.as_mut_ptr(), ptr::null_mut(),
+            ), "launching the kernel")?;
+            check((api.memcpy_dtoh)(results.as_mut_ptr().cast(), self.results, size_of_val(&results)), "reading the results")?;
+        }
+        Ok(gpu::candidates(&results))
+    }
+}
+
+impl Drop for CudaKernel {
+    fn drop(&mut self) {
+        if self.context.is_null() {
+            return;
+        }
+        let api = &self.api;
+        // SAFETY: each handle was created in this kernel's context and is released once
+        unsafe {
+            (api.ctx_set_current)(self.context);
+            for buffer in [self.midstate, self.results] {
+                if buffer != 0 {
+                    (api.mem_free)(buffer);
+                }
+            }
+            if !self.module.is_null() {
+                (api.module_unload)(self.module);
+            }
+            (api.ctx_destroy)(self.context);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+pub use crate::pow::difficulty_from_nbits;
+use crate::rest;
+use crate::settings::MinerSettings;
+use crate::units;
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Blocks between difficulty adjustments.
+pub const RETARGET_INTERVAL: u64 = 2016;
+
+/// Target spacing between blocks, in seconds.
+const TARGET_BLOCK_SPACING: f64 = 600.0;
+
+/// nBits of difficulty 1, the proof-of-work limit of the testnets.
+pub const MIN_DIFFICULTY_BITS: u32 = 0x1d00ffff;
+
+/// On testnet3 and testnet4, a block timestamped more than this long after its parent
+/// (twice the target spacing) may be mined at difficulty 1.
+const MIN_DIFFICULTY_DELAY_SECS: u32 = 20 * 60;
+
+/// A single retarget can change difficulty by at most this factor either way.
+const MAX_RETARGET_FACTOR: f64 = 4.0;
+
+/// Hashes needed on average to find a block at difficulty 1.
+const HASHES_PER_DIFFICULTY: f64 = 4_294_967_296.0; // 2^32
+
+/// How many epochs (including the current one) the history chart shows.
+const HISTORY_EPOCHS: u64 = 12;
+
+/// Width in characters of the longest bar in the chart.
+const CHART_WIDTH: usize = 40;
+
+/// The subset of getblockchaininfo we need.
+#[derive(Deserialize)]
+struct ChainInfo {
+    blocks: u64,
+}
+
+/// The subset of getblockheader we need for difficulty history.
+#[derive(Deserialize)]
+struct EpochHeader {
+    difficulty: f64,
+    time: u64,
+}
+
+/// Unix time the header of the template's block must be past to be mined at difficulty 1,
+/// on chains with the 20-minute rule. None elsewhere, and at retarget heights, where the
+/// adjustment is computed as usual.
+pub fn min_difficulty_after(settings: &MinerSettings, client: &reqwest::blocking::Client) -> io::Result<Option<u32>> {
+    if !matches!(settings.network(), Network::Testnet | Network::Testnet4) || settings.height.is_multiple_of(RETARGET_INTERVAL) {
+        return Ok(None);
+    }
+    let parent: EpochHeader = parse(rest::chain_query(settings, client, "getblockheader", serde_json::json!([settings.prev_block_hash, true]))?)?;
+    Ok(Some(parent.time as u32 + MIN_DIFFICULTY_DELAY_SECS))
+}
+
+/// Difficulty of one retarget epoch.
+pub struct Epoch {
+    /// Height of the first block of the epoch.
+    pub start_height: u64,
+    pub difficulty: f64,
+}
+
+/// Projection of the next difficulty adjustment from the current epoch's pace.
+#[derive(Debug, Clone, Serialize)]
+pub struct RetargetEstimate {
+    /// Height at which the next adjustment takes effect.
+    pub retarget_height: u64,
+    /// Blocks left in the current epoch.
+    pub blocks_remaining: u64,
+    /// Average spacing of the epoch's blocks so far, in seconds.
+    pub average_spacing_secs: f64,
+    /// Difficulty of the current epoch.
+    pub current_difficulty: f64,
+    /// Difficulty the next epoch would get if the current pace holds.
+    pub estimated_difficulty: f64,
+    /// Estimated Unix time of the adjustment.
+    pub estimated_at: u64,
+}
+
+impl RetargetEstimate {
+    /// Estimated change in difficulty, in percent.
+    pub fn change_percent(&self) -> f64 {
+        (self.estimated_difficulty / self.current_difficulty - 1.0) * 100.0
+    }
+}
+
+/// Estimates the next difficulty adjustment from the timestamps of the blocks
+/// mined so far this epoch. The node only knows the real value at the boundary,
+/// so this extrapolates the current pace, clamped like the consensus rule.
+pub fn estimate_retarget(settings: &MinerSettings) -> io::Result<RetargetEstimate> {
+    let client = settings.http_client()?;
+    let info = chain_info(settings, &client)?;
+    let epoch_start = info.blocks - info.blocks % RETARGET_INTERVAL;
+    if info.blocks == epoch_start {
+        return Err(io::Error::other("A new epoch has just started; there are no blocks to estimate its pace from yet"));
+    }
+
+    let first = header_at(settings, &client, epoch_start)?;
+    let tip = header_at(settings, &client, info.blocks)?;
+    let blocks_mined = info.blocks - epoch_start;
+    let average_spacing_secs = (tip.time.saturating_sub(first.time) as f64 / blocks_mined as f64).max(1.0);
+    let blocks_remaining = epoch_start + RETARGET_INTERVAL - info.blocks;
+
+    let factor = (TARGET_BLOCK_SPACING / average_spacing_secs).clamp(1.0 / MAX_RETARGET_FACTOR, MAX_RETARGET_FACTOR);
+    Ok(RetargetEstimate {
+        retarget_height: epoch_start + RETARGET_INTERVAL,
+        blocks_remaining,
+        average_spacing_secs,
+        current_difficulty: first.difficulty,
+        estimated_difficulty: first.difficulty * factor,
+        estimated_at: tip.time + (blocks_remaining as f64 * average_spacing_secs) as u64,
+    })
+}
+
+/// Prints the retarget estimate.
+pub fn print_retarget(estimate: &RetargetEstimate) {
+    println!("\n--- Next Difficulty Adjustment ---");
+    println!("Height {} ({} blocks away, avg spacing this epoch {:.1} min)", estimate.retarget_height, estimate.blocks_remaining, estimate.average_spacing_secs / 60.0);
+    println!(
+        "Estimated change: {:+.2}% ({} -> {}) around {}",
+        estimate.change_percent(), format_difficulty(estimate.current_difficulty),
+        format_difficulty(estimate.estimated_difficulty), units::format_utc(estimate.estimated_at)
+    );
+    println!("----------------------------------");
+}
+
+/// Average time to find a block at `difficulty` with `hashrate` H/s, in seconds.
+pub fn expected_time_to_block(difficulty: f64, hashrate: f64) -> Option<f64> {
+    (hashrate > 0.0).then(|| difficulty * HASHES_PER_DIFFICULTY / hashrate)
+}
+
+/// Height of the node's best block.
+pub fn tip_height(settings: &MinerSettings) -> io::Result<u64> {
+    let client = settings.http_client()?;
+    Ok(chain_info(settings, &client)?.blocks)
+}
+
+/// Fetches the difficulty of the last `HISTORY_EPOCHS` epochs, oldest first.
+pub fn fetch_history(settings: &MinerSettings) -> io::Result<Vec<Epoch>> {
+    let client = settings.http_client()?;
+    let info = chain_info(settings, &client)?;
+    let current_start = info.blocks - info.blocks % RETARGET_INTERVAL;
+
+    let mut epochs = Vec::with_capacity(HISTORY_EPOCHS as usize);
+    for k in (0..HISTORY_EPOCHS).rev() {
+        let Some(start_height) = current_start.checked_sub(k * RETARGET_INTERVAL) else { continue };
+        let header = header_at(settings, &client, start_height)?;
+        epochs.push(Epoch { start_height, difficulty: header.difficulty });
+    }
+    Ok(epochs)
+}
+
+/// Prints a bar chart of recent epoch difficulties with the change from each
+/// epoch to the next, so a small miner can see which way the odds are moving.
+pub fn print_history(epochs: &[Epoch]) {
+    let Some(max) = epochs.iter().map(|e| e.difficulty).reduce(f64::max) else { return };
+
+    println!("\n--- Difficulty History (last {} epochs) ---", epochs.len());
+    let mut previous: Option<f64> = None;
+    for epoch in epochs {
+        let bar_len = if max > 0.0 { ((epoch.difficulty / max) * CHART_WIDTH as f64).round() as usize } else { 0 };
+        let change = previous
+            .filter(|p| *p > 0.0)
+            .map_or(String::new(), |p| format!("{:+.2}%", (epoch.difficulty / p - 1.0) * 100.0));
+        println!(
+            "{:>8} | {:<width$} {:>10} {:>8}",
+            epoch.start_height, "█".repeat(bar_len.max(1)), format_difficulty(epoch.difficulty), change,
+            width = CHART_WIDTH
+        );
+        previous = Some(epoch.difficulty);
+    }
+    if let (Some(first), Some(last)) = (epochs.first(), epochs.last())
+        && first.difficulty > 0.0 && epochs.len() > 1
+    {
+        println!("Change over the period: {:+.2}%", (last.difficulty / first.difficulty - 1.0) * 100.0);
+    }
+    println!("-------------------------------------------");
+}
+
+/// Formats a difficulty with an SI suffix (k, M, G, T, P, E).
+pub fn format_difficulty(difficulty: f64) -> String {
+    const SUFFIXES: [&str; 7] = ["", "k", "M", "G", "T", "P", "E"];
+
+    let mut value = difficulty;
+    let mut suffix = 0;
+    while value.abs() >= 1000.0 && suffix < SUFFIXES.len() - 1 {
+        value /= 1000.0;
+        suffix += 1;
+    }
+    format!("{:.2}{}", value, SUFFIXES[suffix])
+}
+
+fn chain_info(settings: &MinerSettings, client: &reqwest::blocking::Client) -> io::Result<ChainInfo> {
+    parse(rest::chain_query(settings, client, "getblockchaininfo", serde_json::json!([]))?)
+}
+
+fn header_at(settings: &MinerSettings, client: &reqwest::blocking::Client, height: u64) -> io::Result<EpochHeader> {
+    let hash: String = parse(rest::chain_query(settings, client, "getblockhash", serde_json::json!([height]))?)?;
+    parse(rest::chain_query(settings, client, "getblockheader", serde_json::json!([hash, true]))?)
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(value: serde_json::Value) -> io::Result<T> {
+    serde_json::from_value(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected RPC result: {}", e)))
+}
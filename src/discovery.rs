@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+/// Default RPC ports of each network, as bitcoind assigns them.
+const DEFAULT_RPC_PORTS: [(&str, u16); 5] = [
+    ("main", 8332),
+    ("test", 18332),
+    ("testnet4", 48332),
+    ("signet", 38332),
+    ("regtest", 18443),
+];
+
+/// How long to wait for each port to answer.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A node found listening on localhost.
+#[derive(Debug, Clone)]
+pub struct DiscoveredNode {
+    pub network: &'static str,
+    pub url: String,
+}
+
+/// Probes the default RPC port of every network on localhost and returns the
+/// ones where a Bitcoin Core JSON-RPC server answers. No credentials are sent:
+/// an unauthenticated request is answered with a 401 carrying the `jsonrpc`
+/// realm, which is enough to recognise the server.
+pub fn probe_local_nodes() -> Vec<DiscoveredNode> {
+    let Ok(client) = reqwest::blocking::Client::builder().timeout(PROBE_TIMEOUT).build() else {
+        return Vec::new();
+    };
+    DEFAULT_RPC_PORTS.iter()
+        .filter_map(|(network, port)| {
+            let url = format!("http://127.0.0.1:{}", port);
+            let response = client.post(&url).body("{}").send().ok()?;
+            let is_rpc = response.headers().get(reqwest::header::WWW_AUTHENTICATE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("jsonrpc"));
+            is_rpc.then_some(DiscoveredNode { network, url })
+        })
+        .collect()
+}
@@ -0,0 +1,174 @@
+//! The miner as a library object: configure it with `Miner::builder()`, then start,
+//! pause, resume and stop it while it mines on a thread of its own, and read its stats
+//! or follow its events meanwhile. For applications embedding the miner; the binary
+//! drives `miner::mine_block` directly.
+
+use crate::control::MinerControl;
+use crate::events::{EventBus, MinerEvent};
+use crate::hasher::HashBackend;
+use crate::miner::{self, MiningOutcome};
+use crate::scheduler;
+use crate::settings::MinerSettings;
+use crate::stats::{MinerStats, StatsHandle};
+use crate::worker::WorkerPool;
+use std::io;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long the mining thread waits before asking the node again after an error.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Configures a `Miner`. Only the settings are required.
+#[derive(Default)]
+pub struct MinerBuilder {
+    settings: Option<MinerSettings>,
+    backend: Option<HashBackend>,
+    events: EventBus,
+}
+
+impl MinerBuilder {
+    /// The settings to mine with, e.g. from `MinerSettings::load`.
+    pub fn settings(mut self, settings: MinerSettings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// The SHA-256 backend, overriding the settings' `hash_backend`.
+    pub fn backend(mut self, backend: HashBackend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Calls `callback` for every event, on the thread that publishes it. Can be given
+    /// several times.
+    pub fn on_event(self, callback: impl Fn(&MinerEvent) + Send + Sync + 'static) -> Self {
+        self.events.subscribe(callback);
+        self
+    }
+
+    /// Creates the miner, stopped. `HashBackend::Auto` is resolved here, by benchmark.
+    pub fn build(self) -> io::Result<Miner> {
+        let mut settings = self.settings
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Miner::builder() needs settings"))?;
+        if let Some(backend) = self.backend {
+            settings.hash_backend = backend;
+        }
+        if settings.hash_backend != HashBackend::Auto && !HashBackend::available().contains(&settings.hash_backend) {
+            return Err(settings.hash_backend.unavailable());
+        }
+        settings.hash_backend = settings.hash_backend.resolve();
+        let stats = StatsHandle::new(Duration::from_secs_f64(settings.hashrate_half_life_secs.max(0.001)));
+        Ok(Miner { settings, stats, events: self.events, control: MinerControl::new(), thread: None })
+    }
+}
+
+/// A miner with a start/pause/stop lifecycle. Once started, it mines block after block
+/// until stopped: found blocks are submitted and published as events, and node errors
+/// are published as `MinerEvent::Error` and retried. Dropping it stops it.
+pub struct Miner {
+    settings: MinerSettings,
+    stats: StatsHandle,
+    events: EventBus,
+    control: MinerControl,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Miner {
+    /// Starts configuring a miner.
+    pub fn builder() -> MinerBuilder {
+        MinerBuilder::default()
+    }
+
+    /// Starts mining on a new thread. Does nothing if already running.
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.is_running() {
+            return Ok(());
+        }
+        // A stopped miner starts again with fresh controls, keeping the throttle
+        if self.control.is_stopped() {
+            let throttle = self.control.throttle();
+            self.control = MinerControl::new();
+            self.control.set_throttle(throttle);
+        }
+        let (settings, stats, events, control) = (self.settings.clone(), self.stats.clone(), self.events.clone(), self.control.clone());
+        self.thread = Some(thread::Builder::new().name("miner".to_string()).spawn(move || run(settings, stats, events, control))?);
+        Ok(())
+    }
+
+    /// Suspends hashing until `resume`; the job is kept.
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    /// Lets a paused miner continue.
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    /// Stops mining and waits for the mining thread to finish its current step, which
+    /// may be a request to the node. Lifetime stats are saved.
+    pub fn stop(&mut self) -> io::Result<()> {
+        self.control.stop();
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            return Err(io::Error::other("The mining thread panicked"));
+        }
+        self.stats.save_lifetime()
+    }
+
+    /// True between `start` and `stop`.
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// A copy of the current stats.
+    pub fn stats(&self) -> MinerStats {
+        self.stats.snapshot()
+    }
+
+    /// The controls, for throttling.
+    pub fn control(&self) -> &MinerControl {
+        &self.control
+    }
+
+    /// The event bus, to subscribe after building.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+}
+
+impl Drop for Miner {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            let _ = self.stop();
+        }
+    }
+}
+
+/// The mining thread: fetch a template, mine it, repeat until stopped.
+fn run(mut settings: MinerSettings, stats: StatsHandle, events: EventBus, control: MinerControl) {
+    let workers = WorkerPool::start(&settings.workers, stats.clone());
+    let mut gpus = scheduler::gpu_devices(&settings);
+    while !control.is_stopped() {
+        if let Err(e) = settings.update_from_node() {
+            events.publish(MinerEvent::Error { message: e.to_string() });
+            wait(&control, RETRY_DELAY);
+            continue;
+        }
+        match miner::mine_block(&settings, &stats, &events, &control, &workers, &mut gpus) {
+            Ok(MiningOutcome::Stopped) => break,
+            Ok(_) => {},
+            // Already published by mine_block
+            Err(_) => wait(&control, RETRY_DELAY),
+        }
+    }
+}
+
+/// Sleeps for `delay`, waking early if the miner is stopped.
+fn wait(control: &MinerControl, delay: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < delay && !control.is_stopped() {
+        thread::sleep(Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, mpsc};
+
+/// Significant things that happen while mining. `job_id` names the job (see
+/// `MinerSettings::job_id`) the event belongs to.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum MinerEvent {
+    /// A new template was taken up as the current job.
+    TemplateReceived { job_id: String, prev_block_hash: String, nbits: u32, transactions: usize, fees_sats: u64 },
+    /// A hash met difficulty 1. `time` and `version` are the header fields it was found with.
+    ShareFound { job_id: String, hash: String, nonce: u32, time: u32, version: u32 },
+    /// A hash met the block target.
+    BlockFound { job_id: String, hash: String, nonce: u32, time: u32, version: u32 },
+    /// The node answered our block submission.
+    SubmitResult { job_id: String, hash: String, accepted: bool, detail: String },
+    /// The mining engine hit an error it could not recover from.
+    Error { message: String },
+}
+
+type Callback = Box<dyn Fn(&MinerEvent) + Send + Sync>;
+
+/// Fan-out of miner events to any number of subscribers.
+/// Cloning the bus shares the same subscriber list.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Callback>>>,
+}
+
+impl EventBus {
+    /// Creates a bus with no subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a callback invoked synchronously for every event.
+    /// Callbacks run on the mining thread, so they should return quickly.
+    pub fn subscribe(&self, callback: impl Fn(&MinerEvent) + Send + Sync + 'static) {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(callback));
+    }
+
+    /// Returns a channel receiving a copy of every event, for consumers on other threads.
+    pub fn subscribe_channel(&self) -> mpsc::Receiver<MinerEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        self.subscribe(move |event| {
+            // A dropped receiver just means that consumer went away.
+            let _ = sender.lock().unwrap_or_else(|e| e.into_inner()).send(event.clone());
+        });
+        receiver
+    }
+
+    /// Delivers an event to every subscriber.
+    pub fn publish(&self, event: MinerEvent) {
+        for callback in self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            callback(&event);
+        }
+    }
+}
+
+/// Takes over stdout for machine-readable output: returns a handle to the real
+/// stdout and points the process's stdout at stderr, so every human-readable
+/// `println!` lands on stderr from here on. Only available on Unix.
+#[cfg(unix)]
+pub fn detach_stdout() -> io::Result<File> {
+    use std::os::fd::FromRawFd;
+
+    io::stdout().flush()?;
+    // SAFETY: the duplicated descriptor is checked and then owned solely by the returned File.
+    unsafe {
+        let json_out = libc::dup(libc::STDOUT_FILENO);
+        if json_out < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from_raw_fd(json_out))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn detach_stdout() -> io::Result<File> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--events is only supported on Unix"))
+}
+
+/// Writes every event to `out` as one JSON object per line, flushed immediately
+/// so consumers such as `jq` or a supervisor see events as they happen.
+#[cfg(feature = "rpc")]
+pub fn stream_json(bus: &EventBus, out: File) {
+    let out = Mutex::new(out);
+    bus.subscribe(move |event| {
+        let Ok(line) = serde_json::to_string(event) else { return };
+        let mut out = out.lock().unwrap_or_else(|e| e.into_inner());
+        // A closed pipe just means the consumer went away.
+        let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+    });
+}
@@ -0,0 +1,169 @@
+//! Failover between the node and a Stratum pool: solo mine on the node's templates,
+//! mine on the pool's jobs while the node is unreachable, and go back to the node
+//! as soon as it answers again.
+
+use crate::events::{EventBus, MinerEvent};
+use crate::settings::MinerSettings;
+use crate::stratum::{StratumClient, StratumHandle};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before trying again when neither the node nor the pool answers.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+fn default_password() -> String {
+    "x".to_string()
+}
+
+fn default_node_check_secs() -> u64 {
+    30
+}
+
+/// The pool to fall back on (the `failover_pool` setting).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailoverPool {
+    /// "host:port", optionally prefixed by "stratum+tcp://".
+    pub url: String,
+    /// Worker name. Solo pools expect the payout address here, e.g. "bc1q....rig1".
+    pub user: String,
+    #[serde(default = "default_password")]
+    pub password: String,
+    /// How often, in seconds, to check whether the node is back while mining on the pool.
+    #[serde(default = "default_node_check_secs")]
+    pub node_check_secs: u64,
+}
+
+/// Decides where each job comes from, and holds the pool connection while it's used.
+pub struct Failover {
+    pool: FailoverPool,
+    client: Option<StratumClient>,
+    /// The connection the share subscriber submits to, if any.
+    handle: Arc<Mutex<Option<StratumHandle>>>,
+    /// Raised by the node checker once the node answers again.
+    node_back: Arc<AtomicBool>,
+    _checker: Option<NodeChecker>,
+}
+
+impl Failover {
+    /// Subscribes to shares on `events`, to pass them on to the pool while it's used.
+    pub fn new(pool: FailoverPool, events: &EventBus) -> Self {
+        let handle: Arc<Mutex<Option<StratumHandle>>> = Arc::default();
+        let subscriber_handle = Arc::clone(&handle);
+        // Synchronous, so a share (or block) is on the wire before the miner moves on
+        events.subscribe(move |event| {
+            if let MinerEvent::ShareFound { hash, nonce, time, version, .. } = event
+                && let Some(handle) = subscriber_handle.lock().unwrap_or_else(|e| e.into_inner()).as_ref()
+            {
+                handle.submit_share(hash, *nonce, *time, *version);
+            }
+        });
+        Failover {
+            pool,
+            client: None,
+            handle,
+            node_back: Arc::new(AtomicBool::new(false)),
+            _checker: None,
+        }
+    }
+
+    /// Puts the next job in `settings`: a template from the node when it answers,
+    /// otherwise the pool's latest job. Keeps retrying until one of them works.
+    pub fn refresh(&mut self, settings: &mut MinerSettings) {
+        loop {
+            // While on the pool, the node checker tells when the node is worth asking again
+            if self.client.is_none() || self.node_back.load(Ordering::Relaxed) {
+                match settings.update_from_node() {
+                    Ok(()) => {
+                        if self.client.is_some() {
+                            println!("[Failover] Back to solo mining on the node's templates.");
+                        }
+                        self.leave_pool(settings);
+                        return;
+                    },
+                    Err(e) => eprintln!("[Failover] Could not update from node: {}", e),
+                }
+            }
+            match self.pool_work(settings) {
+                Ok(()) => return,
+                Err(e) => {
+                    eprintln!("[Failover] Pool {} is unavailable too: {}. Retrying in {}s.", self.pool.url, e, RETRY_DELAY.as_secs());
+                    self.leave_pool(settings);
+                    thread::sleep(RETRY_DELAY);
+                },
+            }
+        }
+    }
+
+    /// Connects to the pool if needed and puts its latest job in `settings`.
+    fn pool_work(&mut self, settings: &mut MinerSettings) -> std::io::Result<()> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => {
+                println!("[Failover] Node unreachable; mining on pool {} until it's back.", self.pool.url);
+                let client = StratumClient::connect(&self.pool.url, &self.pool.user, &self.pool.password)?;
+                *self.handle.lock().unwrap_or_else(|e| e.into_inner()) = Some(client.handle());
+                self.node_back.store(false, Ordering::Relaxed);
+                self._checker = Some(NodeChecker::spawn(
+                    settings,
+                    Duration::from_secs(self.pool.node_check_secs.max(1)),
+                    Arc::clone(&self.node_back),
+                    client.handle(),
+                ));
+                self.client.insert(client)
+            },
+        };
+        let (header, work) = client.next_work()?;
+        settings.use_stratum_work(header, work);
+        Ok(())
+    }
+
+    /// Closes the pool connection, if any, and drops its work from `settings`.
+    fn leave_pool(&mut self, settings: &mut MinerSettings) {
+        if self.client.take().is_some() {
+            *self.handle.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            self._checker = None;
+            settings.stratum_work = None;
+        }
+    }
+}
+
+/// Background thread that asks the node for its block count while the miner is on the
+/// pool, and drops the pool's work once the node answers. Dropping it stops the thread.
+struct NodeChecker {
+    stop: Arc<AtomicBool>,
+}
+
+impl NodeChecker {
+    fn spawn(settings: &MinerSettings, interval: Duration, node_back: Arc<AtomicBool>, pool: StratumHandle) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let settings = settings.clone();
+
+        thread::spawn(move || {
+            let Ok(client) = settings.http_client() else { return };
+            while !thread_stop.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                if thread_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                if settings.rpc_call(&client, "getblockcount", serde_json::json!([])).is_ok() {
+                    println!("[Failover] Node answers again; switching back to it.");
+                    node_back.store(true, Ordering::Relaxed);
+                    pool.mark_stale();
+                    break;
+                }
+            }
+        });
+        NodeChecker { stop }
+    }
+}
+
+impl Drop for NodeChecker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,196 @@
+//! What the GPU backends (`gpu-opencl`, `gpu-cuda`) share: the scan of
+//! `kernels/sha256d.cl`, launched in batches and checked on the CPU, and the tuning of
+//! launch parameters per device.
+//!
+//! A backend only implements [`Kernel`]: given the header's midstate and a range of
+//! nonces, run the kernel and return its candidates. [`GpuDevice`] turns that into a
+//! scheduler [`Device`].
+//!
+//! The best work-group and batch sizes differ a lot between GPU models, so the first
+//! time a device is used they are measured and the winners are cached in
+//! `gpu_tuning.json`, keyed by device and driver. Delete the file to tune again.
+
+use crate::pow;
+use crate::scheduler::{CPU_SLICE_SIZE, Device};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+/// The kernel, in OpenCL C. The CUDA backend compiles it too, behind a prelude of macros.
+pub const KERNEL_SOURCE: &str = include_str!("kernels/sha256d.cl");
+
+/// Result slots in the kernel's output buffer, after the count (MAX_RESULTS in the kernel).
+pub const MAX_RESULTS: usize = 63;
+
+/// Where tuned parameters are kept between runs.
+const GPU_TUNING_FILE: &str = "gpu_tuning.json";
+
+/// Work-group sizes tried by the autotune pass, up to the device's maximum.
+const TUNE_WORK_GROUP_SIZES: [usize; 6] = [32, 64, 128, 256, 512, 1024];
+
+/// Nonces per kernel launch tried by the autotune pass. Every size is a multiple of
+/// the CPU slice, so slices of all devices stay aligned.
+const TUNE_BATCH_SIZES: [u32; 5] = [1 << 18, 1 << 20, 1 << 22, 1 << 24, 1 << 26];
+
+/// Launches longer than this are skipped when tuning: the miner only notices a new
+/// block or a pause between launches.
+const MAX_LAUNCH_TIME: Duration = Duration::from_millis(500);
+
+/// Time spent measuring each combination.
+const TUNE_MEASURE_TIME: Duration = Duration::from_millis(300);
+
+/// One run of `sha256d_scan` over `count` nonces from `first_nonce`.
+#[derive(Debug, Clone, Copy)]
+pub struct Launch {
+    /// SHA-256 state after the first 64 header bytes.
+    pub midstate: [u32; 8],
+    /// Header bytes 64..76 as big-endian words.
+    pub tail: [u32; 3],
+    pub first_nonce: u32,
+    pub count: u32,
+    /// Most significant word of the target, in display order.
+    pub target_top: u32,
+    /// Work items per work group (threads per block in CUDA).
+    pub work_group_size: usize,
+}
+
+/// A GPU with the kernel loaded, as seen by one backend.
+pub trait Kernel: Send {
+    /// Runs the kernel and returns the candidate nonces it reported.
+    fn launch(&mut self, launch: &Launch) -> io::Result<Vec<u32>>;
+}
+
+/// The candidates in a result buffer read back from the kernel.
+pub fn candidates(results: &[u32; MAX_RESULTS + 1]) -> Vec<u32> {
+    let found = (results[0] as usize).min(MAX_RESULTS);
+    results[1..=found].to_vec()
+}
+
+/// Best parameters measured for one device.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Tuning {
+    work_group_size: usize,
+    batch_size: u32,
+    /// Hash rate measured with these parameters, in H/s.
+    hashrate: f64,
+}
+
+fn load_tunings() -> BTreeMap<String, Tuning> {
+    fs::read_to_string(GPU_TUNING_FILE).ok().and_then(|data| serde_json::from_str(&data).ok()).unwrap_or_default()
+}
+
+fn save_tuning(key: &str, tuning: Tuning) -> io::Result<()> {
+    // Reread so devices tuned by other runs in the meantime are kept
+    let mut tunings = load_tunings();
+    tunings.insert(key.to_string(), tuning);
+    fs::write(GPU_TUNING_FILE, serde_json::to_string_pretty(&tunings)?)
+}
+
+/// A GPU as a scheduler device, launching its kernel with tuned parameters.
+pub struct GpuDevice<K> {
+    name: String,
+    tuning: Tuning,
+    kernel: K,
+}
+
+impl<K: Kernel> GpuDevice<K> {
+    /// Wraps `kernel`, reusing the tuning cached under `key` (the device and its driver)
+    /// or measuring a new one.
+    pub fn new(name: String, model: &str, key: &str, max_work_group_size: usize, mut kernel: K) -> io::Result<Self> {
+        let tuning = match load_tunings().get(key) {
+            Some(tuning) if tuning.work_group_size <= max_work_group_size => *tuning,
+            _ => {
+                println!("[GPU] Tuning {} (first run on this device and driver)...", model);
+                let tuning = autotune(&mut kernel, model, max_work_group_size)?;
+                if let Err(e) = save_tuning(key, tuning) {
+                    eprintln!("[GPU] Could not save {}: {}", GPU_TUNING_FILE, e);
+                }
+                tuning
+            },
+        };
+        println!(
+            "[GPU] {} ({}): work groups of {}, {} nonces per launch, {:.1} MH/s when tuned.",
+            name, model, tuning.work_group_size, tuning.batch_size, tuning.hashrate / 1e6
+        );
+        Ok(GpuDevice { name, tuning, kernel })
+    }
+}
+
+/// Measures every work-group and batch size combination and returns the fastest.
+fn autotune<K: Kernel>(kernel: &mut K, model: &str, max_work_group_size: usize) -> io::Result<Tuning> {
+    let mut launch = Launch {
+        midstate: pow::midstate(&[0; 80]),
+        tail: [0; 3],
+        first_nonce: 0,
+        count: 0,
+        target_top: 0,
+        work_group_size: 0,
+    };
+    let mut best: Option<Tuning> = None;
+    for work_group_size in TUNE_WORK_GROUP_SIZES.into_iter().filter(|&size| size <= max_work_group_size) {
+        for batch_size in TUNE_BATCH_SIZES {
+            launch.work_group_size = work_group_size;
+            launch.count = batch_size;
+            // The first launch compiles and warms up; it isn't measured
+            kernel.launch(&launch)?;
+            let start = Instant::now();
+            let mut hashes: u64 = 0;
+            let mut launch_time = Duration::ZERO;
+            while start.elapsed() < TUNE_MEASURE_TIME {
+                let launch_start = Instant::now();
+                kernel.launch(&launch)?;
+                launch_time = launch_time.max(launch_start.elapsed());
+                hashes += u64::from(batch_size);
+            }
+            let hashrate = hashes as f64 / start.elapsed().as_secs_f64();
+            if best.is_none_or(|best| hashrate > best.hashrate) {
+                best = Some(Tuning { work_group_size, batch_size, hashrate });
+            }
+            // Larger batches would only take longer
+            if launch_time > MAX_LAUNCH_TIME {
+                break;
+            }
+        }
+    }
+    best.ok_or_else(|| io::Error::other(format!("No work-group size fits {} (maximum {})", model, max_work_group_size)))
+}
+
+impl<K: Kernel> Device for GpuDevice<K> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn slice_size(&self) -> u32 {
+        self.tuning.batch_size.max(CPU_SLICE_SIZE)
+    }
+
+    fn scan(&mut self, header: &[u8; 80], nonces: RangeInclusive<u32>, target: &[u8; 32], found: &mut dyn FnMut(u32, [u8; 32])) -> io::Result<()> {
+        let mut launch = Launch {
+            midstate: pow::midstate(header),
+            tail: std::array::from_fn(|i| u32::from_be_bytes(header[64 + 4 * i..68 + 4 * i].try_into().unwrap())),
+            first_nonce: *nonces.start(),
+            count: 0,
+            target_top: u32::from_be_bytes(target[..4].try_into().unwrap()),
+            work_group_size: self.tuning.work_group_size,
+        };
+        // A slice of the whole nonce space is one more than u32::MAX nonces; launch it in halves
+        let total = u64::from(*nonces.end() - *nonces.start()) + 1;
+        let mut launched: u64 = 0;
+        let mut header = *header;
+        while launched < total {
+            launch.count = (total - launched).min(1 << 31) as u32;
+            for nonce in self.kernel.launch(&launch)? {
+                header[76..].copy_from_slice(&nonce.to_le_bytes());
+                let hash = pow::sha256d(&header);
+                if pow::meets_target(&hash, target) {
+                    found(nonce, hash);
+                }
+            }
+            launched += u64::from(launch.count);
+            launch.first_nonce = launch.first_nonce.wrapping_add(launch.count);
+        }
+        Ok(())
+    }
+}
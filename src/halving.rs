@@ -0,0 +1,89 @@
+use crate::units;
+use bitcoin::Network;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Blocks between subsidy halvings.
+pub const HALVING_INTERVAL: u64 = 210_000;
+
+/// Blocks between subsidy halvings on regtest.
+const REGTEST_HALVING_INTERVAL: u64 = 150;
+
+/// Subsidy of the first epoch: 50 BTC.
+const INITIAL_SUBSIDY_SATS: u64 = 50 * 100_000_000;
+
+/// A known mainnet block (the fourth halving) used to estimate the height offline.
+const ANCHOR_HEIGHT: u64 = 840_000;
+const ANCHOR_TIME: u64 = 1_713_571_767;
+
+/// Target spacing between blocks, in seconds.
+const TARGET_BLOCK_SPACING: u64 = 600;
+
+/// Block subsidy in satoshis for a block at `height`, excluding fees.
+pub fn subsidy_for_height(height: u64) -> u64 {
+    subsidy(height, HALVING_INTERVAL)
+}
+
+/// Like `subsidy_for_height`, on `network`: regtest halves every 150 blocks.
+pub fn subsidy_for_height_on(height: u64, network: Network) -> u64 {
+    match network {
+        Network::Regtest => subsidy(height, REGTEST_HALVING_INTERVAL),
+        _ => subsidy(height, HALVING_INTERVAL),
+    }
+}
+
+fn subsidy(height: u64, interval: u64) -> u64 {
+    let halvings = height / interval;
+    // Shifting a u64 by 64 or more is undefined; the subsidy is zero by then anyway.
+    if halvings >= 64 { 0 } else { INITIAL_SUBSIDY_SATS >> halvings }
+}
+
+/// Rough mainnet tip height from the clock, for when no node is reachable.
+pub fn estimated_tip_height() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    ANCHOR_HEIGHT + now.saturating_sub(ANCHOR_TIME) / TARGET_BLOCK_SPACING
+}
+
+/// Prints how far away the next halving is and what it does to the subsidy.
+pub fn print_countdown(tip_height: u64) {
+    let next_height = (tip_height / HALVING_INTERVAL + 1) * HALVING_INTERVAL;
+    let blocks_remaining = next_height - tip_height;
+    let secs = blocks_remaining * TARGET_BLOCK_SPACING;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    println!(
+        "Halving countdown: {} blocks to height {} (~{}, around {}). Subsidy drops from {} to {} BTC.",
+        blocks_remaining, next_height, units::format_duration(secs as f64), units::format_utc(now + secs),
+        units::format_btc(subsidy_for_height(tip_height + 1)), units::format_btc(subsidy_for_height(next_height))
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsidy_halves_at_each_boundary() {
+        assert_eq!(subsidy_for_height(0), 5_000_000_000);
+        assert_eq!(subsidy_for_height(209_999), 5_000_000_000);
+        assert_eq!(subsidy_for_height(210_000), 2_500_000_000);
+        assert_eq!(subsidy_for_height(839_999), 625_000_000);
+        assert_eq!(subsidy_for_height(840_000), 312_500_000);
+        assert_eq!(subsidy_for_height(1_050_000), 156_250_000);
+    }
+
+    #[test]
+    fn subsidy_runs_out() {
+        // 33 halvings take 50 BTC below one satoshi; the shift stops at 64.
+        assert_eq!(subsidy_for_height(32 * HALVING_INTERVAL), 1);
+        assert_eq!(subsidy_for_height(33 * HALVING_INTERVAL), 0);
+        assert_eq!(subsidy_for_height(64 * HALVING_INTERVAL), 0);
+        assert_eq!(subsidy_for_height(u64::MAX), 0);
+    }
+
+    #[test]
+    fn regtest_halves_every_150_blocks() {
+        assert_eq!(subsidy_for_height_on(149, Network::Regtest), 5_000_000_000);
+        assert_eq!(subsidy_for_height_on(150, Network::Regtest), 2_500_000_000);
+        assert_eq!(subsidy_for_height_on(150, Network::Testnet), 5_000_000_000);
+        assert_eq!(subsidy_for_height_on(210_000, Network::Signet), 2_500_000_000);
+    }
+}
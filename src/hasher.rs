@@ -0,0 +1,248 @@
+use crate::pow;
+use crate::simd::{self, VectorUnit};
+use crate::units::format_hashrate;
+use core::ops::RangeInclusive;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How long `HashBackend::resolve` measures each backend.
+const BENCHMARK_TIME: Duration = Duration::from_millis(500);
+
+/// Nonces hashed between two looks at the clock while benchmarking.
+const BENCHMARK_BATCH: u32 = 1 << 12;
+
+/// A double SHA-256 implementation the hashing loop can run on. The loop is
+/// generic over it, so the choice costs nothing per nonce.
+pub trait Hasher {
+    /// The backend this implementation belongs to.
+    const BACKEND: HashBackend;
+
+    /// SHA-256 applied twice, in the byte order the digest produces.
+    fn sha256d(data: &[u8]) -> [u8; 32];
+
+    /// The nonce scan of `pow::scan` with this implementation. By default every nonce
+    /// hashes the whole header; a backend that can resume SHA-256 from a saved state
+    /// hashes the first 64 bytes, which no nonce changes, once per call instead.
+    fn scan(header: &mut [u8; 80], nonces: RangeInclusive<u32>, target: &[u8; 32]) -> Option<(u32, [u8; 32])> {
+        pow::scan(header, nonces, target, Self::sha256d)
+    }
+}
+
+/// The SHA-256 implementations the miner can be built with. Their speed differs a lot
+/// between a Pi, an x86 desktop and an ARM server, so by default the fastest one is
+/// measured at startup; `cargo bench` compares them in more detail.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashBackend {
+    /// Benchmark the backends compiled in at startup and use the fastest (see `resolve`).
+    #[default]
+    Auto,
+    /// RustCrypto's sha2: the CPU's SHA-256 instructions where it has them, otherwise pure
+    /// Rust, or hand-written assembly with the `sha2-asm` feature.
+    Sha2,
+    /// ring's SHA-256 (requires the `ring` feature).
+    Ring,
+    /// OpenSSL's SHA-256 (requires the `openssl` feature).
+    Openssl,
+    /// Several nonces per pass with AVX2 or NEON, on CPUs that have them; elsewhere
+    /// the same as `sha2`.
+    Simd,
+}
+
+impl HashBackend {
+    /// The backends compiled into this build.
+    pub fn available() -> Vec<HashBackend> {
+        let mut backends = vec![HashBackend::Sha2, HashBackend::Simd];
+        if cfg!(feature = "ring") {
+            backends.push(HashBackend::Ring);
+        }
+        if cfg!(feature = "openssl") {
+            backends.push(HashBackend::Openssl);
+        }
+        backends
+    }
+
+    /// Name for logs and error messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashBackend::Auto => "auto",
+            HashBackend::Sha2 => match sha_extensions() {
+                Some(ShaExtensions::ShaNi) => "sha2 (SHA-NI)",
+                Some(ShaExtensions::Armv8) => "sha2 (ARMv8 SHA)",
+                None if cfg!(feature = "sha2-asm") => "sha2 (asm)",
+                None => "sha2",
+            },
+            HashBackend::Ring => "ring",
+            HashBackend::Openssl => "openssl",
+            HashBackend::Simd => match VectorUnit::detect() {
+                Some(VectorUnit::Avx2) => "simd (AVX2)",
+                Some(VectorUnit::Neon) => "simd (NEON)",
+                None => "simd",
+            },
+        }
+    }
+
+    /// The error for a backend selected in the config file but left out of the build.
+    pub fn unavailable(self) -> io::Error {
+        let feature = match self {
+            HashBackend::Auto | HashBackend::Sha2 | HashBackend::Simd => "",
+            HashBackend::Ring => "ring",
+            HashBackend::Openssl => "openssl",
+        };
+        io::Error::new(io::ErrorKind::Unsupported, format!(
+            "hash_backend '{}' is not compiled in; rebuild with `--features {}` or pick one of {:?}",
+            self.name(), feature, HashBackend::available()
+        ))
+    }
+
+    /// The backend to mine with: `self` when set in the config, otherwise the fastest
+    /// backend compiled in, after benchmarking each for half a second.
+    pub fn resolve(self) -> HashBackend {
+        if self == HashBackend::Simd && VectorUnit::detect().is_none() {
+            println!("[Hashing] This CPU has neither AVX2 nor NEON: the simd backend hashes one nonce at a time.");
+        }
+        if self != HashBackend::Auto {
+            return self;
+        }
+        // Without a vector unit, simd would only measure sha2 twice
+        let available: Vec<HashBackend> = HashBackend::available().into_iter()
+            .filter(|backend| *backend != HashBackend::Simd || VectorUnit::detect().is_some())
+            .collect();
+        if let [only] = available[..] {
+            return only;
+        }
+        let rates: Vec<(HashBackend, f64)> = available.into_iter().map(|backend| (backend, backend.benchmark())).collect();
+        let (fastest, _) = rates.iter().copied().fold((HashBackend::Sha2, 0.0), |best, rate| if rate.1 > best.1 { rate } else { best });
+        let report: Vec<String> = rates.iter().map(|(backend, rate)| format!("{} {}", backend.name(), format_hashrate(*rate))).collect();
+        println!("[Hashing] Benchmarked {}; using {} (set hash_backend to override).", report.join(", "), fastest.name());
+        fastest
+    }
+
+    /// Single-thread hash rate of this backend over `BENCHMARK_TIME`.
+    fn benchmark(self) -> f64 {
+        match self {
+            #[cfg(feature = "ring")]
+            HashBackend::Ring => hashrate::<Ring>(),
+            #[cfg(feature = "openssl")]
+            HashBackend::Openssl => hashrate::<Openssl>(),
+            HashBackend::Simd => hashrate::<Simd>(),
+            _ => hashrate::<Sha2>(),
+        }
+    }
+}
+
+/// SHA-256 instructions of the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaExtensions {
+    /// Intel SHA extensions, on x86.
+    ShaNi,
+    /// The ARMv8 cryptography extension's SHA-256 instructions, on AArch64.
+    Armv8,
+}
+
+/// The SHA-256 instructions sha2 hashes with on this CPU, if any. It checks for them at
+/// run time (the same checks as here) and otherwise falls back to software.
+pub fn sha_extensions() -> Option<ShaExtensions> {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("sha")
+        && std::arch::is_x86_feature_detected!("sse2")
+        && std::arch::is_x86_feature_detected!("ssse3")
+        && std::arch::is_x86_feature_detected!("sse4.1")
+    {
+        return Some(ShaExtensions::ShaNi);
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("sha2") {
+        return Some(ShaExtensions::Armv8);
+    }
+    None
+}
+
+fn hashrate<H: Hasher>() -> f64 {
+    let mut header = [0u8; 80];
+    let start = Instant::now();
+    let mut hashes: u64 = 0;
+    while start.elapsed() < BENCHMARK_TIME {
+        // No hash meets an all-zero target, so every nonce is hashed
+        H::scan(&mut header, 0..=BENCHMARK_BATCH - 1, &[0; 32]);
+        hashes += u64::from(BENCHMARK_BATCH);
+    }
+    hashes as f64 / start.elapsed().as_secs_f64()
+}
+
+/// RustCrypto's sha2, on the CPU's SHA-256 instructions when it has them (see
+/// `sha_extensions`). Its compression function is exposed, so scans start each nonce
+/// from the header's midstate and compress only the last 16 bytes and the second hash.
+pub struct Sha2;
+
+impl Hasher for Sha2 {
+    const BACKEND: HashBackend = HashBackend::Sha2;
+
+    fn sha256d(data: &[u8]) -> [u8; 32] {
+        pow::sha256d(data)
+    }
+
+    fn scan(header: &mut [u8; 80], nonces: RangeInclusive<u32>, target: &[u8; 32]) -> Option<(u32, [u8; 32])> {
+        let midstate = pow::midstate(header);
+        let tail: [u8; 12] = header[64..76].try_into().unwrap();
+        let found = pow::scan_midstate(&midstate, &tail, nonces, target);
+        if let Some((nonce, _)) = found {
+            header[76..].copy_from_slice(&nonce.to_le_bytes());
+        }
+        found
+    }
+}
+
+/// RustCrypto's sha2 for single hashes, and batches of nonces across vector lanes for
+/// scans (see `simd`).
+pub struct Simd;
+
+impl Hasher for Simd {
+    const BACKEND: HashBackend = HashBackend::Simd;
+
+    fn sha256d(data: &[u8]) -> [u8; 32] {
+        pow::sha256d(data)
+    }
+
+    fn scan(header: &mut [u8; 80], nonces: RangeInclusive<u32>, target: &[u8; 32]) -> Option<(u32, [u8; 32])> {
+        let midstate = pow::midstate(header);
+        let tail: [u8; 12] = header[64..76].try_into().unwrap();
+        let found = simd::scan_midstate(&midstate, &tail, nonces, target);
+        if let Some((nonce, _)) = found {
+            header[76..].copy_from_slice(&nonce.to_le_bytes());
+        }
+        found
+    }
+}
+
+/// ring's SHA-256.
+#[cfg(feature = "ring")]
+pub struct Ring;
+
+#[cfg(feature = "ring")]
+impl Hasher for Ring {
+    const BACKEND: HashBackend = HashBackend::Ring;
+
+    fn sha256d(data: &[u8]) -> [u8; 32] {
+        use ring::digest::{SHA256, digest};
+
+        let first = digest(&SHA256, data);
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(digest(&SHA256, first.as_ref()).as_ref());
+        hash
+    }
+}
+
+/// OpenSSL's SHA-256.
+#[cfg(feature = "openssl")]
+pub struct Openssl;
+
+#[cfg(feature = "openssl")]
+impl Hasher for Openssl {
+    const BACKEND: HashBackend = HashBackend::Openssl;
+
+    fn sha256d(data: &[u8]) -> [u8; 32] {
+        openssl::sha::sha256(&openssl::sha::sha256(data))
+    }
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "notify")]
+use crate::events::{EventBus, MinerEvent};
+#[cfg(feature = "notify")]
+use std::process::{Command, Stdio};
+#[cfg(feature = "notify")]
+use std::thread;
+#[cfg(feature = "notify")]
+use std::time::{Duration, Instant};
+
+/// Shell commands to run when events happen. Placeholders like `{hash}` are
+/// replaced by the event's fields (shell-quoted), e.g. `"./celebrate.sh {hash}"`.
+/// The whole event is also passed as JSON in the `MINER_EVENT` environment variable.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventHooks {
+    /// Fields: {prev_block_hash}, {nbits}, {transactions}, {fees_sats}
+    #[serde(default)]
+    pub on_template_received: Option<String>,
+    /// Fields: {hash}, {nonce}, {time}, {version}
+    #[serde(default)]
+    pub on_share_found: Option<String>,
+    /// Fields: {hash}, {nonce}, {time}, {version}
+    #[serde(default)]
+    pub on_block_found: Option<String>,
+    /// Fields: {hash}, {accepted}, {detail}
+    #[serde(default)]
+    pub on_submit_result: Option<String>,
+    /// Fields: {message}
+    #[serde(default)]
+    pub on_error: Option<String>,
+}
+
+impl EventHooks {
+    /// Whether any hook command is set.
+    pub fn is_configured(&self) -> bool {
+        [&self.on_template_received, &self.on_share_found, &self.on_block_found, &self.on_submit_result, &self.on_error]
+            .iter()
+            .any(|hook| hook.is_some())
+    }
+
+    #[cfg(feature = "notify")]
+    fn command_for(&self, event: &MinerEvent) -> Option<&String> {
+        match event {
+            MinerEvent::TemplateReceived { .. } => self.on_template_received.as_ref(),
+            MinerEvent::ShareFound { .. } => self.on_share_found.as_ref(),
+            MinerEvent::BlockFound { .. } => self.on_block_found.as_ref(),
+            MinerEvent::SubmitResult { .. } => self.on_submit_result.as_ref(),
+            MinerEvent::Error { .. } => self.on_error.as_ref(),
+        }
+    }
+}
+
+/// Subscribes the configured hooks to the event bus. Each command runs on its own
+/// thread so a slow script never stalls mining, and is killed after `timeout`.
+#[cfg(feature = "notify")]
+pub fn install(bus: &EventBus, hooks: EventHooks, timeout: Duration) {
+    bus.subscribe(move |event| {
+        let Some(template) = hooks.command_for(event) else { return };
+        let command = expand(template, event);
+        let event_json = serde_json::to_string(event).unwrap_or_default();
+        thread::spawn(move || run_with_timeout(&command, &event_json, timeout));
+    });
+}
+
+/// Replaces `{field}` placeholders with the event's shell-quoted field values.
+#[cfg(feature = "notify")]
+fn expand(template: &str, event: &MinerEvent) -> String {
+    let mut command = template.to_string();
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(event) {
+        for (name, value) in fields {
+            let text = match value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            command = command.replace(&format!("{{{}}}", name), &shell_quote(&text));
+        }
+    }
+    command
+}
+
+#[cfg(all(feature = "notify", unix))]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(all(feature = "notify", not(unix)))]
+fn shell_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(feature = "notify")]
+fn run_with_timeout(command: &str, event_json: &str, timeout: Duration) {
+    #[cfg(unix)]
+    let mut cmd = {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(command);
+        c
+    };
+    #[cfg(not(unix))]
+    let mut cmd = {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(command);
+        c
+    };
+
+    let mut child = match cmd.env("MINER_EVENT", event_json).stdin(Stdio::null()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("[Hooks] Could not run '{}': {}", command, e);
+            return;
+        },
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return,
+            Ok(Some(status)) => {
+                eprintln!("[Hooks] '{}' exited with {}", command, status);
+                return;
+            },
+            Ok(None) if Instant::now() >= deadline => {
+                eprintln!("[Hooks] '{}' timed out after {:?}; killing it.", command, timeout);
+                let _ = child.kill();
+                let _ = child.wait();
+                return;
+            },
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => {
+                eprintln!("[Hooks] Could not wait for '{}': {}", command, e);
+                return;
+            },
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! One miner per directory: the config, stats, attestation and found-block files all
+//! live in the working directory, so a second instance started there would clobber
+//! them. A lock file holding the owner's PID keeps it out.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// The lock file, in the working directory next to `miner_config.json`.
+pub const LOCK_FILE: &str = "miner.lock";
+
+/// Held while the miner runs; dropping it removes the lock file.
+pub struct InstanceLock {
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Takes the lock, or fails naming the instance that holds it. A lock left behind
+    /// by a process that is gone (killed, or crashed) is taken over.
+    pub fn acquire() -> io::Result<InstanceLock> {
+        let path = PathBuf::from(LOCK_FILE);
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(InstanceLock { path });
+                },
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path).ok().and_then(|pid| pid.trim().parse::<u32>().ok());
+                    match owner {
+                        Some(pid) if is_running(pid) => {
+                            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!(
+                                "Another miner is already running in this directory (PID {}). Stop it first, or start this one from another directory. If it isn't running, delete {}.",
+                                pid, LOCK_FILE
+                            )));
+                        },
+                        _ => {
+                            println!("[Lock] Taking over {} from a miner that is no longer running.", LOCK_FILE);
+                            fs::remove_file(&path)?;
+                        },
+                    }
+                },
+                Err(e) => return Err(io::Error::new(e.kind(), format!("Could not create {}: {}", LOCK_FILE, e))),
+            }
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether a process with this PID exists. Only checked on Unix; elsewhere every lock
+/// is assumed live.
+fn is_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else { return false };
+        // SAFETY: signal 0 only checks that the process exists and may be signalled.
+        let alive = unsafe { libc::kill(pid, 0) } == 0;
+        alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
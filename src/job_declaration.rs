@@ -0,0 +1,531 @@
+//! Stratum V2 Job Declaration: the miner keeps building blocks from its own node's
+//! templates, declares each one to the pool's Job Declaration Server (JDS), and mines
+//! it on an extended channel of the pool, which provides the extranonce space and
+//! pays out through its own coinbase outputs.
+//!
+//! Message layouts follow the Job Declaration and Mining protocols as deployed by
+//! the SV2 reference implementation (SRI 1.x), including BIP152-style short
+//! transaction ids in DeclareMiningJob.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sv2")]
+use crate::events::{EventBus, MinerEvent};
+#[cfg(feature = "sv2")]
+use crate::merkle;
+#[cfg(feature = "sv2")]
+use crate::miner::{self, PoolCoinbase};
+#[cfg(feature = "sv2")]
+use crate::settings::MinerSettings;
+#[cfg(feature = "sv2")]
+use crate::stats::StatsHandle;
+#[cfg(feature = "sv2")]
+use crate::sv2::{self, Decoder, Encoder, Frame, Sv2Reader, Sv2Writer};
+#[cfg(feature = "sv2")]
+use bitcoin::consensus::{Decodable, Encodable};
+#[cfg(feature = "sv2")]
+use bitcoin::hashes::{Hash, sha256, siphash24};
+#[cfg(feature = "sv2")]
+use std::io;
+#[cfg(feature = "sv2")]
+use std::sync::{Arc, Mutex, mpsc};
+#[cfg(feature = "sv2")]
+use std::thread;
+#[cfg(feature = "sv2")]
+use std::time::{Duration, SystemTime};
+
+/// Where to declare jobs and mine them (the `job_declaration` setting).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobDeclarationSettings {
+    /// The pool's SV2 mining endpoint ("host:port").
+    pub pool_url: String,
+    /// The pool's Job Declaration Server ("host:port").
+    pub jds_url: String,
+    /// Authority public key the pool publishes to authenticate its servers.
+    pub authority_pubkey: String,
+    /// Authority key of the JDS, when it differs from the pool's.
+    #[serde(default)]
+    pub jds_authority_pubkey: Option<String>,
+    /// Account name at the pool, usually with a worker suffix ("account.worker").
+    pub user_identity: String,
+}
+
+// Mining protocol message types.
+#[cfg(feature = "sv2")]
+const OPEN_EXTENDED_MINING_CHANNEL: u8 = 0x13;
+#[cfg(feature = "sv2")]
+const OPEN_EXTENDED_MINING_CHANNEL_SUCCESS: u8 = 0x14;
+#[cfg(feature = "sv2")]
+const OPEN_MINING_CHANNEL_ERROR: u8 = 0x12;
+#[cfg(feature = "sv2")]
+const SUBMIT_SHARES_EXTENDED: u8 = 0x1b;
+#[cfg(feature = "sv2")]
+const SUBMIT_SHARES_SUCCESS: u8 = 0x1c;
+#[cfg(feature = "sv2")]
+const SUBMIT_SHARES_ERROR: u8 = 0x1d;
+#[cfg(feature = "sv2")]
+const SET_TARGET: u8 = 0x21;
+#[cfg(feature = "sv2")]
+const SET_CUSTOM_MINING_JOB: u8 = 0x22;
+#[cfg(feature = "sv2")]
+const SET_CUSTOM_MINING_JOB_SUCCESS: u8 = 0x23;
+#[cfg(feature = "sv2")]
+const SET_CUSTOM_MINING_JOB_ERROR: u8 = 0x24;
+
+// Job Declaration protocol message types.
+#[cfg(feature = "sv2")]
+const ALLOCATE_MINING_JOB_TOKEN: u8 = 0x50;
+#[cfg(feature = "sv2")]
+const ALLOCATE_MINING_JOB_TOKEN_SUCCESS: u8 = 0x51;
+#[cfg(feature = "sv2")]
+const PROVIDE_MISSING_TRANSACTIONS: u8 = 0x55;
+#[cfg(feature = "sv2")]
+const PROVIDE_MISSING_TRANSACTIONS_SUCCESS: u8 = 0x56;
+#[cfg(feature = "sv2")]
+const DECLARE_MINING_JOB: u8 = 0x57;
+#[cfg(feature = "sv2")]
+const DECLARE_MINING_JOB_SUCCESS: u8 = 0x58;
+#[cfg(feature = "sv2")]
+const DECLARE_MINING_JOB_ERROR: u8 = 0x59;
+#[cfg(feature = "sv2")]
+const SUBMIT_SOLUTION: u8 = 0x60;
+
+/// SetupConnection flag asking the pool to accept jobs we select (SetCustomMiningJob).
+#[cfg(feature = "sv2")]
+const REQUIRES_WORK_SELECTION: u32 = 1 << 1;
+
+/// Extranonce bytes the miner wants for itself, after the pool's prefix.
+#[cfg(feature = "sv2")]
+const LOCAL_EXTRANONCE_SIZE: u16 = 4;
+
+/// How long the pool may take to accept a declared job.
+#[cfg(feature = "sv2")]
+const POOL_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The extended channel opened on the pool, shared with the background threads.
+#[cfg(feature = "sv2")]
+struct Channel {
+    id: u32,
+    extranonce_prefix: Vec<u8>,
+    extranonce_size: usize,
+    /// Share target, big-endian.
+    target: Mutex<[u8; 32]>,
+    job: Mutex<Option<DeclaredJob>>,
+}
+
+/// The job currently mined, as both the pool and the JDS know it.
+#[cfg(feature = "sv2")]
+#[derive(Clone)]
+struct DeclaredJob {
+    pool_job_id: u32,
+    /// The full extranonce: the pool's prefix, then ours.
+    extranonce: Vec<u8>,
+    version: u32,
+    /// Previous block hash, internal byte order.
+    prev_hash: [u8; 32],
+    nbits: u32,
+}
+
+/// Connections to the JDS and the pool, kept for the whole session.
+#[cfg(feature = "sv2")]
+pub struct JobDeclarator {
+    user_identity: String,
+    jds_url: String,
+    jds_reader: Sv2Reader,
+    jds_writer: Arc<Mutex<Sv2Writer>>,
+    pool_writer: Arc<Mutex<Sv2Writer>>,
+    /// SetCustomMiningJob replies, forwarded by the pool reader thread.
+    pool_replies: mpsc::Receiver<Frame>,
+    channel: Arc<Channel>,
+    next_request_id: u32,
+    /// Our part of the extranonce, changed for every job.
+    local_extranonce: u32,
+}
+
+#[cfg(feature = "sv2")]
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(feature = "sv2")]
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(feature = "sv2")]
+impl JobDeclarator {
+    /// Connects to the JDS and the pool, opens an extended mining channel, and starts
+    /// submitting the shares and blocks published on `events`.
+    pub fn connect(settings: &JobDeclarationSettings, events: &EventBus, stats: &StatsHandle) -> io::Result<JobDeclarator> {
+        let authority = sv2::parse_authority_key(&settings.authority_pubkey)?;
+        let jds_authority = match &settings.jds_authority_pubkey {
+            Some(key) => sv2::parse_authority_key(key)?,
+            None => authority,
+        };
+
+        let (mut jds_reader, mut jds_writer) = sv2::connect(&settings.jds_url, &jds_authority)?;
+        sv2::setup_connection(&mut jds_reader, &mut jds_writer, sv2::JOB_DECLARATION_PROTOCOL, 0, &settings.jds_url)?;
+        println!("[SV2] Connected to the Job Declaration Server at {}.", settings.jds_url);
+
+        let (mut pool_reader, mut pool_writer) = sv2::connect(&settings.pool_url, &authority)?;
+        // A pool that doesn't take declared jobs refuses the connection here
+        sv2::setup_connection(&mut pool_reader, &mut pool_writer, sv2::MINING_PROTOCOL, REQUIRES_WORK_SELECTION, &settings.pool_url)?;
+
+        // Open the channel we'll mine declared jobs on
+        let nominal_hash_rate = stats.with(|s| s.hashrate_current).max(1.0) as f32;
+        let payload = Encoder::new()
+            .u32(0)
+            .b0_255(settings.user_identity.as_bytes())?
+            .f32(nominal_hash_rate)
+            .u256(&[0xff; 32])
+            .u16(LOCAL_EXTRANONCE_SIZE)
+            .finish();
+        pool_writer.send(OPEN_EXTENDED_MINING_CHANNEL, false, &payload)?;
+        let channel = loop {
+            let frame = pool_reader.recv()?;
+            let mut d = Decoder::new(&frame.payload);
+            match frame.msg_type {
+                OPEN_EXTENDED_MINING_CHANNEL_SUCCESS => {
+                    let _request_id = d.u32()?;
+                    let id = d.u32()?;
+                    let mut target = d.u256()?;
+                    target.reverse();
+                    let extranonce_size = d.u16()? as usize;
+                    let extranonce_prefix = d.b0_255()?.to_vec();
+                    if extranonce_size < extranonce_prefix.len() {
+                        return Err(invalid(format!("Pool granted {} extranonce bytes but a {}-byte prefix", extranonce_size, extranonce_prefix.len())));
+                    }
+                    break Channel { id, extranonce_prefix, extranonce_size, target: Mutex::new(target), job: Mutex::new(None) };
+                },
+                OPEN_MINING_CHANNEL_ERROR => {
+                    let _request_id = d.u32()?;
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!(
+                        "Pool {} refused a channel for '{}': {}", settings.pool_url, settings.user_identity, d.str0_255()?
+                    )));
+                },
+                _ => continue,
+            }
+        };
+        println!(
+            "[SV2] Mining channel {} open at {}: {} extranonce byte(s), share target {}.",
+            channel.id, settings.pool_url, channel.extranonce_size, hex::encode(*lock(&channel.target))
+        );
+
+        let channel = Arc::new(channel);
+        let jds_writer = Arc::new(Mutex::new(jds_writer));
+        let pool_writer = Arc::new(Mutex::new(pool_writer));
+        let (reply_tx, pool_replies) = mpsc::channel();
+        {
+            let channel = Arc::clone(&channel);
+            let pool_url = settings.pool_url.clone();
+            thread::spawn(move || {
+                if let Err(e) = read_pool(&mut pool_reader, &channel, &reply_tx) {
+                    eprintln!("[SV2] Lost the connection to pool {}: {}", pool_url, e);
+                }
+            });
+        }
+        {
+            let (channel, jds_writer, pool_writer) = (Arc::clone(&channel), Arc::clone(&jds_writer), Arc::clone(&pool_writer));
+            let found = events.subscribe_channel();
+            thread::spawn(move || submit_found_work(&found, &channel, &jds_writer, &pool_writer));
+        }
+
+        Ok(JobDeclarator {
+            user_identity: settings.user_identity.clone(),
+            jds_url: settings.jds_url.clone(),
+            jds_reader,
+            jds_writer,
+            pool_writer,
+            pool_replies,
+            channel,
+            next_request_id: 1,
+            local_extranonce: 0,
+        })
+    }
+
+    fn request_id(&mut self) -> u32 {
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        self.next_request_id
+    }
+
+    /// Declares the template in `settings` to the JDS and the pool, and points
+    /// `settings.pool_coinbase` at the coinbase they agreed to.
+    pub fn declare(&mut self, settings: &mut MinerSettings) -> io::Result<()> {
+        // 1. A token for this job, and the outputs the pool wants paid
+        let request_id = self.request_id();
+        let payload = Encoder::new().b0_255(self.user_identity.as_bytes())?.u32(request_id).finish();
+        lock(&self.jds_writer).send(ALLOCATE_MINING_JOB_TOKEN, false, &payload)?;
+        let (token, pool_outputs) = loop {
+            let frame = self.jds_reader.recv()?;
+            if frame.msg_type == ALLOCATE_MINING_JOB_TOKEN_SUCCESS {
+                break parse_token_success(&frame.payload)?;
+            }
+        };
+
+        // 2. Our coinbase: BIP34 height, the extranonce, and the pool's outputs holding the whole reward
+        self.local_extranonce = self.local_extranonce.wrapping_add(1);
+        let local_size = self.channel.extranonce_size - self.channel.extranonce_prefix.len();
+        let mut extranonce = self.channel.extranonce_prefix.clone();
+        extranonce.extend(self.local_extranonce.to_le_bytes().into_iter().chain(std::iter::repeat(0)).take(local_size));
+        let height = i64::try_from(settings.height).map_err(|_| invalid(format!("Invalid template height {}", settings.height)))?;
+        let script_prefix = bitcoin::script::Builder::new().push_int(height).into_script().into_bytes();
+        let outputs = assign_reward(pool_outputs, settings.block_reward_sats)?;
+        let pool_coinbase = PoolCoinbase { script_prefix, extranonce, outputs };
+        let (_, block) = miner::calculate_pool_merkle_root(&pool_coinbase, &settings.transactions, &settings.priority_txids, &settings.block_limits)?;
+        let coinbase = &block.txdata[0];
+
+        // 3. Declare it to the JDS: the serialized coinbase around the extranonce, and the transactions
+        let mut serialized = Vec::new();
+        coinbase.consensus_encode(&mut serialized)?;
+        // version, [segwit marker and flag], input count, outpoint, scriptSig length, height push
+        let witness_flag = if coinbase.input[0].witness.is_empty() { 0 } else { 2 };
+        let split = 4 + witness_flag + 1 + 36 + 1 + pool_coinbase.script_prefix.len();
+        let (coinbase_prefix, rest) = serialized.split_at(split);
+        let coinbase_suffix = &rest[pool_coinbase.extranonce.len()..];
+
+        let short_id_nonce: u64 = rand_u64();
+        let (k0, k1) = short_id_keys(short_id_nonce);
+        let mut short_ids = Encoder::new();
+        let mut txid_bytes = Vec::new();
+        short_ids.u16(u16::try_from(block.txdata.len() - 1).map_err(|_| invalid("Too many transactions to declare".to_string()))?);
+        for tx in &block.txdata[1..] {
+            let txid = tx.compute_txid().to_byte_array();
+            let short_id = siphash24::Hash::hash_with_keys(k0, k1, &txid).as_u64();
+            short_ids.raw(&short_id.to_le_bytes()[..6]);
+            txid_bytes.extend_from_slice(&txid);
+        }
+        let request_id = self.request_id();
+        let payload = Encoder::new()
+            .u32(request_id)
+            .b0_255(&token)?
+            .u32(settings.version)
+            .b0_64k(coinbase_prefix)?
+            .b0_64k(coinbase_suffix)?
+            .u64(short_id_nonce)
+            .raw(&short_ids.finish())
+            .u256(&sha256::Hash::hash(&txid_bytes).to_byte_array())
+            .b0_64k(&[])?
+            .finish();
+        lock(&self.jds_writer).send(DECLARE_MINING_JOB, false, &payload)?;
+        let token = loop {
+            let frame = self.jds_reader.recv()?;
+            let mut d = Decoder::new(&frame.payload);
+            match frame.msg_type {
+                // The JDS may not know some of our transactions yet
+                PROVIDE_MISSING_TRANSACTIONS => {
+                    let missing_request = d.u32()?;
+                    let positions = d.seq0_64k_u16()?;
+                    let mut reply = Encoder::new();
+                    reply.u32(missing_request).u16(positions.len() as u16);
+                    for position in positions {
+                        let tx = block.txdata.get(usize::from(position) + 1)
+                            .ok_or_else(|| invalid(format!("JDS asked for transaction {} of {}", position, block.txdata.len() - 1)))?;
+                        reply.b0_16m(&bitcoin::consensus::serialize(tx))?;
+                    }
+                    lock(&self.jds_writer).send(PROVIDE_MISSING_TRANSACTIONS_SUCCESS, false, &reply.finish())?;
+                },
+                DECLARE_MINING_JOB_SUCCESS if d.u32()? == request_id => break d.b0_255()?.to_vec(),
+                DECLARE_MINING_JOB_ERROR if d.u32()? == request_id => {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!(
+                        "JDS {} rejected the job: {}", self.jds_url, d.str0_255()?
+                    )));
+                },
+                _ => continue,
+            }
+        };
+
+        // 4. Ask the pool to accept shares for it
+        let prev_hash = display_hex_to_internal(&settings.prev_block_hash)?;
+        let mut outputs = Vec::new();
+        coinbase.output.consensus_encode(&mut outputs)?;
+        let mut merkle_path = Encoder::new();
+        let path = merkle::coinbase_branch(&block.txdata);
+        merkle_path.u8(u8::try_from(path.len()).map_err(|_| invalid("Merkle path too long".to_string()))?);
+        for hash in &path {
+            merkle_path.u256(hash);
+        }
+        let request_id = self.request_id();
+        let min_ntime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as u32;
+        let payload = Encoder::new()
+            .u32(self.channel.id)
+            .u32(request_id)
+            .b0_255(&token)?
+            .u32(settings.version)
+            .u256(&prev_hash)
+            .u32(min_ntime)
+            .u32(settings.nbits)
+            .u32(coinbase.version.0 as u32)
+            .b0_255(&pool_coinbase.script_prefix)?
+            .u32(coinbase.input[0].sequence.0)
+            .u64(settings.block_reward_sats)
+            .b0_64k(&outputs)?
+            .u32(coinbase.lock_time.to_consensus_u32())
+            .raw(&merkle_path.finish())
+            .u16(self.channel.extranonce_size as u16)
+            .finish();
+        lock(&self.pool_writer).send(SET_CUSTOM_MINING_JOB, true, &payload)?;
+        let pool_job_id = loop {
+            let frame = self.pool_replies.recv_timeout(POOL_REPLY_TIMEOUT)
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Pool did not answer SetCustomMiningJob"))?;
+            let mut d = Decoder::new(&frame.payload);
+            let (_channel_id, reply_id) = (d.u32()?, d.u32()?);
+            if reply_id != request_id {
+                continue;
+            }
+            if frame.msg_type == SET_CUSTOM_MINING_JOB_ERROR {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Pool rejected the declared job: {}", d.str0_255()?)));
+            }
+            break d.u32()?;
+        };
+
+        *lock(&self.channel.job) = Some(DeclaredJob {
+            pool_job_id,
+            extranonce: pool_coinbase.extranonce.clone(),
+            version: settings.version,
+            prev_hash,
+            nbits: settings.nbits,
+        });
+        println!(
+            "[SV2] Declared job {} at height {}: {} transaction(s), coinbase paying {} output(s) of the pool.",
+            pool_job_id, settings.height, block.txdata.len() - 1, pool_coinbase.outputs.len()
+        );
+        settings.pool_coinbase = Some(pool_coinbase);
+        Ok(())
+    }
+}
+
+/// Reads AllocateMiningJobToken.Success: the token and the pool's coinbase outputs.
+/// Accepts both the layout with `coinbase_output_max_additional_size` and
+/// `async_mining_allowed` and the later one without them.
+#[cfg(feature = "sv2")]
+fn parse_token_success(payload: &[u8]) -> io::Result<(Vec<u8>, Vec<bitcoin::TxOut>)> {
+    let mut d = Decoder::new(payload);
+    let _request_id = d.u32()?;
+    let token = d.b0_255()?.to_vec();
+    let mut later = Decoder::new(&payload[payload.len() - d.remaining()..]);
+    let outputs = match later.b0_64k() {
+        Ok(outputs) if later.remaining() == 0 => outputs,
+        _ => {
+            let _max_additional_size = d.u32()?;
+            let outputs = d.b0_64k()?;
+            let _async_mining_allowed = d.bool()?;
+            outputs
+        },
+    };
+    // Either a consensus-encoded list, or outputs back to back
+    let outputs = match bitcoin::consensus::deserialize::<Vec<bitcoin::TxOut>>(outputs) {
+        Ok(list) => list,
+        Err(_) => {
+            let mut cursor = outputs;
+            let mut list = Vec::new();
+            while !cursor.is_empty() {
+                list.push(bitcoin::TxOut::consensus_decode(&mut cursor).map_err(|e| invalid(format!("Invalid pool coinbase outputs: {}", e)))?);
+            }
+            list
+        },
+    };
+    Ok((token, outputs))
+}
+
+/// Gives the block reward not already claimed by the pool's outputs to its first
+/// zero-valued output (or its first output).
+#[cfg(feature = "sv2")]
+fn assign_reward(mut outputs: Vec<bitcoin::TxOut>, block_reward_sats: u64) -> io::Result<Vec<bitcoin::TxOut>> {
+    let claimed: u64 = outputs.iter().map(|o| o.value.to_sat()).sum();
+    let remaining = block_reward_sats.checked_sub(claimed)
+        .ok_or_else(|| invalid(format!("Pool outputs claim {} sats of a {} sat reward", claimed, block_reward_sats)))?;
+    let index = outputs.iter().position(|o| o.value == bitcoin::Amount::ZERO).unwrap_or(0);
+    let output = outputs.get_mut(index).ok_or_else(|| invalid("Pool sent no coinbase outputs".to_string()))?;
+    output.value += bitcoin::Amount::from_sat(remaining);
+    Ok(outputs)
+}
+
+/// SipHash keys of BIP152-style short ids: the first two words of SHA256(nonce).
+#[cfg(feature = "sv2")]
+fn short_id_keys(nonce: u64) -> (u64, u64) {
+    let hash = sha256::Hash::hash(&nonce.to_le_bytes()).to_byte_array();
+    (u64::from_le_bytes(hash[..8].try_into().unwrap()), u64::from_le_bytes(hash[8..16].try_into().unwrap()))
+}
+
+#[cfg(feature = "sv2")]
+fn rand_u64() -> u64 {
+    use bitcoin::secp256k1::rand::RngCore;
+    bitcoin::secp256k1::rand::thread_rng().next_u64()
+}
+
+#[cfg(feature = "sv2")]
+fn display_hex_to_internal(hash: &str) -> io::Result<[u8; 32]> {
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(hash, &mut bytes).map_err(|e| invalid(format!("Invalid block hash '{}': {}", hash, e)))?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+/// Handles what the pool sends on its own: target changes and share results.
+/// Replies to SetCustomMiningJob go back to `declare`.
+#[cfg(feature = "sv2")]
+fn read_pool(reader: &mut Sv2Reader, channel: &Channel, replies: &mpsc::Sender<Frame>) -> io::Result<()> {
+    loop {
+        let frame = reader.recv()?;
+        let mut d = Decoder::new(&frame.payload);
+        match frame.msg_type {
+            SET_TARGET => {
+                let _channel_id = d.u32()?;
+                let mut target = d.u256()?;
+                target.reverse();
+                println!("[SV2] Pool set the share target to {}.", hex::encode(target));
+                *lock(&channel.target) = target;
+            },
+            SUBMIT_SHARES_SUCCESS => {
+                let (_channel_id, _last_sequence, accepted) = (d.u32()?, d.u32()?, d.u32()?);
+                println!("[SV2] Pool accepted {} share(s).", accepted);
+            },
+            SUBMIT_SHARES_ERROR => {
+                let (_channel_id, sequence) = (d.u32()?, d.u32()?);
+                eprintln!("[SV2] Pool rejected share {}: {}", sequence, d.str0_255()?);
+            },
+            SET_CUSTOM_MINING_JOB_SUCCESS | SET_CUSTOM_MINING_JOB_ERROR if replies.send(frame.clone()).is_err() => return Ok(()),
+            _ => {},
+        }
+    }
+}
+
+/// Submits shares meeting the channel target to the pool, and found blocks to the
+/// JDS as well, so the pool can propagate them too.
+#[cfg(feature = "sv2")]
+fn submit_found_work(events: &mpsc::Receiver<MinerEvent>, channel: &Channel, jds: &Mutex<Sv2Writer>, pool: &Mutex<Sv2Writer>) {
+    let mut sequence_number: u32 = 0;
+    for event in events {
+        let (nonce, time, is_block, hash) = match &event {
+            MinerEvent::ShareFound { hash, nonce, time, .. } => (*nonce, *time, false, hash),
+            MinerEvent::BlockFound { hash, nonce, time, .. } => (*nonce, *time, true, hash),
+            _ => continue,
+        };
+        let Some(job) = lock(&channel.job).clone() else { continue };
+        let result = if is_block {
+            Encoder::new()
+                .b0_255(&job.extranonce)
+                .map(|e| e.u256(&job.prev_hash).u32(time).u32(nonce).u32(job.nbits).u32(job.version).finish())
+                .and_then(|payload| lock(jds).send(SUBMIT_SOLUTION, false, &payload))
+        } else {
+            let meets_target = hex::decode(hash).is_ok_and(|h| h.as_slice() <= lock(&channel.target).as_slice());
+            if !meets_target {
+                continue;
+            }
+            sequence_number = sequence_number.wrapping_add(1);
+            Encoder::new()
+                .u32(channel.id)
+                .u32(sequence_number)
+                .u32(job.pool_job_id)
+                .u32(nonce)
+                .u32(time)
+                .u32(job.version)
+                .b0_255(&job.extranonce[channel.extranonce_prefix.len()..])
+                .map(|e| e.finish())
+                .and_then(|payload| lock(pool).send(SUBMIT_SHARES_EXTENDED, true, &payload))
+        };
+        if let Err(e) = result {
+            eprintln!("[SV2] Could not submit {}: {}", if is_block { "the block to the JDS" } else { "a share to the pool" }, e);
+        }
+    }
+}
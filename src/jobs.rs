@@ -0,0 +1,165 @@
+//! Mining several chains at once (the `extra_chains` setting): next to the main chain,
+//! each extra chain gets a lane that queues up its own jobs. A lane fetches templates
+//! from its own node, hashes them on its own CPU threads, and keeps its own stats and
+//! events, so its shares and blocks never reach the main chain's pools, hooks or
+//! submission path. Pausing and throttling apply to every lane.
+
+use crate::control::MinerControl;
+use crate::events::EventBus;
+use crate::miner::{self, MiningOutcome};
+use crate::scheduler::Device;
+use crate::settings::{self, MinerSettings};
+use crate::stats::StatsHandle;
+use crate::units::format_network;
+use crate::worker::WorkerPool;
+use crate::zmq;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// How long a lane waits before asking its node again after an error.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+fn default_cpu_threads() -> usize {
+    1
+}
+
+/// One extra chain to mine (an entry of the `extra_chains` setting).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraChain {
+    /// Short name for the output and the chain's stats file, e.g. "testnet".
+    pub name: String,
+    /// The chain's node, as for the main chain: a bitcoin.conf to read, or a URL and user.
+    #[serde(default)]
+    pub bitcoin_conf: Option<String>,
+    #[serde(default)]
+    pub rpc_url: String,
+    #[serde(default)]
+    pub rpc_user: String,
+    /// Prompted for at startup unless `bitcoin_conf` is set, and never saved.
+    #[serde(skip_serializing, default)]
+    pub rpc_pass: String,
+    /// The chain the node must follow, as the main `network` setting.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// The chain node's ZMQ new-block endpoint, as the main `zmq_endpoint` setting.
+    /// The main chain's endpoint is never used for a lane.
+    #[serde(default)]
+    pub zmq_endpoint: Option<String>,
+    /// Where the chain's blocks pay, an address on that chain.
+    pub reward_address: String,
+    #[serde(default = "default_cpu_threads")]
+    pub cpu_threads: usize,
+}
+
+/// The lanes mining the extra chains. Lanes run until the process exits.
+pub struct JobQueue {
+    lanes: Vec<(String, StatsHandle)>,
+}
+
+impl JobQueue {
+    /// Starts a lane for every extra chain in `settings`. Fails before anything starts
+    /// if a chain's node or reward address is unusable.
+    pub fn start(settings: &MinerSettings, control: &MinerControl) -> io::Result<JobQueue> {
+        let mut lanes = Vec::new();
+        let mut prepared = Vec::new();
+        for chain in &settings.extra_chains {
+            prepared.push(lane_settings(settings, chain)?);
+        }
+        for lane in prepared {
+            let name = lane.lane.clone().unwrap_or_default();
+            let stats = StatsHandle::for_chain(Duration::from_secs_f64(settings.hashrate_half_life_secs.max(0.001)), &name);
+            println!("[Jobs] Mining {} ({}) on {} CPU thread(s) alongside the main chain.", name, format_network(lane.network()), lane.cpu_threads);
+            let (lane_stats, control) = (stats.clone(), control.clone());
+            thread::Builder::new()
+                .name(format!("lane-{}", name))
+                .spawn(move || run_lane(lane, lane_stats, control))?;
+            lanes.push((name, stats));
+        }
+        Ok(JobQueue { lanes })
+    }
+
+    /// Each lane's name and stats.
+    pub fn lanes(&self) -> &[(String, StatsHandle)] {
+        &self.lanes
+    }
+}
+
+/// The main chain's settings with the extra chain's node, payout and threads in place,
+/// and everything tied to the main chain (pools, workers, GPUs, payout rotation and splits,
+/// ZMQ endpoint) off.
+fn lane_settings(main: &MinerSettings, chain: &ExtraChain) -> io::Result<MinerSettings> {
+    let mut settings = MinerSettings {
+        bitcoin_conf: chain.bitcoin_conf.clone(),
+        network: chain.network.clone(),
+        rpc_url: chain.rpc_url.clone(),
+        rpc_user: chain.rpc_user.clone(),
+        rpc_pass: chain.rpc_pass.clone(),
+        reward_address: chain.reward_address.clone(),
+        reward_addresses: Vec::new(),
+        reward_splits: Vec::new(),
+        payout_xpub: None,
+        zmq_endpoint: None,
+        priority_txids: Vec::new(),
+        compact_block_peers: Vec::new(),
+        cpu_threads: chain.cpu_threads,
+        workers: Vec::new(),
+        opencl_devices: None,
+        cuda_devices: None,
+        job_declaration: None,
+        failover_pool: None,
+        extra_chains: Vec::new(),
+        attestation_key: None,
+        chain: None,
+        lane: Some(chain.name.clone()),
+        ..main.clone()
+    };
+    if let Some(path) = &chain.bitcoin_conf {
+        let node = crate::bitcoin_conf::read(path)?;
+        settings.rpc_url = node.url;
+        settings.rpc_user = node.user;
+        settings.rpc_pass = node.pass;
+    } else if settings.rpc_pass.is_empty() {
+        println!("Please enter the RPC password for {} ({}):", chain.name, chain.rpc_url);
+        settings.rpc_pass = rpassword::prompt_password("Password: ")?;
+    }
+    if let Some(endpoint) = &chain.zmq_endpoint {
+        match zmq::parse_endpoint(endpoint) {
+            Ok(_) => settings.zmq_endpoint = Some(endpoint.clone()),
+            Err(e) => eprintln!("Ignoring zmq_endpoint of extra_chains {}: {}", chain.name, e),
+        }
+    }
+    let context = |e: io::Error| io::Error::new(e.kind(), format!("extra_chains {}: {}", chain.name, e));
+    settings.detect_chain().map_err(context)?;
+    settings::check_reward_address(&settings.reward_address, settings.network())
+        .map_err(|e| context(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    Ok(settings)
+}
+
+/// Mines the lane's chain block after block. Unlike the main chain, a lane keeps going
+/// after a block, and waits out node errors instead of stopping.
+fn run_lane(mut settings: MinerSettings, stats: StatsHandle, control: MinerControl) {
+    let name = settings.lane.clone().unwrap_or_default();
+    let events = EventBus::new();
+    let workers = WorkerPool::start(&[], stats.clone());
+    let mut gpus: Vec<Box<dyn Device>> = Vec::new();
+    loop {
+        if let Err(e) = settings.update_from_node() {
+            eprintln!("[Jobs] {}: could not get a template: {}. Retrying in {}s.", name, e, RETRY_DELAY.as_secs());
+            thread::sleep(RETRY_DELAY);
+            continue;
+        }
+        match miner::mine_block(&settings, &stats, &events, &control, &workers, &mut gpus) {
+            Ok(MiningOutcome::BlockFound { hash, accepted }) => {
+                println!("[Jobs] {}: block {} {}.", name, hash, if accepted { "accepted" } else { "rejected" });
+            },
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("[Jobs] {}: mining error: {}. Retrying in {}s.", name, e, RETRY_DELAY.as_secs());
+                thread::sleep(RETRY_DELAY);
+            },
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Keeping a Mac awake while mining (the `prevent_sleep` setting). macOS puts an idle
+//! machine to sleep even with the CPU busy hashing, which silently ends an overnight run.
+//! While the miner isn't paused, a `caffeinate -i` child holds a "no idle sleep" power
+//! assertion; pausing releases it. `caffeinate` ships with macOS, and exits by itself if
+//! the miner dies, so the assertion never outlives it.
+
+use crate::control::MinerControl;
+use std::io;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the pause state is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Holds the assertion while mining runs unpaused. Dropping it releases the assertion.
+pub struct KeepAwake {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl KeepAwake {
+    /// Starts following `control`: awake while not paused, free to sleep while paused.
+    pub fn start(control: &MinerControl) -> KeepAwake {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_stop, control) = (Arc::clone(&stop), control.clone());
+        let thread = thread::spawn(move || {
+            let mut assertion: Option<Child> = None;
+            while !thread_stop.load(Ordering::Relaxed) && !control.is_stopped() {
+                match (&assertion, control.is_paused()) {
+                    (None, false) => match hold() {
+                        Ok(child) => {
+                            println!("[Power] Keeping the Mac awake while mining.");
+                            assertion = Some(child);
+                        },
+                        Err(e) => {
+                            eprintln!("[Power] Could not prevent sleep with caffeinate: {}", e);
+                            return;
+                        },
+                    },
+                    (Some(_), true) => {
+                        release(assertion.take());
+                        println!("[Power] Mining paused; the Mac may sleep again.");
+                    },
+                    _ => {},
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            release(assertion);
+        });
+        KeepAwake { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for KeepAwake {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Starts `caffeinate`, asserting no idle sleep until it is killed or this process exits.
+fn hold() -> io::Result<Child> {
+    Command::new("caffeinate")
+        .args(["-i", "-w", &std::process::id().to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+}
+
+fn release(assertion: Option<Child>) {
+    if let Some(mut child) = assertion {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
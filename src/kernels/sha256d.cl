@@ -0,0 +1,103 @@
+// Double SHA-256 of block headers, one nonce per work item.
+//
+// The host passes the SHA-256 state after the first 64 header bytes (the midstate)
+// and the next 12 bytes as big-endian words; each work item appends its nonce,
+// finishes the first hash and computes the second. Nonces whose hash has its most
+// significant word (in display order) at or below `target_top` are written to
+// `results`: results[0] counts them, the nonces follow. The host rehashes every
+// candidate against the full target.
+//
+// The CUDA backend compiles this file too, after a prelude mapping the OpenCL
+// built-ins used here to CUDA ones; FUNC marks the helpers as device functions there.
+
+#define MAX_RESULTS 63
+
+#ifndef FUNC
+#define FUNC
+#endif
+
+#define ROTR(x, n) rotate((uint)(x), (uint)(32 - (n)))
+#define SWAP32(x) (((x) >> 24) | (((x) >> 8) & 0xff00u) | (((x) << 8) & 0xff0000u) | ((x) << 24))
+#define CH(x, y, z) bitselect((z), (y), (x))
+#define MAJ(x, y, z) bitselect((x), (y), (x) ^ (z))
+#define BSIG0(x) (ROTR(x, 2) ^ ROTR(x, 13) ^ ROTR(x, 22))
+#define BSIG1(x) (ROTR(x, 6) ^ ROTR(x, 11) ^ ROTR(x, 25))
+#define SSIG0(x) (ROTR(x, 7) ^ ROTR(x, 18) ^ ((x) >> 3))
+#define SSIG1(x) (ROTR(x, 17) ^ ROTR(x, 19) ^ ((x) >> 10))
+
+__constant uint K[64] = {
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+};
+
+// One SHA-256 compression of the 16 words at the start of `w` into `state`.
+FUNC void compress(uint *state, uint *w)
+{
+    for (int i = 16; i < 64; i++) {
+        w[i] = SSIG1(w[i - 2]) + w[i - 7] + SSIG0(w[i - 15]) + w[i - 16];
+    }
+    uint a = state[0], b = state[1], c = state[2], d = state[3];
+    uint e = state[4], f = state[5], g = state[6], h = state[7];
+    for (int i = 0; i < 64; i++) {
+        uint t1 = h + BSIG1(e) + CH(e, f, g) + K[i] + w[i];
+        uint t2 = BSIG0(a) + MAJ(a, b, c);
+        h = g; g = f; f = e; e = d + t1;
+        d = c; c = b; b = a; a = t1 + t2;
+    }
+    state[0] += a; state[1] += b; state[2] += c; state[3] += d;
+    state[4] += e; state[5] += f; state[6] += g; state[7] += h;
+}
+
+__kernel void sha256d_scan(
+    __global const uint *midstate,
+    uint tail0, uint tail1, uint tail2,
+    uint first_nonce, uint count, uint target_top,
+    __global uint *results)
+{
+    uint id = get_global_id(0);
+    if (id >= count) {
+        return;
+    }
+    uint nonce = first_nonce + id;
+    uint w[64];
+    uint state[8];
+
+    // First hash: the last 16 header bytes, padded to a block of an 80-byte message
+    for (int i = 0; i < 8; i++) {
+        state[i] = midstate[i];
+    }
+    w[0] = tail0; w[1] = tail1; w[2] = tail2; w[3] = SWAP32(nonce);
+    w[4] = 0x80000000u;
+    for (int i = 5; i < 15; i++) {
+        w[i] = 0;
+    }
+    w[15] = 640;
+    compress(state, w);
+
+    // Second hash: the 32-byte first hash, padded
+    for (int i = 0; i < 8; i++) {
+        w[i] = state[i];
+    }
+    w[8] = 0x80000000u;
+    for (int i = 9; i < 15; i++) {
+        w[i] = 0;
+    }
+    w[15] = 256;
+    state[0] = 0x6a09e667; state[1] = 0xbb67ae85; state[2] = 0x3c6ef372; state[3] = 0xa54ff53a;
+    state[4] = 0x510e527f; state[5] = 0x9b05688c; state[6] = 0x1f83d9ab; state[7] = 0x5be0cd19;
+    compress(state, w);
+
+    // The hash's last four bytes are the most significant in display order
+    if (SWAP32(state[7]) <= target_top) {
+        uint slot = atomic_inc(&results[0]);
+        if (slot < MAX_RESULTS) {
+            results[1 + slot] = nonce;
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Solo lottery miner: the mining engine and its supporting modules.
+//! The `solo_lottery_miner` binary is a thin command-line wrapper around this library.
+//! Modules that talk to a node need the `rpc` feature (on by default); without it
+//! only the mining core is built, for embedded and offline use.
+
+#[cfg(feature = "rpc")]
+pub mod settings;
+pub mod template;
+pub mod bitcoin_conf;
+#[cfg(feature = "rpc")]
+pub mod discovery;
+pub mod miner;
+pub mod merkle;
+pub mod hasher;
+pub mod pow;
+pub mod simd;
+pub mod trace;
+#[cfg(feature = "rpc")]
+pub mod scheduler;
+#[cfg(any(feature = "gpu-opencl", feature = "gpu-cuda"))]
+pub mod gpu;
+#[cfg(feature = "gpu-opencl")]
+pub mod opencl;
+#[cfg(feature = "gpu-cuda")]
+pub mod cuda;
+#[cfg(feature = "rpc")]
+pub mod worker;
+#[cfg(feature = "sv2")]
+pub mod sv2;
+#[cfg(feature = "rpc")]
+pub mod job_declaration;
+#[cfg(feature = "rpc")]
+pub mod stratum;
+#[cfg(feature = "rpc")]
+pub mod failover;
+#[cfg(feature = "rpc")]
+pub mod jobs;
+#[cfg(feature = "rpc")]
+pub mod engine;
+#[cfg(feature = "rpc")]
+pub mod prefetch;
+#[cfg(feature = "rpc")]
+pub mod reconnect;
+pub mod assembly;
+pub mod p2p;
+#[cfg(feature = "rpc")]
+pub mod chain;
+#[cfg(feature = "rpc")]
+pub mod zmq;
+#[cfg(feature = "rpc")]
+pub mod watch;
+#[cfg(feature = "rpc")]
+pub mod audit;
+#[cfg(feature = "rpc")]
+pub mod rest;
+#[cfg(feature = "rpc")]
+pub mod difficulty;
+pub mod halving;
+#[cfg(feature = "rpc")]
+pub mod submit;
+#[cfg(feature = "rpc")]
+pub mod wallet;
+pub mod units;
+#[cfg(feature = "rpc")]
+pub mod logging;
+#[cfg(feature = "rpc")]
+pub mod instance;
+#[cfg(all(windows, feature = "windows-service"))]
+pub mod service;
+#[cfg(feature = "rpc")]
+pub mod stats;
+#[cfg(feature = "rpc")]
+pub mod power;
+#[cfg(target_os = "macos")]
+pub mod keep_awake;
+#[cfg(feature = "rpc")]
+pub mod attestation;
+pub mod perf;
+pub mod events;
+pub mod hooks;
+pub mod control;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "consensus-check")]
+pub mod validation;
+
+#[cfg(feature = "rpc")]
+pub use engine::{Miner, MinerBuilder};
@@ -0,0 +1,250 @@
+use crate::{stats, units};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Captured streams: the descriptor, where it originally pointed, and the thread draining it.
+#[cfg(unix)]
+static CAPTURED: Mutex<Vec<(libc::c_int, libc::c_int, JoinHandle<()>)>> = Mutex::new(Vec::new());
+
+/// When and how long log files are kept.
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    /// Rotate once the current file would grow beyond this many bytes.
+    pub max_bytes: u64,
+    /// Also rotate once the current file is this old, if set.
+    pub max_age: Option<Duration>,
+    /// Number of rotated files (`<file>.1` ... `<file>.N`) kept besides the current one.
+    pub keep_files: usize,
+}
+
+/// A log file that rotates itself according to a `RotationPolicy`.
+struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    size: u64,
+    opened_at: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, policy: RotationPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Could not open log file {}: {}", path.display(), e)))?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, policy, file, size, opened_at: stats::unix_now() })
+    }
+
+    /// Appends one line, rotating first if it would break the policy.
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let too_big = self.size > 0 && self.size + len > self.policy.max_bytes;
+        let too_old = self.policy.max_age.is_some_and(|age| stats::unix_now().saturating_sub(self.opened_at) >= age.as_secs());
+        if too_big || too_old {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shifts `<file>.N-1` to `<file>.N` and so on, dropping the oldest, then starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.policy.keep_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(numbered(self.policy.keep_files));
+            for n in (1..self.policy.keep_files).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    fs::rename(&from, numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        *self = RotatingFile::open(self.path.clone(), self.policy.clone())?;
+        Ok(())
+    }
+}
+
+/// The system logger to send console output to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemLog {
+    /// The local syslog daemon, via /dev/log.
+    Syslog,
+    /// The systemd journal, via its native protocol.
+    Journald,
+}
+
+impl FromStr for SystemLog {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "syslog" => Ok(SystemLog::Syslog),
+            "journald" => Ok(SystemLog::Journald),
+            other => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown log_target '{}'; use \"syslog\" or \"journald\"", other))),
+        }
+    }
+}
+
+/// Identifier the miner logs under in syslog and the journal.
+const SYSLOG_IDENTIFIER: &str = "solo-miner";
+
+/// syslog severities (RFC 5424), which the journal uses as PRIORITY too.
+const SEVERITY_ERROR: u8 = 3;
+const SEVERITY_WARNING: u8 = 4;
+const SEVERITY_INFO: u8 = 6;
+
+/// syslog facility "daemon", shifted into place.
+const FACILITY_DAEMON: u8 = 3 << 3;
+
+/// Where captured lines are written besides the terminal.
+struct Sinks {
+    file: Option<RotatingFile>,
+    system: Option<(SystemLog, UnixDatagram)>,
+}
+
+impl Sinks {
+    /// Writes one line to every sink. Lines from stderr are errors; lines from
+    /// stdout are warnings if they say so and informational otherwise.
+    fn write_line(&mut self, text: &str, from_stderr: bool) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_line(&format!("{} {}", units::format_timestamp(stats::unix_now()), text))?;
+        }
+        if let Some((target, socket)) = &self.system {
+            let severity = if from_stderr {
+                SEVERITY_ERROR
+            } else if text.contains("Warning") {
+                SEVERITY_WARNING
+            } else {
+                SEVERITY_INFO
+            };
+            let message = match target {
+                SystemLog::Syslog => format!("<{}>{}[{}]: {}", FACILITY_DAEMON | severity, SYSLOG_IDENTIFIER, std::process::id(), text),
+                SystemLog::Journald => format!("PRIORITY={}\nSYSLOG_IDENTIFIER={}\nMESSAGE={}\n", severity, SYSLOG_IDENTIFIER, text),
+            };
+            socket.send(message.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies everything the process prints to stdout and stderr into a rotating log
+/// file (timestamping each line) and/or the system logger, while still showing it
+/// on the terminal. When a system logger is used and the output isn't a terminal
+/// (e.g. running as a service), it isn't echoed, to avoid logging everything twice.
+///
+/// The miner reports through plain `println!`, so rather than routing every call
+/// through a logger, the standard streams are redirected into pipes that a
+/// background thread drains. Only available on Unix.
+#[cfg(unix)]
+pub fn install(file: Option<(&str, RotationPolicy)>, system: Option<SystemLog>) -> io::Result<()> {
+    let file = file.map(|(path, policy)| RotatingFile::open(PathBuf::from(path), policy)).transpose()?;
+    let system = system.map(|target| -> io::Result<_> {
+        let path = match target {
+            SystemLog::Syslog => "/dev/log",
+            SystemLog::Journald => "/run/systemd/journal/socket",
+        };
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path).map_err(|e| io::Error::new(e.kind(), format!("Could not connect to {}: {}", path, e)))?;
+        Ok((target, socket))
+    }).transpose()?;
+    let echo = system.is_none();
+    let sinks = Arc::new(Mutex::new(Sinks { file, system }));
+
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        let (reader, terminal) = capture(fd)?;
+        // SAFETY: isatty and dup only read the descriptor the `terminal` File owns.
+        let (original, echo) = unsafe {
+            let raw = std::os::fd::AsRawFd::as_raw_fd(&terminal);
+            (libc::dup(raw), echo || libc::isatty(raw) == 1)
+        };
+        let sinks = Arc::clone(&sinks);
+        let from_stderr = fd == libc::STDERR_FILENO;
+        let handle = thread::spawn(move || drain(reader, terminal, echo, sinks, from_stderr));
+        CAPTURED.lock().unwrap_or_else(|e| e.into_inner()).push((fd, original, handle));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn install(_file: Option<(&str, RotationPolicy)>, _system: Option<SystemLog>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "log_file and log_target are only supported on Unix; redirect the console output instead"))
+}
+
+/// Restores the original stdout/stderr and waits until everything printed so far
+/// has reached the log. Call before exiting so the last lines aren't lost.
+#[cfg(unix)]
+pub fn finish() {
+    let _ = io::stdout().flush();
+    let captured = std::mem::take(&mut *CAPTURED.lock().unwrap_or_else(|e| e.into_inner()));
+    for (fd, original, handle) in captured {
+        // SAFETY: pointing `fd` back at its original target closes the pipe's only
+        // write end, so the drain thread sees end-of-file once it has caught up.
+        unsafe {
+            libc::dup2(original, fd);
+            libc::close(original);
+        }
+        let _ = handle.join();
+    }
+}
+
+#[cfg(not(unix))]
+pub fn finish() {}
+
+/// Points `fd` at a new pipe. Returns the pipe's read end and a handle to where
+/// `fd` pointed before (normally the terminal).
+#[cfg(unix)]
+fn capture(fd: libc::c_int) -> io::Result<(File, File)> {
+    use std::os::fd::FromRawFd;
+
+    let mut pipe = [0; 2];
+    // SAFETY: `pipe` has room for the two descriptors; every call's result is checked,
+    // and each new descriptor is owned by exactly one `File`.
+    unsafe {
+        if libc::pipe(pipe.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = libc::dup(fd);
+        if original < 0 || libc::dup2(pipe[1], fd) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::close(pipe[1]);
+        Ok((File::from_raw_fd(pipe[0]), File::from_raw_fd(original)))
+    }
+}
+
+/// Echoes each captured line to the terminal (if `echo`) and writes it to the sinks.
+fn drain(reader: File, mut terminal: File, echo: bool, sinks: Arc<Mutex<Sinks>>, from_stderr: bool) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {},
+        }
+        if echo {
+            let _ = terminal.write_all(&line);
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        let mut sinks = sinks.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = sinks.write_line(text, from_stderr) {
+            // stderr may be the pipe we're draining, so report on the terminal directly.
+            let _ = writeln!(terminal, "[Log] Could not write log output: {}", e);
+        }
+    }
+}
@@ -1,23 +1,321 @@
-mod settings;
-mod miner;
+use solo_lottery_miner::{attestation, chain, control, difficulty, events, failover, halving, instance, jobs, logging, miner, perf, pow, power, prefetch, reconnect, settings, scheduler, stats, trace, watch, worker, zmq};
+use std::time::Duration;
 
+/// How long to wait after a failed job before starting on a fresh template.
+const MINING_RETRY_DELAY: Duration = Duration::from_secs(10);
 
 fn main() {
-    match settings::MinerSettings::load() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        // trace-hash [HEADER_HEX] [--rounds]: print the SHA-256d of one header step by step
+        Some("trace-hash") => {
+            if let Err(e) = trace_hash(&args[1..]) {
+                eprintln!("trace-hash: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        },
+        // service install | uninstall: run as a Windows service
+        Some("service") => {
+            #[cfg(all(windows, feature = "windows-service"))]
+            if let Err(e) = solo_lottery_miner::service::command(&args[1..]) {
+                eprintln!("service: {}", e);
+                std::process::exit(1);
+            }
+            #[cfg(not(all(windows, feature = "windows-service")))]
+            eprintln!("Ignoring service: rebuild on Windows with `--features windows-service` to run as a Windows service.");
+            return;
+        },
+        // verify-attestation FILE...: check best-share attestations and rank them
+        Some("verify-attestation") => {
+            attestation::print_leaderboard(&args[1..]);
+            return;
+        },
+        _ => {},
+    }
+    // --events: one JSON object per event on stdout, human-readable output on stderr
+    let events_mode = std::env::args().skip(1).any(|arg| arg == "--events");
+    // --watch: follow templates and found blocks without hashing
+    let watch_mode = std::env::args().skip(1).any(|arg| arg == "--watch");
+    run(events_mode, watch_mode);
+    // Let the log writer catch up before the process exits
+    logging::finish();
+}
+
+fn run(events_mode: bool, watch_mode: bool) {
+    // A second miner in the same directory would overwrite this one's config, stats and block files
+    let _lock = match instance::InstanceLock::acquire() {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        },
+    };
+    let json_out = if events_mode {
+        match events::detach_stdout() {
+            Ok(out) => Some(out),
+            Err(e) => {
+                eprintln!("Could not start the event stream: {}", e);
+                return;
+            },
+        }
+    } else {
+        None
+    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match settings::MinerSettings::load_with_args(&args) {
         Ok(mut settings) => {
+            if settings.log_file.is_some() || settings.log_target.is_some() {
+                let file = settings.log_file.as_deref().map(|path| (path, logging::RotationPolicy {
+                    max_bytes: settings.log_max_bytes,
+                    max_age: settings.log_rotate_hours.map(|h| Duration::from_secs(h * 3600)),
+                    keep_files: settings.log_keep_files,
+                }));
+                let result = settings.log_target.as_deref().map(str::parse).transpose()
+                    .and_then(|system| logging::install(file, system));
+                if let Err(e) = result {
+                    eprintln!("Could not set up logging: {}", e);
+                }
+            }
             println!("\n--- Loaded Miner Settings ---");
-            println!("{:#?}", settings);
+            // Never echo passwords or the attestation key, even in the startup banner
+            println!("{:#?}", settings::MinerSettings {
+                rpc_pass: "[REDACTED]".to_string(),
+                attestation_key: settings.attestation_key.as_ref().map(|_| "[REDACTED]".to_string()),
+                extra_chains: settings.extra_chains.iter()
+                    .map(|chain| jobs::ExtraChain { rpc_pass: "[REDACTED]".to_string(), ..chain.clone() })
+                    .collect(),
+                ..settings.clone()
+            });
+            if let Err(e) = settings.ensure_reward_address() {
+                eprintln!("{}", e);
+                return;
+            }
+            match difficulty::tip_height(&settings) {
+                Ok(height) => {
+                    // Until the first template arrives, pay out exactly the subsidy of the next block
+                    settings.block_reward_sats = halving::subsidy_for_height_on(height + 1, settings.network());
+                    halving::print_countdown(height);
+                },
+                Err(e) => eprintln!("Could not fetch the chain height for the halving countdown: {}", e),
+            }
             println!("-----------------------------");
 
-            // Update settings with live data from the Bitcoin node
-            if let Err(e) = settings.update_from_node() {
-                eprintln!("Could not update settings from node: {}. Check RPC settings in miner_config.json.", e);
+            // kill -USR1 <pid> prints the performance counters
+            perf::install_dump_signal();
+            let stats = stats::StatsHandle::new(Duration::from_secs_f64(settings.hashrate_half_life_secs.max(0.001)));
+            if let Some(source) = power::PowerSource::from_settings(&settings) {
+                stats.set_power_source(source);
+            }
+            let events = events::EventBus::new();
+            let control = control::MinerControl::new();
+            if let Some(out) = json_out {
+                events::stream_json(&events, out);
+            }
+            #[cfg(feature = "notify")]
+            solo_lottery_miner::hooks::install(&events, settings.hooks.clone(), Duration::from_secs(settings.hook_timeout_secs));
+            #[cfg(not(feature = "notify"))]
+            if settings.hooks.is_configured() {
+                eprintln!("Ignoring hooks: rebuild with `--features notify` to run hook commands.");
+            }
+            if let Some(path) = &settings.script_path {
+                #[cfg(feature = "scripting")]
+                if let Err(e) = solo_lottery_miner::scripting::install(&events, &control, path, &settings.profiles) {
+                    eprintln!("{}", e);
+                    return;
+                }
+                #[cfg(not(feature = "scripting"))]
+                eprintln!("Ignoring script_path {}: rebuild with `--features scripting` to enable scripts.", path);
+            }
+            if watch_mode {
+                // No workers, GPUs or pools: nothing gets hashed
+                if let Err(e) = watch::run(&mut settings, &stats, &events) {
+                    eprintln!("Watch-only mode stopped: {}", e);
+                }
                 return;
             }
-            if let Err(e) = miner::mine_block(settings) {
-                eprintln!("A critical mining error occurred: {}", e);
+            #[cfg(target_os = "macos")]
+            let _awake = settings.prevent_sleep.then(|| solo_lottery_miner::keep_awake::KeepAwake::start(&control));
+            #[cfg(not(target_os = "macos"))]
+            if settings.prevent_sleep {
+                eprintln!("Ignoring prevent_sleep: only macOS is kept awake by the miner.");
+            }
+            let outside_bip320 = settings.version_rolling_mask & !pow::BIP320_VERSION_MASK;
+            if outside_bip320 != 0 {
+                eprintln!(
+                    "Ignoring version_rolling_mask bits {:08x}: BIP320 only sets aside {:08x} for miners.",
+                    outside_bip320, pow::BIP320_VERSION_MASK
+                );
+            }
+            if settings.coinbase_tag.len() > miner::MAX_COINBASE_TAG {
+                // Cut on a character boundary, so the tag stays valid UTF-8
+                let mut len = miner::MAX_COINBASE_TAG;
+                while !settings.coinbase_tag.is_char_boundary(len) {
+                    len -= 1;
+                }
+                eprintln!(
+                    "Truncating coinbase_tag from {} to {} bytes: only {} are left in the 100-byte scriptSig after the height and extranonce.",
+                    settings.coinbase_tag.len(), len, miner::MAX_COINBASE_TAG
+                );
+                settings.coinbase_tag.truncate(len);
+            }
+            if let Some(message) = &settings.op_return
+                && let Err(e) = miner::parse_op_return(message)
+            {
+                eprintln!("Ignoring op_return: {}", e);
+                settings.op_return = None;
+            }
+            if let Err(e) = settings.nonce_start.check() {
+                eprintln!("Ignoring nonce_start: {}", e);
+                settings.nonce_start = Default::default();
+            }
+            if let Some(endpoint) = &settings.zmq_endpoint
+                && let Err(e) = zmq::parse_endpoint(endpoint)
+            {
+                eprintln!("Ignoring zmq_endpoint: {}", e);
+                settings.zmq_endpoint = None;
+            }
+            settings.hash_backend = settings.hash_backend.resolve();
+            let workers = worker::WorkerPool::start(&settings.workers, stats.clone());
+            let mut gpus = scheduler::gpu_devices(&settings);
+            let job_queue = match jobs::JobQueue::start(&settings, &control) {
+                Ok(job_queue) => job_queue,
+                Err(e) => {
+                    eprintln!("Could not start mining the extra chains: {}", e);
+                    return;
+                },
+            };
+            #[cfg(feature = "sv2")]
+            let mut declarator = match &settings.job_declaration {
+                Some(jd) => match solo_lottery_miner::job_declaration::JobDeclarator::connect(jd, &events, &stats) {
+                    Ok(declarator) => Some(declarator),
+                    Err(e) => {
+                        eprintln!("Could not set up Stratum V2 job declaration: {}", e);
+                        return;
+                    },
+                },
+                None => None,
+            };
+            #[cfg(not(feature = "sv2"))]
+            if settings.job_declaration.is_some() {
+                eprintln!("Ignoring job_declaration: rebuild with `--features sv2` to mine declared jobs for a pool.");
+            }
+            match difficulty::fetch_history(&settings) {
+                Ok(epochs) => difficulty::print_history(&epochs),
+                Err(e) => eprintln!("Could not fetch difficulty history: {}", e),
+            }
+            match difficulty::estimate_retarget(&settings) {
+                Ok(estimate) => {
+                    difficulty::print_retarget(&estimate);
+                    stats.update(|s| s.retarget = Some(estimate));
+                },
+                Err(e) => eprintln!("Could not estimate the next retarget: {}", e),
+            }
+            let mut failover = settings.failover_pool.clone().map(|pool| failover::Failover::new(pool, &events));
+            // Straight from the node, the next job is prepared while the current one is hashed
+            let prefetcher = if failover.is_none() && settings.job_declaration.is_none() {
+                match prefetch::Prefetcher::start(&settings) {
+                    Ok(prefetcher) => Some(prefetcher),
+                    Err(e) => {
+                        eprintln!("Could not start the template prefetcher: {}", e);
+                        return;
+                    },
+                }
+            } else {
+                None
+            };
+            loop {
+                // Update settings with live data from the Bitcoin node, or from the failover pool while it's down
+                if let Some(failover) = &mut failover {
+                    failover.refresh(&mut settings);
+                } else if let Some(prefetcher) = &prefetcher {
+                    match prefetcher.next_job() {
+                        Ok(job) => settings = job,
+                        Err(e) => {
+                            eprintln!("Could not update settings from node: {}. Check RPC settings in miner_config.json.", e);
+                            return;
+                        },
+                    }
+                } else if let Err(e) = reconnect::update_from_node(&mut settings) {
+                    eprintln!("Could not update settings from node: {}. Check RPC settings in miner_config.json.", e);
+                    return;
+                }
+                #[cfg(feature = "sv2")]
+                if let Some(declarator) = &mut declarator
+                    && settings.stratum_work.is_none()
+                    && let Err(e) = declarator.declare(&mut settings)
+                {
+                    eprintln!("Could not declare the job to the pool: {}", e);
+                    return;
+                }
+                match miner::mine_block(&settings, &stats, &events, &control, &workers, &mut gpus) {
+                    Ok(miner::MiningOutcome::Stopped) => break,
+                    // A reorg, new tip or refresh dropped the job, or the pool found the block:
+                    // the next job is new work already
+                    Ok(miner::MiningOutcome::Abandoned | miner::MiningOutcome::SubmittedToPool { .. }) => {},
+                    // A block or an exhausted job: the template is done with
+                    Ok(outcome) => {
+                        if let Some(prefetcher) = &prefetcher {
+                            prefetcher.refresh();
+                        }
+                        // Tell the user whether the block survives or gets orphaned, while mining on
+                        if let miner::MiningOutcome::BlockFound { hash, accepted: true } = outcome {
+                            let settings = settings.clone();
+                            std::thread::spawn(move || {
+                                if let Err(e) = chain::monitor_submitted_block(&settings, &hash) {
+                                    eprintln!("Could not monitor submitted block {}: {}", hash, e);
+                                }
+                            });
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("A mining error occurred: {}. Starting over with a fresh template in {}s.", e, MINING_RETRY_DELAY.as_secs());
+                        std::thread::sleep(MINING_RETRY_DELAY);
+                        if let Some(prefetcher) = &prefetcher {
+                            prefetcher.refresh();
+                        }
+                    },
+                }
+                // Saved after every job, so the totals survive the process being killed
+                if let Err(e) = stats.save_lifetime() {
+                    eprintln!("Could not save lifetime stats: {}", e);
+                }
+            }
+            if let Err(e) = stats.save_lifetime() {
+                eprintln!("Could not save lifetime stats: {}", e);
+            }
+            for (name, stats) in job_queue.lanes() {
+                if let Err(e) = stats.save_lifetime() {
+                    eprintln!("Could not save lifetime stats for {}: {}", name, e);
+                }
             }
         },
         Err(e) => eprintln!("Failed to load or save configuration: {}", e),
     }
-}
\ No newline at end of file
+}
+
+/// Traces the header given in hex, or the nonce-0 header of the node's current template.
+fn trace_hash(args: &[String]) -> std::io::Result<()> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    let show_rounds = args.iter().any(|arg| arg == "--rounds");
+    let header = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(hex_header) => {
+            let mut header = [0u8; 80];
+            hex::decode_to_slice(hex_header.trim(), &mut header)
+                .map_err(|e| invalid(format!("expected an 80-byte header in hex: {}", e)))?;
+            header
+        },
+        None => {
+            let mut settings = settings::MinerSettings::load()?;
+            settings.update_from_node()?;
+            let (merkle_root, _) = miner::assemble_job(&settings)?;
+            let mut prev_hash = [0u8; 32];
+            hex::decode_to_slice(&settings.prev_block_hash, &mut prev_hash)
+                .map_err(|e| invalid(format!("Invalid prev_block_hash: {}", e)))?;
+            prev_hash.reverse();
+            pow::build_header(settings.version, &prev_hash, &merkle_root, settings.timestamp, settings.nbits, 0)
+        },
+    };
+    trace::print_trace(&header, show_rounds);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! Merkle trees over a block's transactions: the root committed to in the header, the
+//! witness root committed to in the coinbase (BIP141), and the coinbase branch a pool
+//! job carries instead of the transactions.
+//!
+//! All hashes are in internal (little-endian) byte order. A level with an odd number
+//! of hashes pairs its last hash with itself, as consensus requires.
+
+use crate::pow::sha256d;
+use bitcoin::hashes::Hash;
+use std::io;
+
+/// The root of the tree over `leaves`, or `None` without any.
+pub fn root(leaves: impl IntoIterator<Item = [u8; 32]>) -> Option<[u8; 32]> {
+    let mut level: Vec<[u8; 32]> = leaves.into_iter().collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.first().copied()
+}
+
+/// The merkle root of a block's transactions, coinbase first: the header's merkle root.
+pub fn txid_root(txdata: &[bitcoin::Transaction]) -> io::Result<[u8; 32]> {
+    root(txdata.iter().map(|tx| tx.compute_txid().to_byte_array()))
+        .ok_or_else(|| io::Error::other("Cannot compute the merkle root of an empty block"))
+}
+
+/// The witness root of a block's transactions (BIP141). The coinbase's wtxid is
+/// defined as all zeros.
+pub fn witness_root(txdata: &[bitcoin::Transaction]) -> io::Result<[u8; 32]> {
+    let coinbase = txdata.first().map(|_| [0u8; 32]);
+    let wtxids = txdata.iter().skip(1).map(|tx| tx.compute_wtxid().to_byte_array());
+    root(coinbase.into_iter().chain(wtxids))
+        .ok_or_else(|| io::Error::other("Cannot compute witness root of an empty block"))
+}
+
+/// The hashes to combine with the coinbase txid to reach the merkle root.
+pub fn coinbase_branch(txdata: &[bitcoin::Transaction]) -> Vec<[u8; 32]> {
+    let mut level: Vec<[u8; 32]> = txdata.iter().map(|tx| tx.compute_txid().to_byte_array()).collect();
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        branch.push(level[1]);
+        level = next_level(&level);
+    }
+    branch
+}
+
+/// The merkle root reached from the coinbase txid `leaf` along `branch`.
+pub fn root_from_branch(leaf: [u8; 32], branch: &[[u8; 32]]) -> [u8; 32] {
+    branch.iter().fold(leaf, |root, hash| sha256d(&[root, *hash].concat()))
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level.chunks(2)
+        .map(|pair| sha256d(&[pair[0], *pair.last().unwrap()].concat()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+
+    /// `count` distinct transactions, each with a witness so txid and wtxid differ.
+    fn transactions(count: u32) -> Vec<Transaction> {
+        (0..count).map(|i| Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(i),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(&[[i as u8; 32]]),
+            }],
+            output: vec![TxOut { value: Amount::from_sat(u64::from(i)), script_pubkey: ScriptBuf::new() }],
+        }).collect()
+    }
+
+    #[test]
+    fn roots_match_the_bitcoin_crate() {
+        for count in 1..=9 {
+            let txdata = transactions(count);
+            let txids = txdata.iter().map(|tx| tx.compute_txid());
+            let expected = bitcoin::merkle_tree::calculate_root(txids).unwrap();
+            assert_eq!(txid_root(&txdata).unwrap(), expected.to_byte_array(), "{} transactions", count);
+
+            let block = bitcoin::Block { header: bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header, txdata };
+            assert_eq!(witness_root(&block.txdata).unwrap(), block.witness_root().unwrap().to_byte_array());
+        }
+    }
+
+    #[test]
+    fn a_single_transaction_is_its_own_root() {
+        let txdata = transactions(1);
+        assert_eq!(txid_root(&txdata).unwrap(), txdata[0].compute_txid().to_byte_array());
+        assert_eq!(witness_root(&txdata).unwrap(), [0u8; 32]);
+        assert!(coinbase_branch(&txdata).is_empty());
+    }
+
+    #[test]
+    fn the_coinbase_branch_leads_to_the_root() {
+        for count in 1..=9 {
+            let txdata = transactions(count);
+            let branch = coinbase_branch(&txdata);
+            let root = root_from_branch(txdata[0].compute_txid().to_byte_array(), &branch);
+            assert_eq!(root, txid_root(&txdata).unwrap(), "{} transactions", count);
+        }
+    }
+
+    #[test]
+    fn empty_blocks_have_no_root() {
+        assert_eq!(root(std::iter::empty()), None);
+        assert!(txid_root(&[]).is_err());
+        assert!(witness_root(&[]).is_err());
+    }
+}
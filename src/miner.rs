@@ -1,273 +1,936 @@
-use crate::settings::MinerSettings;
-use std::{io, str::FromStr, time};
-use std::io::Write;
+use crate::assembly::{self, AssembledBlock, BlockLimits};
+use crate::merkle;
+use crate::template::TemplateTransaction;
+use crate::units::format_network;
+pub use crate::pow::{build_header, compact_to_target, sha256d};
+use std::{io, str::FromStr};
 use bitcoin::Network;
-use bitcoin::consensus::Encodable;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "rpc")]
 use bitcoin::hashes::Hash;
-use sha2::{Digest, Sha256};
-use byteorder::{LittleEndian, WriteBytesExt};
-use hex;
+#[cfg(feature = "rpc")]
+use crate::{attestation, chain, difficulty, halving, perf, pow, stats, submit, units, wallet};
+#[cfg(feature = "rpc")]
+use crate::audit::TemplateSnapshot;
+#[cfg(feature = "rpc")]
+use crate::control::MinerControl;
+#[cfg(feature = "rpc")]
+use crate::hasher::{self, HashBackend, Hasher};
+#[cfg(feature = "rpc")]
+use crate::events::{EventBus, MinerEvent};
+#[cfg(feature = "rpc")]
+use crate::stats::{JobInfo, StatsHandle};
+#[cfg(feature = "rpc")]
+use crate::settings::MinerSettings;
+#[cfg(feature = "rpc")]
+use crate::scheduler::{CpuDevice, Device, NonceStart, Scheduler};
+#[cfg(feature = "rpc")]
+use crate::worker::WorkerPool;
+#[cfg(feature = "rpc")]
+use std::ops::RangeInclusive;
+#[cfg(feature = "rpc")]
+use std::sync::{Arc, mpsc};
+#[cfg(feature = "rpc")]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "rpc")]
+use std::{thread, time};
+#[cfg(feature = "rpc")]
+use bitcoin::consensus::Encodable;
+#[cfg(feature = "rpc")]
+use bitcoin::pow::Target;
+
+/// Most bytes an OP_RETURN output may carry and still be relayed by default.
+const MAX_OP_RETURN_DATA: usize = 80;
+
+/// How often the supervising thread merges hash counts and checks workers and the abandon flag.
+#[cfg(feature = "rpc")]
+const SUPERVISOR_TICK: time::Duration = time::Duration::from_millis(100);
+
+/// Local hashes between two status reports.
+#[cfg(feature = "rpc")]
+const STATUS_INTERVAL_HASHES: u64 = 1 << 20;
+
+/// Consensus bounds on the length of the coinbase scriptSig, in bytes.
+pub(crate) const COINBASE_SCRIPT_SIG_MIN: usize = 2;
+pub(crate) const COINBASE_SCRIPT_SIG_MAX: usize = 100;
+
+/// Longest `coinbase_tag` that always fits in full: what the scriptSig limit leaves after
+/// the BIP34 height push (up to 5 bytes), the largest solo extranonce push (13 bytes)
+/// and the tag's own push opcodes (2 bytes).
+pub const MAX_COINBASE_TAG: usize = COINBASE_SCRIPT_SIG_MAX - 5 - 13 - 2;
+
+/// Assembles the coinbase scriptSig as pushes of [BIP34 height] [extranonce] [tag], after
+/// `prefix` (a scriptSig handed to us to extend, or nothing).
+/// The tag is truncated (with a warning) when the script would exceed the consensus
+/// maximum, and the script is padded when it would be shorter than the minimum,
+/// so no user configuration can produce an invalid coinbase.
+fn build_coinbase_script_sig(prefix: &[u8], height: Option<u64>, extranonce: &[u8], tag: &[u8]) -> io::Result<bitcoin::ScriptBuf> {
+    let to_push = |data: &[u8]| {
+        <&bitcoin::script::PushBytes>::try_from(data)
+            .map(|p| p.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Coinbase push too large: {}", e)))
+    };
 
+    let mut builder = bitcoin::script::Builder::from(prefix.to_vec());
+    if let Some(height) = height {
+        builder = builder.push_int(height as i64);
+    }
+    if !extranonce.is_empty() {
+        builder = builder.push_slice(to_push(extranonce)?);
+    }
 
-const COINBASE_DATA: &[u8] = b"/solo-miner/rust-pi-edu/";
+    // Work out how much of the tag fits: a push of n <= 75 bytes costs 1 extra byte, otherwise 2.
+    let remaining = COINBASE_SCRIPT_SIG_MAX.saturating_sub(builder.len());
+    let mut tag_len = tag.len();
+    while tag_len > 0 && tag_len + if tag_len <= 75 { 1 } else { 2 } > remaining {
+        tag_len -= 1;
+    }
+    if tag_len < tag.len() {
+        println!(
+            "[Mining] Warning: coinbase tag truncated from {} to {} bytes to respect the {}-byte scriptSig limit.",
+            tag.len(), tag_len, COINBASE_SCRIPT_SIG_MAX
+        );
+    }
+    if tag_len > 0 {
+        builder = builder.push_slice(to_push(&tag[..tag_len])?);
+    }
 
-/// Helper function to perform Double SHA-256 (SHA-256d)
-pub fn sha256d(data: &[u8]) -> [u8; 32] {
-    let hash1 = Sha256::digest(data);
-    let hash2 = Sha256::digest(&hash1);
-    
-    // The hash is returned in big-endian order 
-    hash2.into()
+    // Pad with OP_0 pushes up to the consensus minimum.
+    while builder.len() < COINBASE_SCRIPT_SIG_MIN {
+        builder = builder.push_opcode(bitcoin::opcodes::OP_0);
+    }
+
+    let script_sig = builder.into_script();
+    if script_sig.len() > COINBASE_SCRIPT_SIG_MAX {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Coinbase scriptSig is {} bytes; the consensus limit is {}", script_sig.len(), COINBASE_SCRIPT_SIG_MAX
+        )));
+    }
+    Ok(script_sig)
 }
 
-/// Converts the compact difficulty (nBits) into the full 256-bit target hash.
-/// The mining hash must be LESS THAN this target.
-fn compact_to_target_old(nbits: u32) -> [u8; 32] {
-    let mut target = [0u8; 32];
-    let nbits_bytes = nbits.to_be_bytes();
-    
-    // The first byte is the exponent (size of the target in bytes)
-    let size = nbits_bytes[0]; 
-    // The next three bytes are the mantissa (significand)
-    let mantissa = u32::from_be_bytes([0, nbits_bytes[1], nbits_bytes[2], nbits_bytes[3]]);
-
-    if size > 3 {
-        // We write the mantissa bytes backwards (due to big-endian representation of the number)
-        for i in 0..3 {
-            let byte_index = (size as usize) - 1 - i;
-            if byte_index < 32 {
-                target[31 - byte_index] = (mantissa >> (i * 8)) as u8;
-            }
-        }
+/// The coinbase of a job mined for a pool, which pays the pool's outputs and carries
+/// its extranonce instead of paying `reward_address`.
+#[derive(Debug, Clone)]
+pub struct PoolCoinbase {
+    /// Start of the scriptSig, ahead of the extranonce (the BIP34 height push).
+    pub script_prefix: Vec<u8>,
+    /// The pool's extranonce prefix followed by the miner's own part, as raw bytes
+    /// ending the scriptSig.
+    pub extranonce: Vec<u8>,
+    /// Outputs to pay, with the whole block reward already assigned.
+    pub outputs: Vec<bitcoin::TxOut>,
+}
+
+/// A share of every block reward paid to another address than `reward_address`, e.g. a
+/// second wallet or a donation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardSplit {
+    pub address: String,
+    /// Percentage of the block reward, e.g. 1.5.
+    pub percent: f64,
+}
+
+/// Creates the Coinbase Transaction for the block at `height`, assembles it with the
+/// template transactions, and returns the Merkle Root (internal byte order) together
+/// with the assembled block.
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_merkle_root(
+    reward_address: &str,
+    reward_splits: &[RewardSplit],
+    op_returns: &[&[u8]],
+    tag: &[u8],
+    network: Network,
+    height: u64,
+    block_reward_sats: u64,
+    transactions: &[TemplateTransaction],
+    priority_txids: &[String],
+    limits: &BlockLimits,
+) -> io::Result<([u8; 32], AssembledBlock)> {
+    // 1. Decode the payout addresses into outputs sharing the reward
+    let mut outputs = payout_outputs(reward_address, reward_splits, network, block_reward_sats)?;
+    outputs.extend(op_returns.iter().map(|data| op_return_output(data)).collect::<io::Result<Vec<_>>>()?);
+
+    // 2. Build the Coinbase Transaction, its scriptSig starting with the height (BIP34)
+    let script_sig = build_coinbase_script_sig(&[], Some(height), &[], tag)?;
+    let tx = coinbase_transaction(script_sig, outputs);
+
+    assemble_with_merkle_root(tx, transactions, priority_txids, limits)
+}
+
+/// Like `calculate_merkle_root`, for a template that brings its own coinbase (BIP22
+/// `coinbasetxn`): `extranonce` and `tag` are appended to its scriptSig, and whatever
+/// of `block_reward_sats` its outputs leave unclaimed is shared among our payouts.
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_template_merkle_root(
+    coinbase: &bitcoin::Transaction,
+    extranonce: &[u8],
+    reward_address: &str,
+    reward_splits: &[RewardSplit],
+    op_returns: &[&[u8]],
+    tag: &[u8],
+    network: Network,
+    block_reward_sats: u64,
+    transactions: &[TemplateTransaction],
+    priority_txids: &[String],
+    limits: &BlockLimits,
+) -> io::Result<([u8; 32], AssembledBlock)> {
+    let mut tx = coinbase.clone();
+    let input = tx.input.first_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The template's coinbase has no input"))?;
+    input.script_sig = build_coinbase_script_sig(input.script_sig.as_bytes(), None, extranonce, tag)?;
+    let claimed: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+    if block_reward_sats > claimed {
+        tx.output.extend(payout_outputs(reward_address, reward_splits, network, block_reward_sats - claimed)?);
     }
-    target
+    tx.output.extend(op_returns.iter().map(|data| op_return_output(data)).collect::<io::Result<Vec<_>>>()?);
+    assemble_with_merkle_root(tx, transactions, priority_txids, limits)
 }
 
-/// Converts compact nBits to a 32-byte big-endian target.
-fn compact_to_target(nbits: u32) -> [u8; 32] {
-    let mut target = [0u8; 32];
-    let exponent = (nbits >> 24) as i32;
-    // Mask off the sign bit for standard mantissa representation
-    let mut mantissa = nbits & 0x007fffff; 
-
-    if exponent <= 3 {
-        // Handle targets smaller than 4 bytes (shift the mantissa right)
-        let shift = 8 * (3 - exponent);
-        mantissa >>= shift as u32;
-        // Place the 4 bytes of the (padded) mantissa at the end
-        target[28..32].copy_from_slice(&mantissa.to_be_bytes());
-    } else {
-        // Handle targets larger than 4 bytes (place mantissa at the appropriate offset)
-        let byte_pos = (32 - (exponent as usize)) as isize;
-        // mantissa as 3 bytes big-endian
-        let mant_bytes = [(mantissa >> 16) as u8, (mantissa >> 8) as u8, mantissa as u8];
-        if byte_pos >= 0 && (byte_pos as usize) + 3 <= 32 {
-            target[(byte_pos as usize)..(byte_pos as usize + 3)].copy_from_slice(&mant_bytes);
+/// Outputs paying `amount_sats`: each split its percentage, rounded down, and
+/// `reward_address` the rest, so they always sum to exactly `amount_sats`.
+fn payout_outputs(reward_address: &str, reward_splits: &[RewardSplit], network: Network, amount_sats: u64) -> io::Result<Vec<bitcoin::TxOut>> {
+    let mut outputs = Vec::new();
+    let mut remaining = amount_sats;
+    for split in reward_splits {
+        let value = ((amount_sats as f64 * split.percent / 100.0).floor() as u64).min(remaining);
+        if value > 0 {
+            remaining -= value;
+            outputs.push(bitcoin::TxOut { value: bitcoin::Amount::from_sat(value), script_pubkey: payout_script(&split.address, network)? });
         }
     }
+    // Even with nothing left over, the block still pays somewhere
+    if remaining > 0 || outputs.is_empty() {
+        outputs.insert(0, bitcoin::TxOut { value: bitcoin::Amount::from_sat(remaining), script_pubkey: payout_script(reward_address, network)? });
+    }
+    Ok(outputs)
+}
+
+/// The bytes an `op_return` setting embeds: its text, or the bytes of a "hex:..." value.
+pub fn parse_op_return(message: &str) -> Result<Vec<u8>, String> {
+    let data = match message.strip_prefix("hex:") {
+        Some(digits) => hex::decode(digits).map_err(|e| format!("'{}' is not valid hex: {}", digits, e))?,
+        None => message.as_bytes().to_vec(),
+    };
+    if data.len() > MAX_OP_RETURN_DATA {
+        return Err(format!("{} bytes is more than the {} nodes relay by default", data.len(), MAX_OP_RETURN_DATA));
+    }
+    // The block's witness commitment is found by this prefix
+    if data.starts_with(&[0xaa, 0x21, 0xa9, 0xed]) {
+        return Err("data starting with aa21a9ed would pass for the witness commitment".to_string());
+    }
+    Ok(data)
+}
 
-    // This target is now in BIG-ENDIAN order
-    target 
+/// An unspendable, zero-value output carrying `data`.
+fn op_return_output(data: &[u8]) -> io::Result<bitcoin::TxOut> {
+    let data = bitcoin::script::PushBytesBuf::try_from(data.to_vec())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("OP_RETURN data: {}", e)))?;
+    Ok(bitcoin::TxOut { value: bitcoin::Amount::ZERO, script_pubkey: bitcoin::ScriptBuf::new_op_return(data) })
 }
 
-/// Creates a minimal Coinbase Transaction and returns its double SHA-256 hash,
-/// which serves as the Merkle Root for a block containing only this transaction.
-fn calculate_merkle_root(reward_address: &str, block_reward_sats: u64) -> io::Result<([u8; 32], bitcoin::Transaction)> {
-    // 1. Decode the reward address to get the scriptPubKey
+/// The scriptPubKey paying `reward_address`, which must be an address on `network`.
+fn payout_script(reward_address: &str, network: Network) -> io::Result<bitcoin::ScriptBuf> {
     // First, parse the string into an unchecked address.
     let address = bitcoin::Address::from_str(reward_address)
-        // Then, require that the address is valid for the Bitcoin main network.
-        .and_then(|addr| addr.require_network(Network::Bitcoin))
+        // Then, require that the address is valid for the node's network.
+        .and_then(|addr| addr.require_network(network))
         .map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid or non-mainnet Bitcoin address: {}", e))
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid Bitcoin address for {}: {}", format_network(network), e))
         })?;
-    let script_pubkey = address.script_pubkey(); // Now we use the network-checked address.
+    Ok(address.script_pubkey())
+}
+
+/// Like `calculate_merkle_root`, for a job mined for a pool.
+pub fn calculate_pool_merkle_root(
+    pool: &PoolCoinbase,
+    transactions: &[TemplateTransaction],
+    priority_txids: &[String],
+    limits: &BlockLimits,
+) -> io::Result<([u8; 32], AssembledBlock)> {
+    let script_sig = [pool.script_prefix.as_slice(), &pool.extranonce].concat();
+    if !(COINBASE_SCRIPT_SIG_MIN..=COINBASE_SCRIPT_SIG_MAX).contains(&script_sig.len()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "Pool coinbase scriptSig is {} bytes; consensus allows {} to {}", script_sig.len(), COINBASE_SCRIPT_SIG_MIN, COINBASE_SCRIPT_SIG_MAX
+        )));
+    }
+    let tx = coinbase_transaction(bitcoin::ScriptBuf::from_bytes(script_sig), pool.outputs.clone());
+    assemble_with_merkle_root(tx, transactions, priority_txids, limits)
+}
 
-    // 2. Build the Coinbase Transaction (minimal, non-standard)
-    let tx = bitcoin::Transaction {
+fn coinbase_transaction(script_sig: bitcoin::ScriptBuf, output: Vec<bitcoin::TxOut>) -> bitcoin::Transaction {
+    bitcoin::Transaction {
         version: bitcoin::transaction::Version::ONE,
         lock_time: bitcoin::absolute::LockTime::ZERO,
         input: vec![
             bitcoin::TxIn {
                 previous_output: bitcoin::OutPoint::null(),
-                script_sig: bitcoin::ScriptBuf::from_bytes(COINBASE_DATA.to_vec()),
+                script_sig,
                 sequence: bitcoin::transaction::Sequence::MAX,
                 witness: bitcoin::Witness::new(), 
             },
         ],
-        output: vec![
-            bitcoin::TxOut {
-                value: bitcoin::Amount::from_sat(block_reward_sats),
-                script_pubkey: script_pubkey,
-            },
-        ],
+        output,
+    }
+}
+
+fn assemble_with_merkle_root(
+    coinbase: bitcoin::Transaction,
+    transactions: &[TemplateTransaction],
+    priority_txids: &[String],
+    limits: &BlockLimits,
+) -> io::Result<([u8; 32], AssembledBlock)> {
+    // 3. Assemble the block, keeping it within the template's weight, sigops and size limits
+    let block = assembly::assemble_block(coinbase, transactions, priority_txids, limits)?;
+
+    // 4. Hash the transaction ids pairwise up to the Merkle Root
+    let merkle_root_hash = merkle::txid_root(&block.txdata)?;
+
+    Ok((merkle_root_hash, block))
+}
+
+/// How a call to `mine_block` ended.
+pub enum MiningOutcome {
+    /// A block was found. `accepted` tells whether the node took it.
+    BlockFound { hash: String, accepted: bool },
+    /// The nonce space of this template was searched without success.
+    Exhausted,
+    /// The job was dropped because the chain reorganized or the pool replaced it; fetch new work.
+    Abandoned,
+    /// A block was found on work from the failover pool, which assembles and broadcasts it.
+    SubmittedToPool { hash: String },
+    /// Mining was stopped through the controls.
+    Stopped,
+}
+
+/// Assembles the 80-byte block header and starts the high-speed hashing loop.
+/// Progress is published to `stats` and `events`; unrecoverable errors are also
+/// published as `MinerEvent::Error` before being returned.
+#[cfg(feature = "rpc")]
+pub fn mine_block(
+    settings: &MinerSettings,
+    stats: &StatsHandle,
+    events: &EventBus,
+    control: &MinerControl,
+    workers: &WorkerPool,
+    gpus: &mut [Box<dyn Device>],
+) -> io::Result<MiningOutcome> {
+    // Dispatch once to a loop monomorphised for the configured SHA-256 implementation
+    let result = match settings.hash_backend {
+        // Left to the default by a caller that didn't resolve it: sha2 is always built in
+        HashBackend::Auto | HashBackend::Sha2 => mine_job::<hasher::Sha2>(settings, stats, events, control, workers, gpus),
+        HashBackend::Simd => mine_job::<hasher::Simd>(settings, stats, events, control, workers, gpus),
+        #[cfg(feature = "ring")]
+        HashBackend::Ring => mine_job::<hasher::Ring>(settings, stats, events, control, workers, gpus),
+        #[cfg(feature = "openssl")]
+        HashBackend::Openssl => mine_job::<hasher::Openssl>(settings, stats, events, control, workers, gpus),
+        #[allow(unreachable_patterns)]
+        backend => Err(backend.unavailable()),
     };
+    if let Err(e) = &result {
+        events.publish(MinerEvent::Error { message: e.to_string() });
+    }
+    result
+}
 
-    // 3. Serialize and Double Hash the transaction to get the Merkle Root
-    let mut serialized_tx = Vec::new();
-    tx.consensus_encode(&mut serialized_tx).map_err(|e| {
-         io::Error::new(io::ErrorKind::Other, format!("Failed to serialize tx: {}", e))
+/// Builds the coinbase for the template in `settings` and assembles the block, returning
+/// its merkle root (internal byte order) and the block.
+#[cfg(feature = "rpc")]
+pub fn assemble_job(settings: &MinerSettings) -> io::Result<([u8; 32], AssembledBlock)> {
+    if let Some(job) = &settings.prepared {
+        return Ok((job.merkle_root, job.block.clone()));
+    }
+    let op_return = settings.op_return.as_deref().map(parse_op_return).transpose()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("op_return: {}", e)))?;
+    // Shares can only be attested by the key the coinbase names
+    let attestation = settings.attestation_key.as_deref().map(attestation::coinbase_commitment).transpose()?;
+    let op_returns: Vec<&[u8]> = op_return.iter().chain(attestation.iter()).map(Vec::as_slice).collect();
+    let (merkle_root_bytes, block) = perf::time(perf::Section::MerkleRoot, || match (&settings.pool_coinbase, &settings.template_coinbase) {
+        (Some(pool), _) => calculate_pool_merkle_root(pool, &settings.transactions, &settings.priority_txids, &settings.block_limits),
+        (None, Some(coinbase)) => calculate_template_merkle_root(
+            coinbase,
+            &solo_extranonce(settings, 0),
+            &settings.reward_address,
+            &settings.reward_splits,
+            &op_returns,
+            settings.coinbase_tag.as_bytes(),
+            settings.network(),
+            settings.block_reward_sats,
+            &settings.transactions,
+            &settings.priority_txids,
+            &settings.block_limits,
+        ),
+        (None, None) => calculate_merkle_root(
+            &settings.reward_address,
+            &settings.reward_splits,
+            &op_returns,
+            settings.coinbase_tag.as_bytes(),
+            settings.network(),
+            settings.height,
+            settings.block_reward_sats,
+            &settings.transactions,
+            &settings.priority_txids,
+            &settings.block_limits,
+        ),
     })?;
+    if block.txdata.len() <= settings.transactions.len() && !settings.mutations.transactions {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            "The block only fits the weight, sigops and size limits without some template transactions, but the server doesn't allow removing any (no \"transactions\" in mutable)"));
+    }
+    println!(
+        "[Mining] Block assembled: {} transaction(s), {} sats in fees, weight {}/{} WU, sigops cost {}/{}, {} bytes.",
+        block.txdata.len(), block.fees, block.weight, settings.block_limits.weight,
+        block.sigops_cost, settings.block_limits.sigops_cost, block.size
+    );
+    // Let the network's own script interpreter catch assembly mistakes before any work is spent
+    #[cfg(feature = "consensus-check")]
+    crate::validation::check_block(settings, &block, &merkle_root_bytes)?;
+    Ok((merkle_root_bytes, block))
+}
 
-    let merkle_root_hash = sha256d(&serialized_tx);
+/// Checks, before any hashing, that the coinbase of `block` claims no more than the
+/// subsidy at the template's height plus the fees of the included transactions; a block
+/// paying more is consensus-invalid.
+#[cfg(feature = "rpc")]
+fn check_coinbase_value(settings: &MinerSettings, block: &AssembledBlock) -> io::Result<()> {
+    let subsidy = halving::subsidy_for_height_on(settings.height, settings.network());
+    let claimed: u64 = block.txdata.first().map_or(0, |coinbase| coinbase.output.iter().map(|output| output.value.to_sat()).sum());
+    let allowed = subsidy + block.fees;
+    if claimed > allowed {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "The coinbase pays {} sats, more than the {} sats of subsidy plus {} sats of fees allowed at height {}; the network would reject the block",
+            claimed, subsidy, block.fees, settings.height
+        )));
+    }
+    Ok(())
+}
 
-    Ok((merkle_root_hash, tx))
+/// Checks, before any hashing, that the coinbase scriptSig of `block` is 2 to 100 bytes
+/// and starts with the template's height (BIP34), and that a scriptSig built entirely by
+/// us (height, extranonce, tag) uses minimal pushes only. A pool's extranonce or a
+/// server's scriptSig may hold raw bytes, which consensus allows.
+#[cfg(feature = "rpc")]
+fn check_coinbase_script_sig(settings: &MinerSettings, block: &AssembledBlock) -> io::Result<()> {
+    let invalid = |problem: String| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid coinbase scriptSig: {}", problem));
+    let script_sig = &block.txdata.first()
+        .and_then(|coinbase| coinbase.input.first())
+        .ok_or_else(|| invalid("the assembled block has no coinbase input".to_string()))?
+        .script_sig;
+    if !(COINBASE_SCRIPT_SIG_MIN..=COINBASE_SCRIPT_SIG_MAX).contains(&script_sig.len()) {
+        return Err(invalid(format!(
+            "{} bytes; consensus allows {} to {}", script_sig.len(), COINBASE_SCRIPT_SIG_MIN, COINBASE_SCRIPT_SIG_MAX
+        )));
+    }
+    let height = bitcoin::script::Builder::new().push_int(settings.height as i64).into_script();
+    if !script_sig.as_bytes().starts_with(height.as_bytes()) {
+        return Err(invalid(format!("it doesn't start with the height {} (BIP34)", settings.height)));
+    }
+    if settings.pool_coinbase.is_none() && settings.template_coinbase.is_none()
+        && let Some(Err(e)) = script_sig.instructions_minimal().find(|instruction| instruction.is_err())
+    {
+        return Err(invalid(format!("{} in {}", e, script_sig.to_hex_string())));
+    }
+    Ok(())
 }
 
-/// Assembles the 80-byte block header and starts the high-speed hashing loop.
-pub fn mine_block(settings: MinerSettings) -> io::Result<()> {
-    println!("\n[Mining] Initializing Block...");
-    
-    // Convert hex strings to byte arrays
-    let mut prev_hash_bytes = hex::decode(&settings.prev_block_hash).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    prev_hash_bytes.reverse();
-    let (mut merkle_root_bytes, coinbase_tx) = calculate_merkle_root(&settings.reward_address, settings.block_reward_sats)?;
-    merkle_root_bytes.reverse();
-    let mut target = compact_to_target(settings.nbits);
+/// The extranonce of a solo job's coinbase. A template's own coinbase gets the template
+/// time and process id, unique among miners sharing the server's coinbase. After the
+/// nonce space has run out `rolls` times, the roll count follows.
+#[cfg(feature = "rpc")]
+fn solo_extranonce(settings: &MinerSettings, rolls: u32) -> Vec<u8> {
+    let mut extranonce = Vec::new();
+    if settings.template_coinbase.is_some() {
+        extranonce.extend_from_slice(&settings.timestamp.to_le_bytes());
+        extranonce.extend_from_slice(&std::process::id().to_le_bytes());
+    }
+    if rolls > 0 {
+        extranonce.extend_from_slice(&rolls.to_le_bytes());
+    }
+    extranonce
+}
 
-    target.reverse();
+/// Rewrites the coinbase scriptSig of a solo job's `block` with the extranonce for
+/// `rolls`, keeping its outputs and the rest of the block, and returns the new merkle
+/// root (internal byte order). Fails if the longer coinbase takes the block over the
+/// weight or size limit.
+#[cfg(feature = "rpc")]
+fn roll_extranonce(settings: &MinerSettings, block: &mut AssembledBlock, rolls: u32) -> io::Result<[u8; 32]> {
+    let prefix = settings.template_coinbase.as_ref()
+        .and_then(|coinbase| coinbase.input.first())
+        .map_or(Vec::new(), |input| input.script_sig.to_bytes());
+    // A template's own coinbase already starts with the height
+    let height = settings.template_coinbase.is_none().then_some(settings.height);
+    let script_sig = build_coinbase_script_sig(&prefix, height, &solo_extranonce(settings, rolls), settings.coinbase_tag.as_bytes())?;
+    let coinbase = block.txdata.first_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The assembled block has no coinbase"))?;
+    let (old_weight, old_size) = (coinbase.weight().to_wu(), coinbase.total_size() as u64);
+    coinbase.input[0].script_sig = script_sig;
+    let weight = block.weight - old_weight + coinbase.weight().to_wu();
+    if weight > settings.block_limits.weight {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "A rolled extranonce would take the block to weight {}, over the {} limit", weight, settings.block_limits.weight
+        )));
+    }
+    let size = block.size - old_size + coinbase.total_size() as u64;
+    if size > settings.block_limits.size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "A rolled extranonce would take the block to {} bytes, over the {}-byte limit", size, settings.block_limits.size
+        )));
+    }
+    block.size = size;
+    block.weight = weight;
+    merkle::txid_root(&block.txdata)
+}
 
-    let mut nonce: u32 = 0;
-    let mut hash_rate_start = time::Instant::now();
-    let mut hash_count: u64 = 0;
+#[cfg(feature = "rpc")]
+fn mine_job<H: Hasher + 'static>(
+    settings: &MinerSettings,
+    stats: &StatsHandle,
+    events: &EventBus,
+    control: &MinerControl,
+    workers: &WorkerPool,
+    gpus: &mut [Box<dyn Device>],
+) -> io::Result<MiningOutcome> {
+    // A profile a script switched to sets the main chain's threads; lanes keep their own
+    let cpu_threads = match control.profile_cpu_threads().filter(|_| settings.lane.is_none()).unwrap_or(settings.cpu_threads) {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let lane = settings.lane.as_ref().map_or(String::new(), |name| format!(" for {}", name));
+    println!(
+        "\n[Mining] Initializing Block{}, job {} (hashing with {} on {} CPU thread(s))...",
+        lane, settings.job_id, H::BACKEND.name(), cpu_threads
+    );
+
+    // Watch the chain in the background so a new block or a reorg stops us wasting work on a stale tip.
+    // Pool work has no node to watch; the pool client flags it stale instead
+    // A prefetched job is also dropped once the prefetcher has the next one ready
+    let abandon = match (&settings.stratum_work, &settings.prepared) {
+        (Some(work), _) => Arc::clone(&work.stale),
+        (None, Some(job)) => Arc::clone(&job.superseded),
+        (None, None) => Arc::new(AtomicBool::new(false)),
+    };
 
-    println!("[Mining] Target Hash (Little Endian): {}", hex::encode(&target));
 
+    // Convert hex strings to byte arrays
+    let mut prev_hash_bytes = [0u8; 32];
+    hex::decode_to_slice(&settings.prev_block_hash, &mut prev_hash_bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid prev_block_hash: {}", e)))?;
+    prev_hash_bytes.reverse();
+    let (mut merkle_root_bytes, mut block) = match &settings.stratum_work {
+        // The pool holds the transactions; we only need the root of its merkle branch
+        Some(work) => {
+            println!("[Mining] Working on job {} from pool {}.", work.job_id, work.pool);
+            (work.merkle_root(), None)
+        },
+        None => {
+            let (merkle_root_bytes, block) = assemble_job(settings)?;
+            check_coinbase_value(settings, &block)?;
+            check_coinbase_script_sig(settings, &block)?;
+            (merkle_root_bytes, Some(block))
+        },
+    };
+    // Started from the template's tip, so a reorg during assembly is still caught
+    let _tip_watcher = settings.stratum_work.is_none().then(|| {
+        let audit = block.as_ref().filter(|_| settings.audit_blocks).map(|block| TemplateSnapshot::new(settings, block));
+        chain::TipWatcher::spawn(settings, Arc::clone(&abandon), audit)
+    });
+    let (transactions, fees_sats) = block.as_ref().map_or((0, 0), |block| (block.txdata.len(), block.fees));
+    stats.update(|s| s.job = Some(JobInfo {
+        job_id: settings.job_id.clone(),
+        prev_block_hash: settings.prev_block_hash.clone(),
+        nbits: settings.nbits,
+        transactions,
+        fees_sats,
+        started_at: stats::unix_now(),
+    }));
+    events.publish(MinerEvent::TemplateReceived {
+        job_id: settings.job_id.clone(),
+        prev_block_hash: settings.prev_block_hash.clone(),
+        nbits: settings.nbits,
+        transactions,
+        fees_sats,
+    });
+    let target = settings.block_target().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("The template's nBits {:08x} is not a valid compact target", settings.nbits))
+    })?;
+    // One scan stops at shares and blocks alike, so it runs against the easier of the
+    // two targets (on regtest the block target is above difficulty 1)
+    let scan_target = pow::DIFF1_TARGET.max(target);
+
+    println!("[Mining] Target: {}", hex::encode(target.to_be_bytes()));
+
+    // Version bits to roll (BIP320) once the nonce space runs out: those the pool lets us
+    // roll, or on solo jobs the configured ones, except any the template's version sets
+    // (deployment signals, required bits). A declared job's version is the pool's
+    let version_mask = match (&settings.stratum_work, &settings.pool_coinbase) {
+        (Some(work), _) => work.version_mask,
+        (None, None) => settings.version_rolling_mask & !settings.version,
+        (None, Some(_)) => 0,
+    } & pow::BIP320_VERSION_MASK;
+    let mut cpus: Vec<Box<dyn Device>> = (0..cpu_threads)
+        .map(|i| Box::new(CpuDevice::<H>::new(i)) as Box<dyn Device>)
+        .collect();
+    let mut extranonce_rolls = 0;
+    // A template from the node is refreshed every so often for new transactions
+    let refresh_at = (settings.stratum_work.is_none() && settings.template_refresh_secs > 0)
+        .then(|| time::Instant::now() + time::Duration::from_secs(settings.template_refresh_secs));
+    let (nonces, first_nonce) = settings.nonce_start.plan(&settings.nonce_range);
+    if settings.nonce_start != NonceStart::Zero {
+        println!("[Mining] Searching nonces {:08x}..={:08x}, starting at {:08x}.", nonces.start(), nonces.end(), first_nonce);
+    }
     loop {
-        // 1. Construct the 80-byte Block Header
-        let mut block_header = [0u8; 80];
-        let mut cursor = io::Cursor::new(&mut block_header[..]);
-
-        // All values are written in Little-Endian byte order
-        cursor.write_u32::<LittleEndian>(settings.version)?;
-        cursor.write_all(&prev_hash_bytes)?; 
-        cursor.write_all(&merkle_root_bytes)?;
-        
-        let current_time = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs() as u32;
-        cursor.write_u32::<LittleEndian>(current_time)?;
-        
-        cursor.write_u32::<LittleEndian>(settings.nbits)?;
-        cursor.write_u32::<LittleEndian>(nonce)?; // The variable we are changing
-
-        // 2. Perform Double SHA-256
-        let mut block_hash = sha256d(&block_header);
-        hash_count += 1;
-        //it will be the Little-Endian protocol hash
-        block_hash.reverse();
-        // 3. Check Difficulty: Compare the hash against the target
-        if block_hash.lt(&target) {
-            println!("\n==============================================");
-            println!("🎉 BLOCK FOUND! (The Lottery is Won!)");
-            println!("Hash: {}", hex::encode(&block_hash));
-            println!("Nonce: {}", nonce);
-            println!("==============================================");
-
-            match serialize_block(
-                &settings, 
-                &prev_hash_bytes, 
-                &merkle_root_bytes, 
-                nonce, 
-                current_time,
-                &coinbase_tx 
-            ) {
-                Ok(block_hex) => {
-                    // Call the RPC submission function
-                    submit_block_to_node(&settings,&block_hex)?; 
-                },
-                Err(e) => {
-                    eprintln!("Error serializing block for submission: {}", e);
-                    // We still break, but note the error.
-                }
+        // Workers hash the top of the nonce space with the timestamp fixed at job start,
+        // so their work never overlaps the devices below
+        let job = DeviceJob {
+            header: build_header(settings.version, &prev_hash_bytes, &merkle_root_bytes, header_time(&settings.time_range, settings.clock_offset_secs), settings.nbits, 0),
+            scan_target: scan_target.to_be_bytes(),
+            time_range: settings.time_range.clone(),
+            clock_offset_secs: settings.clock_offset_secs,
+        };
+        let _worker_job = workers.start_job(&job.header, &job.scan_target, &nonces);
+        let scheduler = Scheduler::new(settings.version, version_mask, nonces.clone(), first_nonce);
+        let stop = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+        let (hits_sender, hits) = mpsc::channel();
+
+        let outcome = thread::scope(|scope| {
+            for device in cpus.iter_mut().chain(gpus.iter_mut()) {
+                let (scheduler, job, stop, hashes, hits) = (&scheduler, &job, &stop, &hashes, hits_sender.clone());
+                scope.spawn(move || run_device(device.as_mut(), scheduler, job, control, stop, hashes, hits));
             }
+            drop(hits_sender);
+
+            // This thread handles everything but hashing: found work, stats, workers and the abandon flag
+            let mut last_tick = time::Instant::now();
+            let mut last_report = time::Instant::now();
+            let mut hashes_since_report: u64 = 0;
+            let outcome = 'supervise: loop {
+                match hits.recv_timeout(SUPERVISOR_TICK) {
+                    Ok(hit) => {
+                        let hit_value = Target::from_be_bytes(hit.hash);
+                        if hit_value <= pow::DIFF1_TARGET {
+                            let header = build_header(hit.version, &prev_hash_bytes, &merkle_root_bytes, hit.time, settings.nbits, hit.nonce);
+                            if stats.record_share(&settings.job_id, &header, &hit.hash)
+                                && let Err(e) = attestation::save_best(settings, stats, block.as_ref().map(|block| block.txdata.as_slice()))
+                            {
+                                eprintln!("[Attestation] Could not sign the best share: {}", e);
+                            }
+                            events.publish(MinerEvent::ShareFound { job_id: settings.job_id.clone(), hash: hex::encode(hit.hash), nonce: hit.nonce, time: hit.time, version: hit.version });
+                        }
+                        // 3. Check Difficulty: Compare the hash against the target
+                        if hit_value <= target {
+                            stop.store(true, Ordering::Relaxed);
+                            break finish_block(settings, stats, events, &prev_hash_bytes, &merkle_root_bytes, hit.hash, hit.nonce, hit.time, hit.version, Some(hit.slice), block.take().map(|block| block.txdata));
+                        }
+                    },
+                    Err(mpsc::RecvTimeoutError::Timeout) => {},
+                    // Every device ran out of slices
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break Ok(MiningOutcome::Exhausted),
+                }
+                if last_tick.elapsed() < SUPERVISOR_TICK {
+                    continue;
+                }
 
-            break;
-        }
+                // Merge the devices' hashes into one rate. Time spent paused isn't mining time,
+                // so it's left out of the hash rate
+                let done = hashes.swap(0, Ordering::Relaxed);
+                if control.is_paused() {
+                    hashes.fetch_add(done, Ordering::Relaxed);
+                } else {
+                    stats.record_hashes(done, last_tick.elapsed());
+                    hashes_since_report += done;
+                }
+                last_tick = time::Instant::now();
+
+                // Check what the workers found on their own copy of the header. They're untrusted:
+                // every candidate is rehashed before it counts
+                for candidate in workers.candidates() {
+                    let mut block_hash = H::sha256d(&candidate.header);
+                    let block_value = pow::hash_value(&block_hash);
+                    block_hash.reverse();
+                    let found = u32::from_le_bytes(candidate.header[76..].try_into().unwrap());
+                    if block_value > scan_target {
+                        eprintln!("[Workers] {} reported nonce {} which misses the target; check its firmware.", candidate.worker, found);
+                        continue;
+                    }
+                    let candidate_time = u32::from_le_bytes(candidate.header[68..72].try_into().unwrap());
+                    let candidate_version = u32::from_le_bytes(candidate.header[..4].try_into().unwrap());
+                    if block_value <= pow::DIFF1_TARGET {
+                        if stats.record_share(&settings.job_id, &candidate.header, &block_hash)
+                            && let Err(e) = attestation::save_best(settings, stats, block.as_ref().map(|block| block.txdata.as_slice()))
+                        {
+                            eprintln!("[Attestation] Could not sign the best share: {}", e);
+                        }
+                        events.publish(MinerEvent::ShareFound { job_id: settings.job_id.clone(), hash: hex::encode(block_hash), nonce: found, time: candidate_time, version: candidate_version });
+                    }
+                    if block_value <= target {
+                        println!("[Workers] Block found by {}.", candidate.worker);
+                        stop.store(true, Ordering::Relaxed);
+                        break 'supervise finish_block(settings, stats, events, &prev_hash_bytes, &merkle_root_bytes, block_hash, found, candidate_time, candidate_version, None, block.take().map(|block| block.txdata));
+                    }
+                }
 
-        // 4. Increment Nonce
-        nonce = nonce.wrapping_add(1);
+                if abandon.load(Ordering::Relaxed) {
+                    println!("[Mining] Dropping in-flight job {}.", settings.job_id);
+                    break Ok(MiningOutcome::Abandoned);
+                }
+                if control.is_stopped() {
+                    break Ok(MiningOutcome::Stopped);
+                }
+                // Testnet's 20-minute rule: once the window opens, start over at difficulty 1
+                if settings.nbits != difficulty::MIN_DIFFICULTY_BITS
+                    && settings.min_difficulty_after.is_some_and(|after| stats::unix_now() > u64::from(after))
+                {
+                    println!("[Testnet] 20 minutes without a block: restarting at difficulty 1.");
+                    break Ok(MiningOutcome::Abandoned);
+                }
+                // BIP23 expiry: a prefetched job is replaced by the prefetcher instead
+                if settings.prepared.is_none() && settings.template_expiring() {
+                    println!("[Mining] The template is about to expire: fetching a fresh one.");
+                    break Ok(MiningOutcome::Abandoned);
+                }
+                // Likewise for the periodic refresh
+                if settings.prepared.is_none() && refresh_at.is_some_and(|at| time::Instant::now() >= at) {
+                    println!("[Mining] Refreshing the template to pick up new transactions.");
+                    break Ok(MiningOutcome::Abandoned);
+                }
+                perf::dump_if_requested();
 
-        // Periodically report Hash Rate
-        if nonce % 1_000_000 == 0 {
-            let elapsed = hash_rate_start.elapsed().as_secs_f64();
-            let hashrate = hash_count as f64 / elapsed / 1_000_000.0;
-            println!("Status: Hashed {}M nonces. Hashrate: {:.3} MH/s", hash_count / 1_000_000, hashrate);
-            hash_rate_start = time::Instant::now();
-            hash_count = 0;
+                // Periodically report Hash Rate, about every million nonces but at most once a second
+                if hashes_since_report >= STATUS_INTERVAL_HASHES && last_report.elapsed() >= time::Duration::from_secs(1) {
+                    report_status(settings, stats);
+                    hashes_since_report = 0;
+                    last_report = time::Instant::now();
+                }
+            };
+            stop.store(true, Ordering::Relaxed);
+            outcome
+        });
+        if !matches!(outcome, Ok(MiningOutcome::Exhausted)) {
+            return outcome;
         }
-        
-        // If nonce overflows, the miner needs to get a new block template
-        if nonce == 0 {
+        // A solo job goes on with a new merkle root: the same template, with the next
+        // extranonce in the coinbase. Pool work is left for the pool to replace
+        let Some(block) = block.as_mut().filter(|_| settings.pool_coinbase.is_none()) else {
             println!("[Mining] Nonce overflowed! Stopping search in this template space.");
-            return Ok(());
-        }
+            return outcome;
+        };
+        extranonce_rolls += 1;
+        merkle_root_bytes = roll_extranonce(settings, block, extranonce_rolls)?;
+        let mut display_root = merkle_root_bytes;
+        display_root.reverse();
+        println!("[Mining] Nonce space exhausted; rolled the extranonce to {} (merkle root {}).", extranonce_rolls, hex::encode(display_root));
     }
-
-    Ok(())
 }
 
-// In src/miner.rs, add this new function before mine_block or after sha256d
+/// What every device needs to build headers for the current job.
+#[cfg(feature = "rpc")]
+struct DeviceJob {
+    /// The header, serialized once per job; each slice patches its version, time and nonce.
+    header: [u8; 80],
+    scan_target: [u8; 32],
+    /// Timestamps the template allows.
+    time_range: RangeInclusive<u32>,
+    /// How far the node's clock is ahead of ours.
+    clock_offset_secs: i64,
+}
 
-/// Submits the raw, serialized block to the Bitcoin node via the submitblock RPC.
-pub fn submit_block_to_node(
-    settings: &MinerSettings, // Need to reference the settings struct
-    block_hex: &str
-) -> io::Result<()> {
-    println!("\n[RPC] Submitting found block to node...");
+/// The header timestamp for work started now: the node's clock (ours shifted by
+/// `clock_offset_secs`), kept within `range`.
+#[cfg(feature = "rpc")]
+fn header_time(range: &RangeInclusive<u32>, clock_offset_secs: i64) -> u32 {
+    let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let node_now = now.saturating_add(clock_offset_secs);
+    node_now.clamp(i64::from(*range.start()), i64::from(*range.end())) as u32
+}
 
-    // 1. Define the RPC response structure for submission
-    // Note: You may need to ensure serde::Deserialize is available in miner.rs
-    // or pass the response handling to settings.rs if you want to keep
-    // serde::Deserialize there. Since submit_block is simple, let's keep it here.
-    use serde::Deserialize; 
+/// A hash a device found at or below the scan target. `hash` is in display order.
+#[cfg(feature = "rpc")]
+struct Hit {
+    hash: [u8; 32],
+    nonce: u32,
+    time: u32,
+    version: u32,
+    /// First and last nonce of the slice it was found in.
+    slice: (u32, u32),
+}
 
-    #[derive(Deserialize)]
-    struct RpcResponse {
-        result: Option<String>,
+/// Feeds one device slices from the scheduler until the job stops or runs out of slices.
+/// The scan runs once per nonce: it works on stack buffers only and must not
+/// allocate. Everything else runs once per slice or on rare paths (shares, found blocks).
+#[cfg(feature = "rpc")]
+fn run_device(
+    device: &mut dyn Device,
+    scheduler: &Scheduler,
+    job: &DeviceJob,
+    control: &MinerControl,
+    stop: &AtomicBool,
+    hashes: &AtomicU64,
+    hits: mpsc::Sender<Hit>,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let Some(slice) = scheduler.next_slice(device.slice_size()) else { break };
+        // 1. Patch the job's 80-byte Block Header once per slice; the device only rewrites the nonce.
+        // The clock is read here too, so the timestamp moves on with every slice, not every hash
+        let slice_start = time::Instant::now();
+        let current_time = header_time(&job.time_range, job.clock_offset_secs);
+        let mut header = job.header;
+        pow::patch_header(&mut header, slice.version, current_time, *slice.nonces.start());
+        perf::record(perf::Section::HeaderBuild, slice_start.elapsed());
+
+        // 2. Perform Double SHA-256 over the slice, reporting every share
+        let slice_hashes = u64::from(slice.nonces.end() - slice.nonces.start()) + 1;
+        let slice_range = (*slice.nonces.start(), *slice.nonces.end());
+        let scanned = device.scan(&header, slice.nonces, &job.scan_target, &mut |nonce, mut hash| {
+            // Display (big-endian) order from here on
+            hash.reverse();
+            let _ = hits.send(Hit { hash, nonce, time: current_time, version: slice.version, slice: slice_range });
+        });
+        if let Err(e) = scanned {
+            // The slice is lost, but the other devices carry on with the job
+            eprintln!("[Mining] {} stopped: {}", device.name(), e);
+            break;
+        }
+        hashes.fetch_add(slice_hashes, Ordering::Relaxed);
+        perf::record(perf::Section::Hash, slice_start.elapsed().div_f64(slice_hashes as f64));
+        perf::record(perf::Section::HashBatch, slice_start.elapsed());
+        control.after_batch(slice_start.elapsed());
     }
+}
 
-    let client = reqwest::blocking::Client::new();
+/// Announces a found block, then serializes and submits it. `block_hash` is in display order,
+/// and `nonce_range` is the slice it was found in (unknown for workers' finds).
+/// Without `txdata` the block was found on pool work, and its share submission already carried it.
+#[cfg(feature = "rpc")]
+#[allow(clippy::too_many_arguments)]
+fn finish_block(
+    settings: &MinerSettings,
+    stats: &StatsHandle,
+    events: &EventBus,
+    prev_hash_bytes: &[u8; 32],
+    merkle_root_bytes: &[u8; 32],
+    block_hash: [u8; 32],
+    nonce: u32,
+    current_time: u32,
+    version: u32,
+    nonce_range: Option<(u32, u32)>,
+    txdata: Option<Vec<bitcoin::Transaction>>,
+) -> io::Result<MiningOutcome> {
+    println!("\n==============================================");
+    println!("🎉 BLOCK FOUND! (The Lottery is Won!)");
+    println!("Hash: {}", hex::encode(block_hash));
+    println!("Nonce: {}", nonce);
+    println!("Job: {} (version {:08x}, time {}{})", settings.job_id, version, current_time,
+        nonce_range.map_or(String::new(), |(first, last)| format!(", nonces {}..={}", first, last)));
+    println!("==============================================");
+    events.publish(MinerEvent::BlockFound { job_id: settings.job_id.clone(), hash: hex::encode(block_hash), nonce, time: current_time, version });
+    let Some(txdata) = txdata else {
+        println!("[Pool] The block went to the pool as a share; the pool broadcasts it.");
+        return Ok(MiningOutcome::SubmittedToPool { hash: hex::encode(block_hash) });
+    };
 
-    // 2. Construct the JSON-RPC request body
-    let request_body = serde_json::json!({
-        "jsonrpc": "1.0",
-        "id": "solo-miner-submit",
-        "method": "submitblock",
-        "params": [block_hex] 
-    });
+    let mut accepted = false;
+    match serialize_block(
+        settings, 
+        prev_hash_bytes, 
+        merkle_root_bytes, 
+        nonce, 
+        current_time,
+        version,
+        // We return right after submitting, so the transactions can be moved rather than cloned
+        txdata
+    ) {
+        Ok((block, block_hex)) => {
+            // Save, submit, announce, and record the attempt in the audit log
+            match submit::submit_found_block(settings, &block, &block_hex, nonce_range) {
+                Ok(()) => {
+                    accepted = true;
+                    stats.update(|s| s.last_submit_result = Some("accepted".to_string()));
+                    events.publish(MinerEvent::SubmitResult { job_id: settings.job_id.clone(), hash: hex::encode(block_hash), accepted: true, detail: "accepted".to_string() });
+                    wallet::report_coinbase(settings, &block);
+                },
+                Err(submit::SubmitError::Rejected(rejection)) => {
+                    eprintln!("❌ [RPC] Block rejected by node: {}", rejection);
+                    eprintln!("   Hint: {}", rejection.hint());
+                    stats.update(|s| s.last_submit_result = Some(format!("rejected: {}", rejection)));
+                    events.publish(MinerEvent::SubmitResult { job_id: settings.job_id.clone(), hash: hex::encode(block_hash), accepted: false, detail: rejection.to_string() });
+                },
+                Err(submit::SubmitError::Invalid(problem)) => {
+                    eprintln!("❌ [Submit] Not submitting the block: it fails local validation: {}", problem);
+                    eprintln!("   The block hex is kept, so it can be checked or resubmitted by hand.");
+                    stats.update(|s| s.last_submit_result = Some(format!("invalid: {}", problem)));
+                    events.publish(MinerEvent::SubmitResult { job_id: settings.job_id.clone(), hash: hex::encode(block_hash), accepted: false, detail: problem });
+                },
+                Err(submit::SubmitError::Rpc(e)) => {
+                    stats.update(|s| s.last_submit_result = Some(format!("error: {}", e)));
+                    return Err(e);
+                },
+            }
+        },
+        Err(e) => {
+            eprintln!("Error serializing block for submission: {}", e);
+            // We still stop, but note the error.
+        }
+    }
 
-    // 3. Send the authenticated request using fields from the passed settings reference
-    let response = client.post(&settings.rpc_url)
-        .basic_auth(&settings.rpc_user, Some(&settings.rpc_pass))
-        .json(&request_body)
-        .send()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("RPC submission request failed: {}", e)))?;
-
-    // 4. Parse and check the response (rest of the logic remains the same)
-    if response.status().is_success() {
-        let rpc_response: RpcResponse = response.json()
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to parse submitblock JSON response: {}", e)))?;
-        
-        if let Some(err_message) = rpc_response.result {
-            return Err(io::Error::new(io::ErrorKind::Other, format!("Block submission rejected by node: {}", err_message)));
+    Ok(MiningOutcome::BlockFound { hash: hex::encode(block_hash), accepted })
+}
+
+/// Prints the periodic status report and saves lifetime stats. Kept out of the
+/// mining loop: it formats strings, and the loop itself must not allocate.
+#[cfg(feature = "rpc")]
+#[cold]
+fn report_status(settings: &MinerSettings, stats: &StatsHandle) {
+    stats.with(|snapshot| {
+        let lane = settings.lane.as_ref().map_or(String::new(), |name| format!(" [{}]", name));
+        println!("Status{}: Hashed {}M nonces. Hashrate: {}", lane, snapshot.hashes_total / 1_000_000, units::format_hashrate(snapshot.hashrate_current));
+        if snapshot.worker_hashrate > 0.0 {
+            println!("        Workers: {}", units::format_hashrate(snapshot.worker_hashrate));
+        }
+        if let (Some(watts), Some(kwh)) = (snapshot.power_watts, snapshot.energy_kwh) {
+            println!(
+                "        Power: {:.1} W, {} J/GH. Energy this session: {:.3} kWh",
+                watts, snapshot.joules_per_gigahash.map_or("n/a".to_string(), |j| format!("{:.0}", j)), kwh
+            );
+        }
+        if let Some(luck) = snapshot.luck_percent {
+            // Share counts are Poisson distributed: the standard deviation is sqrt(expected)
+            println!(
+                "        Luck: {:.0}% ({} shares vs {:.1} ± {:.1} expected). Lifetime luck: {}",
+                luck, snapshot.shares, snapshot.expected_shares, snapshot.expected_shares.sqrt(),
+                snapshot.lifetime_luck_percent.map_or("n/a".to_string(), |l| format!("{:.0}%", l))
+            );
         }
-        
-        println!("✅ [RPC] Block submitted successfully! Check your node logs.");
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, format!("RPC Submission Error: {} - {}", response.status(), response.text().unwrap_or_default())))
+        if let Some(best) = &snapshot.best_share {
+            println!("        Best share: difficulty {:.1} ({})", best.difficulty, best.hash);
+        }
+        let difficulty = difficulty::difficulty_from_nbits(settings.nbits);
+        if let Some(secs) = difficulty::expected_time_to_block(difficulty, snapshot.hashrate_current) {
+            let after_retarget = snapshot.retarget.as_ref()
+                .and_then(|r| difficulty::expected_time_to_block(r.estimated_difficulty, snapshot.hashrate_current))
+                .map_or(String::new(), |s| format!(" ({} after the next retarget)", units::format_duration(s)));
+            println!("        Expected time to block: {}{}", units::format_duration(secs), after_retarget);
+        }
+    });
+    if let Err(e) = stats.save_lifetime() {
+        eprintln!("[Stats] Could not save lifetime stats: {}", e);
     }
 }
 
-/// Creates the full block structure, serializes it, and returns the hex string.
+/// Creates the full block structure, serializes it, and returns the block with its hex string.
+#[cfg(feature = "rpc")]
 fn serialize_block(
     settings: &crate::settings::MinerSettings, 
     prev_hash_bytes: &[u8], 
     merkle_root_bytes: &[u8], 
     nonce: u32, 
     current_time: u32,
-    // NEW PARAMETER: Accept the pre-built Coinbase Transaction
-    coinbase_tx: &bitcoin::Transaction 
-) -> io::Result<String> {
+    version: u32,
+    // The coinbase followed by the selected template transactions
+    txdata: Vec<bitcoin::Transaction>
+) -> io::Result<(bitcoin::Block, String)> {
     
     let prev_blockhash = bitcoin::block::BlockHash::from_slice(prev_hash_bytes)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid prev hash: {}", e)))?;
@@ -275,7 +938,7 @@ fn serialize_block(
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid merkle root: {}", e)))?;
 
     let block_header = bitcoin::block::Header {
-        version: bitcoin::block::Version::from_consensus(settings.version as i32),
+        version: bitcoin::block::Version::from_consensus(version as i32),
         prev_blockhash,
         merkle_root,
         time: current_time,
@@ -287,16 +950,15 @@ fn serialize_block(
     // Create the full Block structure
     let block = bitcoin::Block {
         header: block_header,
-        // Use the passed transaction reference
-        txdata: vec![coinbase_tx.clone()], 
+        txdata,
     };
 
-    // Serialize the block into a Vec<u8>
-    let mut serialized_block = Vec::new();
+    // Serialize the block into a Vec<u8> sized up front
+    let mut serialized_block = Vec::with_capacity(block.total_size());
     block.consensus_encode(&mut serialized_block).map_err(|e| {
-         io::Error::new(io::ErrorKind::Other, format!("Failed to serialize block: {}", e))
+         io::Error::other(format!("Failed to serialize block: {}", e))
     })?;
 
     // Return the result as a hex string for RPC submission
-    Ok(hex::encode(&serialized_block))
+    Ok((block, hex::encode(&serialized_block)))
 }
\ No newline at end of file
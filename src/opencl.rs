@@ -0,0 +1,278 @@
+//! OpenCL GPUs as scheduler devices (the `gpu-opencl` feature).
+//!
+//! The OpenCL library is loaded at run time, so the miner builds without an SDK and
+//! starts without GPU drivers. Each GPU builds `kernels/sha256d.cl` from source;
+//! batching, tuning and the CPU check of candidates are shared with the other GPU
+//! backends in `gpu.rs`.
+
+use crate::gpu::{self, GpuDevice, Kernel, Launch, MAX_RESULTS};
+use libloading::Library;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::sync::Arc;
+use std::{io, ptr};
+
+#[cfg(target_os = "windows")]
+const LIBRARY_NAMES: &[&str] = &["OpenCL.dll"];
+#[cfg(target_os = "macos")]
+const LIBRARY_NAMES: &[&str] = &["/System/Library/Frameworks/OpenCL.framework/OpenCL"];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARY_NAMES: &[&str] = &["libOpenCL.so.1", "libOpenCL.so"];
+
+type Handle = *mut c_void;
+
+const CL_SUCCESS: i32 = 0;
+const CL_DEVICE_NOT_FOUND: i32 = -1;
+const CL_BUILD_PROGRAM_FAILURE: i32 = -11;
+const CL_DEVICE_TYPE_GPU: u64 = 1 << 2;
+const CL_DEVICE_MAX_WORK_GROUP_SIZE: u32 = 0x1004;
+const CL_DEVICE_NAME: u32 = 0x102b;
+const CL_DRIVER_VERSION: u32 = 0x102d;
+const CL_PLATFORM_NAME: u32 = 0x0902;
+const CL_PROGRAM_BUILD_LOG: u32 = 0x1183;
+const CL_MEM_READ_WRITE: u64 = 1 << 0;
+const CL_MEM_READ_ONLY: u64 = 1 << 2;
+const CL_TRUE: u32 = 1;
+
+/// The OpenCL entry points the miner uses, resolved from the loaded library.
+struct Api {
+    get_platform_ids: unsafe extern "system" fn(u32, *mut Handle, *mut u32) -> i32,
+    get_platform_info: unsafe extern "system" fn(Handle, u32, usize, *mut c_void, *mut usize) -> i32,
+    get_device_ids: unsafe extern "system" fn(Handle, u64, u32, *mut Handle, *mut u32) -> i32,
+    get_device_info: unsafe extern "system" fn(Handle, u32, usize, *mut c_void, *mut usize) -> i32,
+    create_context: unsafe extern "system" fn(*const isize, u32, *const Handle, *const c_void, *mut c_void, *mut i32) -> Handle,
+    create_command_queue: unsafe extern "system" fn(Handle, Handle, u64, *mut i32) -> Handle,
+    create_program_with_source: unsafe extern "system" fn(Handle, u32, *const *const c_char, *const usize, *mut i32) -> Handle,
+    build_program: unsafe extern "system" fn(Handle, u32, *const Handle, *const c_char, *const c_void, *mut c_void) -> i32,
+    get_program_build_info: unsafe extern "system" fn(Handle, Handle, u32, usize, *mut c_void, *mut usize) -> i32,
+    create_kernel: unsafe extern "system" fn(Handle, *const c_char, *mut i32) -> Handle,
+    create_buffer: unsafe extern "system" fn(Handle, u64, usize, *mut c_void, *mut i32) -> Handle,
+    set_kernel_arg: unsafe extern "system" fn(Handle, u32, usize, *const c_void) -> i32,
+    enqueue_write_buffer: unsafe extern "system" fn(Handle, Handle, u32, usize, usize, *const c_void, u32, *const Handle, *mut Handle) -> i32,
+    enqueue_read_buffer: unsafe extern "system" fn(Handle, Handle, u32, usize, usize, *mut c_void, u32, *const Handle, *mut Handle) -> i32,
+    enqueue_nd_range_kernel: unsafe extern "system" fn(Handle, Handle, u32, *const usize, *const usize, *const usize, u32, *const Handle, *mut Handle) -> i32,
+    release_mem_object: unsafe extern "system" fn(Handle) -> i32,
+    release_kernel: unsafe extern "system" fn(Handle) -> i32,
+    release_program: unsafe extern "system" fn(Handle) -> i32,
+    release_command_queue: unsafe extern "system" fn(Handle) -> i32,
+    release_context: unsafe extern "system" fn(Handle) -> i32,
+    /// Keeps the entry points above valid.
+    _library: Library,
+}
+
+impl Api {
+    fn load() -> io::Result<Api> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, format!("No OpenCL library found (tried {})", LIBRARY_NAMES.join(", ")));
+        // SAFETY: loading the system's OpenCL ICD loader, which has no unusual initialization
+        let library = LIBRARY_NAMES.iter().find_map(|name| unsafe { Library::new(name) }.ok()).ok_or_else(not_found)?;
+        macro_rules! entry {
+            ($name:literal) => {
+                // SAFETY: the signature matches the OpenCL 1.2 declaration of the function
+                *unsafe { library.get($name) }
+                    .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("OpenCL library lacks {}: {}", String::from_utf8_lossy($name), e)))?
+            };
+        }
+        Ok(Api {
+            get_platform_ids: entry!(b"clGetPlatformIDs"),
+            get_platform_info: entry!(b"clGetPlatformInfo"),
+            get_device_ids: entry!(b"clGetDeviceIDs"),
+            get_device_info: entry!(b"clGetDeviceInfo"),
+            create_context: entry!(b"clCreateContext"),
+            create_command_queue: entry!(b"clCreateCommandQueue"),
+            create_program_with_source: entry!(b"clCreateProgramWithSource"),
+            build_program: entry!(b"clBuildProgram"),
+            get_program_build_info: entry!(b"clGetProgramBuildInfo"),
+            create_kernel: entry!(b"clCreateKernel"),
+            create_buffer: entry!(b"clCreateBuffer"),
+            set_kernel_arg: entry!(b"clSetKernelArg"),
+            enqueue_write_buffer: entry!(b"clEnqueueWriteBuffer"),
+            enqueue_read_buffer: entry!(b"clEnqueueReadBuffer"),
+            enqueue_nd_range_kernel: entry!(b"clEnqueueNDRangeKernel"),
+            release_mem_object: entry!(b"clReleaseMemObject"),
+            release_kernel: entry!(b"clReleaseKernel"),
+            release_program: entry!(b"clReleaseProgram"),
+            release_command_queue: entry!(b"clReleaseCommandQueue"),
+            release_context: entry!(b"clReleaseContext"),
+            _library: library,
+        })
+    }
+
+    /// A string-valued platform or device property.
+    fn info_string(&self, object: Handle, param: u32, platform: bool) -> io::Result<String> {
+        let query = if platform { self.get_platform_info } else { self.get_device_info };
+        let mut size = 0;
+        // SAFETY: a size query, then a read into a buffer of the reported size
+        check(unsafe { query(object, param, 0, ptr::null_mut(), &mut size) }, "querying a property size")?;
+        let mut value = vec![0u8; size];
+        check(unsafe { query(object, param, size, value.as_mut_ptr().cast(), ptr::null_mut()) }, "querying a property")?;
+        Ok(CStr::from_bytes_until_nul(&value).map_or_else(|_| String::from_utf8_lossy(&value).into_owned(), |s| s.to_string_lossy().into_owned()))
+    }
+
+    /// Every GPU of every platform, in a stable order, with its platform's name.
+    fn gpus(&self) -> io::Result<Vec<(Handle, String)>> {
+        let mut count = 0;
+        // SAFETY: count queries followed by reads into buffers of that many handles
+        check(unsafe { (self.get_platform_ids)(0, ptr::null_mut(), &mut count) }, "listing platforms")?;
+        let mut platforms = vec![ptr::null_mut(); count as usize];
+        check(unsafe { (self.get_platform_ids)(count, platforms.as_mut_ptr(), ptr::null_mut()) }, "listing platforms")?;
+
+        let mut gpus = Vec::new();
+        for platform in platforms {
+            let platform_name = self.info_string(platform, CL_PLATFORM_NAME, true)?;
+            let mut count = 0;
+            match unsafe { (self.get_device_ids)(platform, CL_DEVICE_TYPE_GPU, 0, ptr::null_mut(), &mut count) } {
+                CL_DEVICE_NOT_FOUND => continue,
+                status => check(status, "listing devices")?,
+            }
+            let mut devices = vec![ptr::null_mut(); count as usize];
+            check(unsafe { (self.get_device_ids)(platform, CL_DEVICE_TYPE_GPU, count, devices.as_mut_ptr(), ptr::null_mut()) }, "listing devices")?;
+            gpus.extend(devices.into_iter().map(|device| (device, platform_name.clone())));
+        }
+        Ok(gpus)
+    }
+}
+
+fn check(status: i32, doing: &str) -> io::Result<()> {
+    if status == CL_SUCCESS {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("OpenCL error {} while {}", status, doing)))
+    }
+}
+
+/// Sets up the GPUs at `indices` among all GPUs found (all of them when empty), tuning
+/// those not in the cache yet.
+pub fn devices(indices: &[usize]) -> io::Result<Vec<GpuDevice<OpenClKernel>>> {
+    let api = Arc::new(Api::load()?);
+    let gpus = api.gpus()?;
+    if gpus.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "OpenCL found no GPU"));
+    }
+    for (i, (device, platform)) in gpus.iter().enumerate() {
+        println!("[OpenCL] GPU {}: {} ({})", i, api.info_string(*device, CL_DEVICE_NAME, false)?, platform);
+    }
+    let chosen: Vec<usize> = if indices.is_empty() { (0..gpus.len()).collect() } else { indices.to_vec() };
+
+    let mut devices = Vec::new();
+    for index in chosen {
+        let (device, platform) = gpus.get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No OpenCL GPU {} (found {})", index, gpus.len())))?;
+        let model = api.info_string(*device, CL_DEVICE_NAME, false)?;
+        let key = format!("{} / {} / {}", platform, model, api.info_string(*device, CL_DRIVER_VERSION, false)?);
+        let mut max_work_group_size: usize = 0;
+        // SAFETY: reads a size_t property into a size_t
+        check(unsafe {
+            (api.get_device_info)(*device, CL_DEVICE_MAX_WORK_GROUP_SIZE, size_of::<usize>(), (&mut max_work_group_size as *mut usize).cast(), ptr::null_mut())
+        }, "querying the work-group size")?;
+        let kernel = OpenClKernel::new(Arc::clone(&api), *device, &model)?;
+        devices.push(GpuDevice::new(format!("opencl{}", index), &model, &key, max_work_group_size, kernel)?);
+    }
+    Ok(devices)
+}
+
+/// One GPU with the kernel built and its buffers allocated.
+pub struct OpenClKernel {
+    api: Arc<Api>,
+    context: Handle,
+    queue: Handle,
+    program: Handle,
+    kernel: Handle,
+    midstate: Handle,
+    results: Handle,
+}
+
+// SAFETY: OpenCL objects may be used from any thread; each kernel is only driven
+// by one thread at a time, which keeps its arguments consistent.
+unsafe impl Send for OpenClKernel {}
+
+impl OpenClKernel {
+    fn new(api: Arc<Api>, device: Handle, model: &str) -> io::Result<OpenClKernel> {
+        let mut status = CL_SUCCESS;
+        // Every object is released by Drop once assigned, so `?` below leaks nothing
+        let mut gpu = OpenClKernel {
+            api: Arc::clone(&api),
+            context: ptr::null_mut(),
+            queue: ptr::null_mut(),
+            program: ptr::null_mut(),
+            kernel: ptr::null_mut(),
+            midstate: ptr::null_mut(),
+            results: ptr::null_mut(),
+        };
+        // SAFETY: each call gets valid handles created above and checks its status
+        unsafe {
+            gpu.context = (api.create_context)(ptr::null(), 1, &device, ptr::null(), ptr::null_mut(), &mut status);
+            check(status, "creating a context")?;
+            gpu.queue = (api.create_command_queue)(gpu.context, device, 0, &mut status);
+            check(status, "creating a command queue")?;
+            let source = gpu::KERNEL_SOURCE.as_ptr().cast::<c_char>();
+            gpu.program = (api.create_program_with_source)(gpu.context, 1, &source, &gpu::KERNEL_SOURCE.len(), &mut status);
+            check(status, "loading the kernel")?;
+            let options = CString::default();
+            match (api.build_program)(gpu.program, 1, &device, options.as_ptr(), ptr::null(), ptr::null_mut()) {
+                CL_BUILD_PROGRAM_FAILURE => {
+                    let mut size = 0;
+                    (api.get_program_build_info)(gpu.program, device, CL_PROGRAM_BUILD_LOG, 0, ptr::null_mut(), &mut size);
+                    let mut log = vec![0u8; size];
+                    (api.get_program_build_info)(gpu.program, device, CL_PROGRAM_BUILD_LOG, size, log.as_mut_ptr().cast(), ptr::null_mut());
+                    return Err(io::Error::other(format!("The kernel did not build for {}: {}", model, String::from_utf8_lossy(&log))));
+                },
+                status => check(status, "building the kernel")?,
+            }
+            gpu.kernel = (api.create_kernel)(gpu.program, c"sha256d_scan".as_ptr(), &mut status);
+            check(status, "creating the kernel")?;
+            gpu.midstate = (api.create_buffer)(gpu.context, CL_MEM_READ_ONLY, size_of::<[u32; 8]>(), ptr::null_mut(), &mut status);
+            check(status, "allocating the midstate buffer")?;
+            gpu.results = (api.create_buffer)(gpu.context, CL_MEM_READ_WRITE, size_of::<[u32; MAX_RESULTS + 1]>(), ptr::null_mut(), &mut status);
+            check(status, "allocating the result buffer")?;
+            check((api.set_kernel_arg)(gpu.kernel, 0, size_of::<Handle>(), (&gpu.midstate as *const Handle).cast()), "setting kernel arguments")?;
+            check((api.set_kernel_arg)(gpu.kernel, 7, size_of::<Handle>(), (&gpu.results as *const Handle).cast()), "setting kernel arguments")?;
+        }
+        Ok(gpu)
+    }
+}
+
+impl Kernel for OpenClKernel {
+    fn launch(&mut self, launch: &Launch) -> io::Result<Vec<u32>> {
+        let api = &self.api;
+        let mut results = [0u32; MAX_RESULTS + 1];
+        let global_size = (launch.count as usize).div_ceil(launch.work_group_size) * launch.work_group_size;
+        let args = [launch.tail[0], launch.tail[1], launch.tail[2], launch.first_nonce, launch.count, launch.target_top];
+        // SAFETY: buffers and arguments match the kernel's declaration; the blocking read
+        // on the in-order queue returns once the kernel is done
+        unsafe {
+            check((api.enqueue_write_buffer)(self.queue, self.midstate, CL_TRUE, 0, size_of_val(&launch.midstate), launch.midstate.as_ptr().cast(), 0, ptr::null(), ptr::null_mut()), "writing the midstate")?;
+            check((api.enqueue_write_buffer)(self.queue, self.results, CL_TRUE, 0, size_of::<u32>(), results.as_ptr().cast(), 0, ptr::null(), ptr::null_mut()), "clearing the results")?;
+            for (i, arg) in args.iter().enumerate() {
+                check((api.set_kernel_arg)(self.kernel, 1 + i as u32, size_of::<u32>(), (arg as *const u32).cast()), "setting kernel arguments")?;
+            }
+            check((api.enqueue_nd_range_kernel)(self.queue, self.kernel, 1, ptr::null(), &global_size, &launch.work_group_size, 0, ptr::null(), ptr::null_mut()), "launching the kernel")?;
+            check((api.enqueue_read_buffer)(self.queue, self.results, CL_TRUE, 0, size_of_val(&results), results.as_mut_ptr().cast(), 0, ptr::null(), ptr::null_mut()), "reading the results")?;
+        }
+        Ok(gpu::candidates(&results))
+    }
+}
+
+impl Drop for OpenClKernel {
+    fn drop(&mut self) {
+        let api = &self.api;
+        // SAFETY: each handle was created by this kernel and is released once
+        unsafe {
+            for buffer in [self.midstate, self.results] {
+                if !buffer.is_null() {
+                    (api.release_mem_object)(buffer);
+                }
+            }
+            if !self.kernel.is_null() {
+                (api.release_kernel)(self.kernel);
+            }
+            if !self.program.is_null() {
+                (api.release_program)(self.program);
+            }
+            if !self.queue.is_null() {
+                (api.release_command_queue)(self.queue);
+            }
+            if !self.context.is_null() {
+                (api.release_context)(self.context);
+            }
+        }
+    }
+}
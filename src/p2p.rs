@@ -0,0 +1,140 @@
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use bitcoin::Network;
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::p2p::message_compact_blocks::{BlockTxn, CmpctBlock, SendCmpct};
+use bitcoin::p2p::message_network::VersionMessage;
+use bitcoin::p2p::{Address, ServiceFlags};
+
+/// How long to wait for a peer to connect and complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to stay connected after announcing, to answer getblocktxn requests.
+const LINGER: Duration = Duration::from_secs(3);
+
+/// Compact block version 2 uses wtxids for short ids (required for segwit blocks).
+const COMPACT_BLOCK_VERSION: u32 = 2;
+
+/// The protocol version we advertise. Peers only take `sendcmpct` and `cmpctblock`
+/// from 70014 (BIP152) on; 70016 is the version Bitcoin Core speaks.
+const PROTOCOL_VERSION: u32 = 70016;
+
+/// Announces a found block to each configured peer as a BIP152 compact block, with
+/// the message magic of `network`, the chain the peers follow. Failures are reported
+/// per peer and never abort the others; the outcome for each peer is returned so it
+/// can be recorded.
+pub fn announce_compact_block(peers: &[String], block: &bitcoin::Block, network: Network) -> Vec<(String, io::Result<()>)> {
+    let mut results = Vec::with_capacity(peers.len());
+    for peer in peers {
+        println!("[P2P] Announcing compact block to {}...", peer);
+        let result = announce_to_peer(peer, block, network);
+        match &result {
+            Ok(()) => println!("[P2P] Compact block sent to {}.", peer),
+            Err(e) => eprintln!("[P2P] Could not announce block to {}: {}", peer, e),
+        }
+        results.push((peer.clone(), result));
+    }
+    results
+}
+
+/// Connects to a single peer, performs the version handshake, sends the compact
+/// block, and lingers briefly to serve any missing transactions it asks for.
+fn announce_to_peer(peer: &str, block: &bitcoin::Block, network: Network) -> io::Result<()> {
+    let addr = peer.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Could not resolve peer address '{}'", peer)))?;
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    // 1. Handshake: send our version, then wait for theirs and their verack. A peer
+    // sending other messages meanwhile only has until the deadline to finish.
+    send(&mut writer, network, version_message(&addr))?;
+    let mut got_verack = false;
+    let mut got_version = false;
+    while !(got_verack && got_version) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!(
+                "No version handshake within {} seconds", HANDSHAKE_TIMEOUT.as_secs()
+            )));
+        }
+        reader.get_ref().set_read_timeout(Some(remaining))?;
+        match receive(&mut reader)? {
+            NetworkMessage::Version(_) => {
+                got_version = true;
+                send(&mut writer, network, NetworkMessage::Verack)?;
+            },
+            NetworkMessage::Verack => got_verack = true,
+            NetworkMessage::Ping(nonce) => send(&mut writer, network, NetworkMessage::Pong(nonce))?,
+            _ => {},
+        }
+    }
+
+    // 2. Announce the block.
+    let nonce = random_nonce();
+    let compact_block = bitcoin::bip152::HeaderAndShortIds::from_block(block, nonce, COMPACT_BLOCK_VERSION, &[])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to build compact block: {}", e)))?;
+    send(&mut writer, network, NetworkMessage::SendCmpct(SendCmpct { send_compact: false, version: COMPACT_BLOCK_VERSION as u64 }))?;
+    send(&mut writer, network, NetworkMessage::CmpctBlock(CmpctBlock { compact_block }))?;
+
+    // 3. Serve getblocktxn for transactions the peer doesn't have in its mempool.
+    reader.get_ref().set_read_timeout(Some(LINGER))?;
+    let deadline = Instant::now() + LINGER;
+    while Instant::now() < deadline {
+        match receive(&mut reader) {
+            Ok(NetworkMessage::GetBlockTxn(request)) => {
+                let transactions = bitcoin::bip152::BlockTransactions::from_request(&request.txs_request, block)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Peer requested an invalid index: {}", e)))?;
+                send(&mut writer, network, NetworkMessage::BlockTxn(BlockTxn { transactions }))?;
+            },
+            Ok(NetworkMessage::Ping(nonce)) => send(&mut writer, network, NetworkMessage::Pong(nonce))?,
+            Ok(_) => {},
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Builds our `version` message. We advertise no services: we only push one block.
+fn version_message(peer: &SocketAddr) -> NetworkMessage {
+    let unspecified = SocketAddr::from(([0, 0, 0, 0], 0));
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let version = VersionMessage::new(
+        ServiceFlags::NONE,
+        timestamp,
+        Address::new(peer, ServiceFlags::NONE),
+        Address::new(&unspecified, ServiceFlags::NONE),
+        random_nonce(),
+        "/solo-miner/".to_string(),
+        0,
+    );
+    NetworkMessage::Version(VersionMessage { version: PROTOCOL_VERSION, ..version })
+}
+
+/// Serializes and writes a single P2P message.
+fn send(writer: &mut TcpStream, network: Network, payload: NetworkMessage) -> io::Result<()> {
+    let message = RawNetworkMessage::new(network.magic(), payload);
+    let mut bytes = Vec::new();
+    message.consensus_encode(&mut bytes)?;
+    writer.write_all(&bytes)
+}
+
+/// Reads and decodes a single P2P message.
+fn receive(reader: &mut BufReader<TcpStream>) -> io::Result<NetworkMessage> {
+    RawNetworkMessage::consensus_decode(reader)
+        .map(|m| m.into_payload())
+        .map_err(|e| match e {
+            bitcoin::consensus::encode::Error::Io(io_err) => io::Error::from(io_err),
+            other => io::Error::new(io::ErrorKind::InvalidData, format!("Bad P2P message: {}", other)),
+        })
+}
+
+/// A nonce that differs between announcements; BIP152 only needs it to be unpredictable to peers.
+fn random_nonce() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    (nanos as u64) ^ ((nanos >> 64) as u64) ^ (std::process::id() as u64).rotate_left(32)
+}
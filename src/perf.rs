@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The instrumented parts of the miner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// Building the 80-byte header of a batch.
+    HeaderBuild,
+    /// One double SHA-256 of a header, averaged over a batch.
+    Hash,
+    /// A batch of nonces between control checks.
+    HashBatch,
+    /// Assembling a block and computing its merkle root.
+    MerkleRoot,
+    /// A JSON-RPC round trip to the node.
+    RpcCall,
+}
+
+const SECTIONS: [Section; 5] = [Section::HeaderBuild, Section::Hash, Section::HashBatch, Section::MerkleRoot, Section::RpcCall];
+
+impl Section {
+    fn name(self) -> &'static str {
+        match self {
+            Section::HeaderBuild => "header build",
+            Section::Hash => "sha256d",
+            Section::HashBatch => "hash batch",
+            Section::MerkleRoot => "merkle root",
+            Section::RpcCall => "rpc call",
+        }
+    }
+}
+
+/// Count, total and maximum time of one section, in nanoseconds.
+struct Counter {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Counter {
+    const fn new() -> Self {
+        Counter { count: AtomicU64::new(0), total_nanos: AtomicU64::new(0), max_nanos: AtomicU64::new(0) }
+    }
+}
+
+static COUNTERS: [Counter; 5] = [Counter::new(), Counter::new(), Counter::new(), Counter::new(), Counter::new()];
+
+/// Set from a signal handler to ask for a report at the next opportunity.
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Adds one timing to a section. Lock-free; safe to call from any thread.
+pub fn record(section: Section, elapsed: Duration) {
+    let counter = &COUNTERS[section as usize];
+    let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+    counter.count.fetch_add(1, Ordering::Relaxed);
+    counter.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+    counter.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+}
+
+/// Runs `f`, recording how long it took under `section`.
+pub fn time<T>(section: Section, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    record(section, start.elapsed());
+    result
+}
+
+/// A table of every section's count, average and maximum time.
+pub fn report() -> String {
+    let mut out = format!("{:<14} {:>12} {:>14} {:>14}\n", "section", "count", "avg", "max");
+    for section in SECTIONS {
+        let counter = &COUNTERS[section as usize];
+        let count = counter.count.load(Ordering::Relaxed);
+        let total = counter.total_nanos.load(Ordering::Relaxed);
+        let avg = total.checked_div(count).unwrap_or(0);
+        out.push_str(&format!(
+            "{:<14} {:>12} {:>14?} {:>14?}\n",
+            section.name(), count, Duration::from_nanos(avg), Duration::from_nanos(counter.max_nanos.load(Ordering::Relaxed))
+        ));
+    }
+    out
+}
+
+/// Prints the report if one was requested (e.g. with SIGUSR1) since the last call.
+pub fn dump_if_requested() {
+    if DUMP_REQUESTED.swap(false, Ordering::Relaxed) {
+        println!("\n--- Performance Counters ---\n{}----------------------------", report());
+    }
+}
+
+/// Makes `kill -USR1 <pid>` request a performance report. Unix only; elsewhere a no-op.
+pub fn install_dump_signal() {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_signal(_: libc::c_int) {
+            // Only an atomic store: anything more isn't async-signal-safe.
+            DUMP_REQUESTED.store(true, Ordering::Relaxed);
+        }
+        // SAFETY: the handler only performs an atomic store.
+        unsafe {
+            libc::signal(libc::SIGUSR1, on_signal as *const () as libc::sighandler_t);
+        }
+    }
+}
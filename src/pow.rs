@@ -0,0 +1,257 @@
+//! The proof-of-work core: header serialization, targets and the nonce scan.
+//!
+//! Only `core`, `sha2` and the target types of `bitcoin` (which builds without `std`)
+//! are used here, with no allocation, so this file can be lifted as-is into a
+//! `#![no_std]` crate for embedded or WASM builds. The `pow-no-std` workspace member
+//! does just that, so `cargo check -p pow-no-std` catches anything that needs `std`.
+
+use bitcoin::pow::{CompactTarget, Target};
+use core::ops::RangeInclusive;
+use sha2::digest::generic_array::GenericArray;
+use sha2::{Digest, Sha256};
+
+/// Byte offset of the timestamp in a serialized header.
+const TIME_OFFSET: usize = 68;
+
+/// Byte offset of the nonce in a serialized header.
+const NONCE_OFFSET: usize = 76;
+
+/// SHA-256 initial hash value (FIPS 180-4, 5.3.3).
+pub(crate) const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Double SHA-256 with RustCrypto's sha2, in the byte order the digest produces
+/// (the internal, little-endian order of block hashes).
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    let hash1 = Sha256::digest(data);
+    Sha256::digest(hash1).into()
+}
+
+/// Header version bits BIP320 sets aside for miners to roll (bits 13 to 28).
+pub const BIP320_VERSION_MASK: u32 = 0x1fff_e000;
+
+/// The `n`th rolled version (BIP320): the bits of `n` are spread, lowest first, over
+/// the set bits of `mask`, and the bits of `version` outside `mask` are kept.
+/// `None` once `n` needs more bits than `mask` has.
+pub fn roll_version(version: u32, mask: u32, n: u32) -> Option<u32> {
+    let mut rolled = version & !mask;
+    let mut bits = n;
+    let mut free = mask;
+    while free != 0 {
+        let lowest = free & free.wrapping_neg();
+        if bits & 1 != 0 {
+            rolled |= lowest;
+        }
+        bits >>= 1;
+        free &= free - 1;
+    }
+    (bits == 0).then_some(rolled)
+}
+
+/// Serializes an 80-byte block header. All fields are little-endian; the hashes
+/// are expected in internal (little-endian) byte order.
+pub fn build_header(version: u32, prev_hash: &[u8; 32], merkle_root: &[u8; 32], time: u32, nbits: u32, nonce: u32) -> [u8; 80] {
+    let mut header = [0u8; 80];
+    header[0..4].copy_from_slice(&version.to_le_bytes());
+    header[4..36].copy_from_slice(prev_hash);
+    header[36..68].copy_from_slice(merkle_root);
+    header[TIME_OFFSET..72].copy_from_slice(&time.to_le_bytes());
+    header[72..76].copy_from_slice(&nbits.to_le_bytes());
+    header[NONCE_OFFSET..].copy_from_slice(&nonce.to_le_bytes());
+    header
+}
+
+/// Rewrites the fields that change within a job (version, time and nonce) in a header
+/// from `build_header`; the hashes and nBits are left as they are.
+pub fn patch_header(header: &mut [u8; 80], version: u32, time: u32, nonce: u32) {
+    header[..4].copy_from_slice(&version.to_le_bytes());
+    header[TIME_OFFSET..72].copy_from_slice(&time.to_le_bytes());
+    header[NONCE_OFFSET..].copy_from_slice(&nonce.to_le_bytes());
+}
+
+/// The difficulty-1 target (0xffff << 208). Hashes at or below it count as shares.
+pub const DIFF1_TARGET: Target = Target::MAX;
+
+/// The target encoded by compact nBits, decoded as Bitcoin Core does. `None` for
+/// encodings Core rejects: a negative mantissa, or a value that overflows 256 bits.
+/// A zero mantissa decodes to the zero target, which no hash meets.
+pub fn target_from_nbits(nbits: u32) -> Option<Target> {
+    let exponent = nbits >> 24;
+    let mantissa = nbits & 0x007f_ffff;
+    let negative = mantissa != 0 && nbits & 0x0080_0000 != 0;
+    let overflow = mantissa != 0 && (exponent > 34 || (mantissa > 0xff && exponent > 33) || (mantissa > 0xffff && exponent > 32));
+    (!negative && !overflow).then(|| Target::from_compact(CompactTarget::from_consensus(nbits)))
+}
+
+/// Converts compact nBits to a 32-byte big-endian target, the form the nonce scans
+/// take. Invalid encodings (see `target_from_nbits`) give the zero target.
+pub fn compact_to_target(nbits: u32) -> [u8; 32] {
+    target_from_nbits(nbits).unwrap_or(Target::ZERO).to_be_bytes()
+}
+
+/// Difficulty of compact nBits: how many times harder than difficulty 1 its target is
+/// to meet. 0 for the zero target and for invalid encodings.
+pub fn difficulty_from_nbits(nbits: u32) -> f64 {
+    match target_from_nbits(nbits) {
+        Some(target) if target != Target::ZERO => target.difficulty_float(),
+        _ => 0.0,
+    }
+}
+
+/// A hash in internal (little-endian) order as a 256-bit number, for comparing with targets.
+pub fn hash_value(hash: &[u8; 32]) -> Target {
+    Target::from_le_bytes(*hash)
+}
+
+/// Whether a hash in internal (little-endian) order is at or below a big-endian target.
+pub fn meets_target(hash: &[u8; 32], target: &[u8; 32]) -> bool {
+    hash_value(hash) <= Target::from_be_bytes(*target)
+}
+
+/// The SHA-256 state after the first 64 bytes of a header. Those bytes don't depend
+/// on the nonce, so a worker only has to compress the last 16 bytes per nonce.
+pub fn midstate(header: &[u8; 80]) -> [u32; 8] {
+    let mut state = SHA256_IV;
+    sha2::compress256(&mut state, &[GenericArray::clone_from_slice(&header[..64])]);
+    state
+}
+
+/// Double SHA-256 of a header given its midstate, the 12 bytes following the first
+/// 64 (end of the merkle root, time, nBits) and the nonce. Same result, in the same
+/// byte order, as `sha256d` over the full header.
+pub fn sha256d_midstate(midstate: &[u32; 8], tail: &[u8; 12], nonce: u32) -> [u8; 32] {
+    // Second block of the header: the tail, the nonce, then padding for an 80-byte message
+    let mut block = [0u8; 64];
+    block[..12].copy_from_slice(tail);
+    block[12..16].copy_from_slice(&nonce.to_le_bytes());
+    block[16] = 0x80;
+    block[56..].copy_from_slice(&(80u64 * 8).to_be_bytes());
+    let mut state = *midstate;
+    sha2::compress256(&mut state, &[GenericArray::clone_from_slice(&block)]);
+
+    // Hash the 32-byte digest again, as a single padded block
+    let mut block = [0u8; 64];
+    for (chunk, word) in block.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    block[32] = 0x80;
+    block[56..].copy_from_slice(&(32u64 * 8).to_be_bytes());
+    let mut state = SHA256_IV;
+    sha2::compress256(&mut state, &[GenericArray::clone_from_slice(&block)]);
+
+    let mut hash = [0u8; 32];
+    for (chunk, word) in hash.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+/// The nonce scan a worker runs: like `scan`, but from a midstate and header tail.
+pub fn scan_midstate(midstate: &[u32; 8], tail: &[u8; 12], nonces: RangeInclusive<u32>, target: &[u8; 32]) -> Option<(u32, [u8; 32])> {
+    let target = Target::from_be_bytes(*target);
+    for nonce in nonces {
+        let hash = sha256d_midstate(midstate, tail, nonce);
+        if hash_value(&hash) <= target {
+            return Some((nonce, hash));
+        }
+    }
+    None
+}
+
+/// Hashes `header` with each nonce in `nonces` and returns the first nonce whose
+/// hash meets `target`, with that hash (internal byte order). Only the nonce field
+/// of `header` is rewritten; on return it holds the last nonce tried.
+pub fn scan(
+    header: &mut [u8; 80],
+    nonces: RangeInclusive<u32>,
+    target: &[u8; 32],
+    sha256d: impl Fn(&[u8]) -> [u8; 32],
+) -> Option<(u32, [u8; 32])> {
+    let target = Target::from_be_bytes(*target);
+    for nonce in nonces {
+        header[NONCE_OFFSET..].copy_from_slice(&nonce.to_le_bytes());
+        let hash = sha256d(header);
+        if hash_value(&hash) <= target {
+            return Some((nonce, hash));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midstate_hash_matches_the_full_header_hash() {
+        let header = build_header(0x2000_0000, &[7u8; 32], &[9u8; 32], 1_700_000_000, 0x207fffff, 0);
+        let tail: [u8; 12] = header[64..76].try_into().unwrap();
+        let state = midstate(&header);
+        for nonce in [0, 1, 0xdead_beef, u32::MAX] {
+            let mut header = header;
+            patch_header(&mut header, 0x2000_0000, 1_700_000_000, nonce);
+            assert_eq!(sha256d_midstate(&state, &tail, nonce), sha256d(&header));
+        }
+    }
+
+    #[test]
+    fn scan_midstate_finds_the_same_nonce_as_scan() {
+        let mut header = build_header(0x2000_0000, &[7u8; 32], &[9u8; 32], 1_700_000_000, 0x207fffff, 0);
+        let tail: [u8; 12] = header[64..76].try_into().unwrap();
+        let state = midstate(&header);
+        let target = compact_to_target(0x207fffff);
+        let found = scan_midstate(&state, &tail, 0..=1000, &target);
+        assert!(found.is_some());
+        assert_eq!(found, scan(&mut header, 0..=1000, &target, sha256d));
+    }
+
+    #[test]
+    fn target_from_nbits_decodes_valid_encodings() {
+        assert_eq!(target_from_nbits(0x1d00ffff), Some(DIFF1_TARGET));
+        assert_eq!(target_from_nbits(0x207fffff), Some(Target::MAX_ATTAINABLE_REGTEST));
+        assert_eq!(difficulty_from_nbits(0x1d00ffff), 1.0);
+        // The largest mantissa that still fits at the largest exponent.
+        assert!(target_from_nbits(0x2200_00ff).is_some());
+    }
+
+    #[test]
+    fn target_from_nbits_rejects_negative_and_overflowing_encodings() {
+        assert_eq!(target_from_nbits(0x1d80ffff), None);
+        assert_eq!(target_from_nbits(0x2300_0001), None);
+        assert_eq!(target_from_nbits(0x2200_0100), None);
+        assert_eq!(target_from_nbits(0x2101_0000), None);
+        assert_eq!(compact_to_target(0x1d80ffff), [0u8; 32]);
+        assert_eq!(difficulty_from_nbits(0x2300_0001), 0.0);
+    }
+
+    #[test]
+    fn target_from_nbits_zero_mantissa_is_the_zero_target() {
+        // The sign and overflow checks only apply to a nonzero mantissa, as in Core.
+        for nbits in [0, 0x1d00_0000, 0x1d80_0000, 0xff00_0000] {
+            assert_eq!(target_from_nbits(nbits), Some(Target::ZERO), "nbits {:08x}", nbits);
+        }
+        assert!(!meets_target(&[1u8; 32], &compact_to_target(0)));
+        assert_eq!(difficulty_from_nbits(0), 0.0);
+    }
+
+    #[test]
+    fn roll_version_spreads_n_over_the_mask() {
+        assert_eq!(roll_version(0x2000_0000, BIP320_VERSION_MASK, 0), Some(0x2000_0000));
+        assert_eq!(roll_version(0x2000_0000, BIP320_VERSION_MASK, 1), Some(0x2000_2000));
+        assert_eq!(roll_version(0x2000_0000, BIP320_VERSION_MASK, 3), Some(0x2000_6000));
+        // Bits outside the mask are kept, bits inside it are replaced.
+        assert_eq!(roll_version(0x2000_2004, BIP320_VERSION_MASK, 0), Some(0x2000_0004));
+        // A sparse mask takes the bits of n lowest first.
+        assert_eq!(roll_version(0, 0b1010_0100, 0b101), Some(0b1000_0100));
+    }
+
+    #[test]
+    fn roll_version_runs_out_with_the_mask() {
+        let last = (1 << BIP320_VERSION_MASK.count_ones()) - 1;
+        assert_eq!(roll_version(0x2000_0000, BIP320_VERSION_MASK, last), Some(0x3fff_e000));
+        assert_eq!(roll_version(0x2000_0000, BIP320_VERSION_MASK, last + 1), None);
+        assert_eq!(roll_version(0x2000_0000, 0, 0), Some(0x2000_0000));
+        assert_eq!(roll_version(0x2000_0000, 0, 1), None);
+    }
+}
@@ -0,0 +1,62 @@
+//! Power draw of the mining machine, for energy and efficiency figures in the stats.
+//!
+//! The draw is either configured (`power_watts`) or read from a meter file
+//! (`power_meter`): a cumulative energy counter in microjoules such as Intel/AMD RAPL's
+//! `/sys/class/powercap/intel-rapl:0/energy_uj`, or an instantaneous reading in
+//! microwatts such as hwmon's `/sys/class/hwmon/hwmon*/power1_input`.
+
+use crate::settings::MinerSettings;
+use std::time::Duration;
+use std::{fs, io};
+
+/// Where the power figures come from.
+#[derive(Debug)]
+pub enum PowerSource {
+    /// A constant draw in watts.
+    Fixed(f64),
+    /// A cumulative energy counter in microjoules, with the previous reading.
+    EnergyCounter { path: String, last: Option<u64> },
+    /// An instantaneous power reading in microwatts.
+    PowerGauge { path: String },
+}
+
+impl PowerSource {
+    /// The source configured in `settings`, if any. A meter that can't be read is
+    /// reported, and `power_watts` is used instead when set.
+    pub fn from_settings(settings: &MinerSettings) -> Option<PowerSource> {
+        if let Some(path) = &settings.power_meter {
+            let source = if path.ends_with("energy_uj") {
+                PowerSource::EnergyCounter { path: path.clone(), last: None }
+            } else {
+                PowerSource::PowerGauge { path: path.clone() }
+            };
+            match read_micro(path) {
+                Ok(_) => return Some(source),
+                Err(e) => eprintln!("[Power] Could not read power_meter {}: {}", path, e),
+            }
+        }
+        settings.power_watts.filter(|watts| *watts > 0.0).map(PowerSource::Fixed)
+    }
+
+    /// Energy in joules used over the last `elapsed`, or `None` when the meter has
+    /// nothing to tell yet (a counter's first reading) or can't be read.
+    pub fn energy(&mut self, elapsed: Duration) -> Option<f64> {
+        match self {
+            PowerSource::Fixed(watts) => Some(*watts * elapsed.as_secs_f64()),
+            PowerSource::PowerGauge { path } => read_micro(path).ok().map(|microwatts| microwatts as f64 / 1e6 * elapsed.as_secs_f64()),
+            PowerSource::EnergyCounter { path, last } => {
+                let now = read_micro(path).ok()?;
+                // The counter covers the time since the previous reading, not `elapsed`;
+                // it also wraps around, which skips one interval
+                let joules = last.and_then(|previous| now.checked_sub(previous)).map(|microjoules| microjoules as f64 / 1e6);
+                *last = Some(now);
+                joules
+            },
+        }
+    }
+}
+
+fn read_micro(path: &str) -> io::Result<u64> {
+    fs::read_to_string(path)?.trim().parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{} does not hold a number: {}", path, e)))
+}
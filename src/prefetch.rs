@@ -0,0 +1,275 @@
+//! Template prefetching: while the current job is hashed, a background thread watches
+//! the node and fully prepares the next one (template, coinbase, merkle root and block),
+//! then raises the current job's `superseded` flag. The mining loop picks the prepared
+//! job up straight away, so a new tip or a template refresh costs no RPC round trip of
+//! idle hashers.
+//!
+//! When the node supports BIP22 long polling, a second thread keeps a getblocktemplate
+//! request open with the template's `longpollid`. The node answers it as soon as it has
+//! a new template, which is prepared right away instead of at the next tip check.
+//! With `zmq_endpoint` set, the node's new-block notifications do the same for a new tip.
+
+use crate::assembly::AssembledBlock;
+use crate::difficulty;
+use crate::miner;
+use crate::reconnect::{self, Backoff};
+use crate::rest;
+use crate::settings::MinerSettings;
+use crate::stats;
+use crate::zmq;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What wakes the prefetch thread before its next tip check.
+enum Wakeup {
+    /// A template the node answered a long poll with.
+    Template(serde_json::Value),
+    /// A new block announced over ZMQ, by its hash.
+    Block(String),
+    /// A fresh job asked for through `Prefetcher::refresh`.
+    Refresh,
+}
+
+/// A job assembled ahead of time, carried in `MinerSettings::prepared`.
+#[derive(Debug, Clone)]
+pub struct PreparedJob {
+    /// Merkle root of the block, in internal byte order.
+    pub merkle_root: [u8; 32],
+    pub block: AssembledBlock,
+    /// Raised once the next job is ready to replace this one.
+    pub superseded: Arc<AtomicBool>,
+}
+
+/// Keeps the next job ready. Dropping it stops the background thread.
+pub struct Prefetcher {
+    jobs: Receiver<io::Result<MinerSettings>>,
+    wakeups: Sender<Wakeup>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Prefetcher {
+    /// Starts preparing jobs for the node in `settings`.
+    pub fn start(settings: &MinerSettings) -> io::Result<Prefetcher> {
+        let (sender, jobs) = mpsc::channel();
+        let (wakeup_sender, wakeups) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (settings, thread_wakeups, thread_stop) = (settings.clone(), wakeup_sender.clone(), Arc::clone(&stop));
+        thread::Builder::new()
+            .name("prefetch".to_string())
+            .spawn(move || run(settings, sender, thread_wakeups, wakeups, thread_stop))?;
+        Ok(Prefetcher { jobs, wakeups: wakeup_sender, stop })
+    }
+
+    /// Asks for a fresh job now, rather than at the next new block or refresh: after a
+    /// job that ended without one, such as a rejected block.
+    pub fn refresh(&self) {
+        let _ = self.wakeups.send(Wakeup::Refresh);
+    }
+
+    /// The newest prepared job, waiting for one if the last has already been taken.
+    /// Fails if the very first template can't be fetched or assembled.
+    pub fn next_job(&self) -> io::Result<MinerSettings> {
+        let stopped = || io::Error::other("The template prefetcher stopped");
+        let mut job = self.jobs.recv().map_err(|_| stopped())?;
+        while let Ok(newer) = self.jobs.try_recv() {
+            job = newer;
+        }
+        job
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Fetches and assembles a template, or assembles the one a long poll brought, returning
+/// the settings to mine it with and its `superseded` flag.
+fn prepare(
+    settings: &mut MinerSettings,
+    client: &reqwest::blocking::Client,
+    longpolled: Option<serde_json::Value>,
+) -> io::Result<(MinerSettings, Arc<AtomicBool>)> {
+    match longpolled {
+        Some(result) => settings.update_from_longpoll(result, client)?,
+        None => settings.update_from_node()?,
+    }
+    let (merkle_root, block) = miner::assemble_job(settings)?;
+    let superseded = Arc::new(AtomicBool::new(false));
+    let job = MinerSettings {
+        prepared: Some(PreparedJob { merkle_root, block, superseded: Arc::clone(&superseded) }),
+        ..settings.clone()
+    };
+    Ok((job, superseded))
+}
+
+/// The prefetch thread: prepare a job whenever the tip moves, the template is due for
+/// a refresh or about to expire, testnet's minimum-difficulty window opens, or a long
+/// poll or ZMQ brings news, and hand it over.
+fn run(
+    mut settings: MinerSettings,
+    sender: Sender<io::Result<MinerSettings>>,
+    wakeup_sender: Sender<Wakeup>,
+    wakeups: Receiver<Wakeup>,
+    stop: Arc<AtomicBool>,
+) {
+    let client = match settings.http_client() {
+        Ok(client) => client,
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            return;
+        },
+    };
+    let refresh = (settings.template_refresh_secs > 0).then(|| Duration::from_secs(settings.template_refresh_secs));
+    let mut current: Option<(Arc<AtomicBool>, Instant)> = None;
+    let mut due = true;
+    // Set while the node is unreachable: mining waits, and the node is asked again with backoff
+    let mut outage: Option<Backoff> = None;
+    // Templates from the long-poll thread, once it's started, blocks from ZMQ and refresh
+    // requests; the sender is kept so waiting on the channel never fails early
+    let mut longpolling = false;
+    if let Some(endpoint) = &settings.zmq_endpoint {
+        let sender = wakeup_sender.clone();
+        match zmq::watch_blocks(endpoint, Arc::clone(&stop), move |hash| sender.send(Wakeup::Block(hash)).is_ok()) {
+            Ok(()) => println!("[ZMQ] Listening for new blocks at {}.", endpoint),
+            Err(e) => eprintln!("[ZMQ] Could not start listening for new blocks: {}", e),
+        }
+    }
+    let mut longpolled = None;
+    while !stop.load(Ordering::Relaxed) {
+        if due {
+            let started = Instant::now();
+            match prepare(&mut settings, &client, longpolled.take()) {
+                Ok((job, superseded)) => {
+                    if outage.take().is_some() {
+                        println!("[Node] The node is back; resuming with a fresh template.");
+                    }
+                    if !longpolling && let Some(longpollid) = settings.longpollid.clone() {
+                        let (settings, sender, stop) = (settings.clone(), wakeup_sender.clone(), Arc::clone(&stop));
+                        longpolling = thread::Builder::new()
+                            .name("longpoll".to_string())
+                            .spawn(move || longpoll(settings, longpollid, sender, stop))
+                            .is_ok();
+                    }
+                    if sender.send(Ok(job)).is_err() {
+                        break;
+                    }
+                    if let Some((previous, _)) = current.replace((superseded, Instant::now()))
+                        && !previous.swap(true, Ordering::Relaxed)
+                    {
+                        println!("[Prefetch] Next job {} ready in {} ms; switching.", settings.job_id, started.elapsed().as_millis());
+                    }
+                    due = false;
+                },
+                // Checked first, so a node that's down at startup is waited for too
+                Err(e) if reconnect::node_unreachable(&e) => {
+                    // The job could no longer be submitted, so stop hashing it
+                    if let Some((job, _)) = &current {
+                        job.store(true, Ordering::Relaxed);
+                    }
+                    let delay = outage.get_or_insert_with(Backoff::new).next_delay();
+                    eprintln!("[Node] {}. Mining paused; retrying in {}s.", e, delay.as_secs());
+                    thread::sleep(delay);
+                    continue;
+                },
+                // Without a first job there is nothing to mine; after that, keep the current one
+                Err(e) if current.is_none() => {
+                    let _ = sender.send(Err(e));
+                    break;
+                },
+                Err(e) => eprintln!("[Prefetch] Could not prepare the next job: {}. Retrying.", e),
+            }
+        }
+        // Sleep until the next tip check, or until a long poll brings a template this
+        // thread hasn't already fetched itself, or ZMQ a new block
+        let mut new_block = false;
+        match wakeups.recv_timeout(Duration::from_secs(settings.tip_poll_secs.max(1))) {
+            Ok(Wakeup::Template(result)) if result.get("longpollid").and_then(|id| id.as_str()) != settings.longpollid.as_deref() => {
+                longpolled = Some(result);
+                due = true;
+                continue;
+            },
+            Ok(Wakeup::Block(hash)) => new_block = hash != settings.prev_block_hash,
+            Ok(Wakeup::Refresh) => {
+                due = true;
+                continue;
+            },
+            _ => {},
+        }
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let expired = settings.template_expiring()
+            || current.as_ref().is_some_and(|(_, since)| refresh.is_some_and(|refresh| since.elapsed() >= refresh));
+        let min_difficulty = settings.nbits != difficulty::MIN_DIFFICULTY_BITS
+            && settings.min_difficulty_after.is_some_and(|after| stats::unix_now() > u64::from(after));
+        due = due || expired || min_difficulty;
+        if !due && (new_block || tip_moved(&settings, &client)) {
+            due = true;
+            // A server that doesn't allow `prevblock` changes only takes blocks on its
+            // current tip, so the job is dropped before the next one is ready
+            if !settings.mutations.prevblock
+                && let Some((job, _)) = &current
+            {
+                println!("[Prefetch] The tip moved, and the server doesn't allow work on an older one (no \"prevblock\" in mutable).");
+                job.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// The long-poll thread: asks the node for the template after the one with `longpollid`,
+/// which it holds the request for until it has one, and hands each over to the prefetch
+/// thread. Stops if the node answers without waiting, as one without long polling does.
+fn longpoll(settings: MinerSettings, mut longpollid: String, templates: Sender<Wakeup>, stop: Arc<AtomicBool>) {
+    let client = match settings.longpoll_client() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("[Longpoll] Could not start long polling: {}", e);
+            return;
+        },
+    };
+    let mut outage: Option<Backoff> = None;
+    while !stop.load(Ordering::Relaxed) {
+        match settings.longpoll_template(&client, &longpollid) {
+            Ok(result) => {
+                outage = None;
+                let next = result.get("longpollid").and_then(|id| id.as_str()).map(str::to_string);
+                if templates.send(Wakeup::Template(result)).is_err() {
+                    break;
+                }
+                match next {
+                    Some(next) if next != longpollid => longpollid = next,
+                    _ => {
+                        println!("[Longpoll] The node doesn't hold long polls; new templates are picked up at the tip checks instead.");
+                        break;
+                    },
+                }
+            },
+            Err(e) => {
+                let delay = outage.get_or_insert_with(Backoff::new).next_delay();
+                eprintln!("[Longpoll] {}. Retrying in {}s.", e, delay.as_secs());
+                thread::sleep(delay);
+            },
+        }
+    }
+}
+
+/// Whether the node's best block is no longer the one the current template builds on.
+/// An unreachable node counts as moved, so the next fetch notices the outage.
+fn tip_moved(settings: &MinerSettings, client: &reqwest::blocking::Client) -> bool {
+    match rest::chain_query(settings, client, "getbestblockhash", serde_json::json!([])) {
+        Ok(best) => best.as_str().is_some_and(|best| best != settings.prev_block_hash),
+        Err(e) if reconnect::node_unreachable(&e) => true,
+        Err(e) => {
+            eprintln!("[Prefetch] Could not check the chain tip: {}", e);
+            false
+        },
+    }
+}
@@ -0,0 +1,67 @@
+//! Riding out node restarts: while the node can't be reached (or is still starting up),
+//! mining waits and the node is asked again with exponential backoff, then work resumes
+//! on a fresh template.
+
+use crate::settings::MinerSettings;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// First wait after the node stops answering.
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between attempts.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Whether `e` means the node is down or restarting, rather than refusing the request.
+pub fn node_unreachable(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotConnected
+}
+
+/// Delays between reconnection attempts, doubling up to a minute.
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff { next: INITIAL_DELAY }
+    }
+
+    /// The delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_DELAY);
+        delay
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Updates `settings` from the node like `update_from_node`, but waits for an unreachable
+/// node to come back instead of failing. Other errors are returned.
+pub fn update_from_node(settings: &mut MinerSettings) -> io::Result<()> {
+    let mut backoff = Backoff::new();
+    let mut down = false;
+    loop {
+        match settings.update_from_node() {
+            Ok(()) => {
+                if down {
+                    println!("[Node] The node is back; resuming with a fresh template.");
+                }
+                return Ok(());
+            },
+            Err(e) if node_unreachable(&e) => {
+                down = true;
+                let delay = backoff.next_delay();
+                eprintln!("[Node] {}. Mining paused; retrying in {}s.", e, delay.as_secs());
+                thread::sleep(delay);
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::settings::MinerSettings;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once the fallback has been announced, so it's only reported the first time.
+static FALLBACK_ANNOUNCED: AtomicBool = AtomicBool::new(false);
+
+/// Makes a read-only chain query over RPC, falling back to bitcoind's
+/// unauthenticated REST interface (`rest=1`) when the RPC user isn't allowed to
+/// make it, e.g. credentials whitelisted for submitblock only.
+///
+/// Only the queries used to follow the chain have a REST equivalent:
+/// getblockchaininfo, getbestblockhash, getblockhash and getblockheader.
+pub fn chain_query(settings: &MinerSettings, client: &reqwest::blocking::Client, method: &str, params: serde_json::Value) -> io::Result<serde_json::Value> {
+    let rpc_error = match settings.rpc_call(client, method, params.clone()) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => e,
+        other => return other,
+    };
+    if !FALLBACK_ANNOUNCED.swap(true, Ordering::Relaxed) {
+        println!("[REST] {} Following the chain over the REST interface instead.", rpc_error);
+    }
+    rest_query(settings, client, method, &params)
+        .map_err(|e| io::Error::new(e.kind(), format!("{} (REST fallback also failed: {})", rpc_error, e)))
+}
+
+/// Answers an RPC chain query from the equivalent REST endpoint.
+fn rest_query(settings: &MinerSettings, client: &reqwest::blocking::Client, method: &str, params: &serde_json::Value) -> io::Result<serde_json::Value> {
+    let param = |i: usize| params.get(i).cloned().unwrap_or(serde_json::Value::Null);
+    match method {
+        "getblockchaininfo" => get(settings, client, "chaininfo.json"),
+        "getbestblockhash" => {
+            let info = get(settings, client, "chaininfo.json")?;
+            Ok(info["bestblockhash"].clone())
+        },
+        "getblockhash" => {
+            let height = param(0).as_u64().ok_or_else(|| invalid("getblockhash needs a height"))?;
+            let result = get(settings, client, &format!("blockhashbyheight/{}.json", height))?;
+            Ok(result["blockhash"].clone())
+        },
+        "getblockheader" => {
+            let hash = param(0).as_str().map(str::to_string).ok_or_else(|| invalid("getblockheader needs a hash"))?;
+            let headers = get(settings, client, &format!("headers/{}.json?count=1", hash))?;
+            headers.get(0).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Block {} not found", hash)))
+        },
+        _ => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} has no REST equivalent", method))),
+    }
+}
+
+/// GETs `/rest/<path>` from the node behind `rpc_url`.
+fn get(settings: &MinerSettings, client: &reqwest::blocking::Client, path: &str) -> io::Result<serde_json::Value> {
+    let mut url = settings.rpc_endpoint()?;
+    url.set_query(None);
+    // A wallet path (/wallet/<name>) is part of the RPC endpoint only; REST lives at the root.
+    let url = url.join(&format!("/rest/{}", path)).map_err(|e| invalid(&e.to_string()))?;
+
+    let host = crate::settings::display_host(&url);
+    if settings.rpc_trace {
+        settings.trace(&format!("-> GET {} {}", host, url.path()));
+    }
+    let response = client.get(url).send()
+        .map_err(|e| io::Error::other(format!("REST request to {} failed: {}", host, e.without_url())))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND && path.starts_with("chaininfo") {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "REST interface is disabled; start the node with rest=1"));
+    }
+    let body = response.text().unwrap_or_default();
+    if settings.rpc_trace {
+        settings.trace(&format!("<- {} {}", status, body));
+    }
+    if !status.is_success() {
+        return Err(io::Error::other(format!("REST error: {} - {}", status, body.trim())));
+    }
+    serde_json::from_str(&body).map_err(|e| invalid(&format!("Failed to parse REST response: {}", e)))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
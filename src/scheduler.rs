@@ -0,0 +1,242 @@
+//! Runs several hashing devices against the same job: CPU threads and GPUs. The scheduler hands each device disjoint slices of the
+//! job's search space (nonce ranges, then rolled versions once the nonces run out),
+//! so devices of any speed can share a job without repeating each other's work.
+
+use crate::hasher::Hasher;
+use crate::pow;
+use crate::settings::MinerSettings;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Nonces in a CPU slice: the header is rebuilt, with a fresh timestamp, once per slice.
+pub const CPU_SLICE_SIZE: u32 = 1 << 16;
+
+/// Something that hashes slices of a job: a CPU thread, or a GPU.
+pub trait Device: Send {
+    /// Name for messages, e.g. "cpu0".
+    fn name(&self) -> &str;
+
+    /// Nonces to hand out at a time. Larger slices suit devices with a high launch cost.
+    fn slice_size(&self) -> u32;
+
+    /// Hashes `header` with every nonce in `nonces`, calling `found` with each nonce
+    /// whose hash (internal byte order) meets `target`. An error takes the device out of the job.
+    fn scan(&mut self, header: &[u8; 80], nonces: RangeInclusive<u32>, target: &[u8; 32], found: &mut dyn FnMut(u32, [u8; 32])) -> io::Result<()>;
+}
+
+/// One mining thread hashing with `H`.
+pub struct CpuDevice<H> {
+    name: String,
+    hasher: PhantomData<fn() -> H>,
+}
+
+impl<H: Hasher> CpuDevice<H> {
+    pub fn new(index: usize) -> Self {
+        CpuDevice { name: format!("cpu{}", index), hasher: PhantomData }
+    }
+}
+
+impl<H: Hasher> Device for CpuDevice<H> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn slice_size(&self) -> u32 {
+        CPU_SLICE_SIZE
+    }
+
+    fn scan(&mut self, header: &[u8; 80], nonces: RangeInclusive<u32>, target: &[u8; 32], found: &mut dyn FnMut(u32, [u8; 32])) -> io::Result<()> {
+        let mut header = *header;
+        let last = *nonces.end();
+        let mut next = *nonces.start();
+        while let Some((nonce, hash)) = H::scan(&mut header, next..=last, target) {
+            found(nonce, hash);
+            match nonce.checked_add(1) {
+                Some(n) if n <= last => next = n,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets up the GPUs selected in `settings`, which then hash every job next to the CPU
+/// threads. GPUs that can't be used are reported and left out.
+pub fn gpu_devices(settings: &MinerSettings) -> Vec<Box<dyn Device>> {
+    #[allow(unused_mut)]
+    let mut devices: Vec<Box<dyn Device>> = Vec::new();
+    if let Some(indices) = &settings.opencl_devices {
+        #[cfg(feature = "gpu-opencl")]
+        match crate::opencl::devices(indices) {
+            Ok(gpus) => devices.extend(gpus.into_iter().map(|gpu| Box::new(gpu) as Box<dyn Device>)),
+            Err(e) => eprintln!("[OpenCL] Mining without OpenCL GPUs: {}", e),
+        }
+        #[cfg(not(feature = "gpu-opencl"))]
+        {
+            let _ = indices;
+            eprintln!("Ignoring opencl_devices: rebuild with `--features gpu-opencl` to hash on GPUs.");
+        }
+    }
+    if let Some(indices) = &settings.cuda_devices {
+        #[cfg(feature = "gpu-cuda")]
+        match crate::cuda::devices(indices) {
+            Ok(gpus) => devices.extend(gpus.into_iter().map(|gpu| Box::new(gpu) as Box<dyn Device>)),
+            Err(e) => eprintln!("[CUDA] Mining without CUDA GPUs: {}", e),
+        }
+        #[cfg(not(feature = "gpu-cuda"))]
+        {
+            let _ = indices;
+            eprintln!("Ignoring cuda_devices: rebuild with `--features gpu-cuda` to hash on NVIDIA GPUs.");
+        }
+    }
+    devices
+}
+
+/// Where in a job's nonces the search starts (the `nonce_start` setting). Wherever it
+/// starts, the search wraps around to cover every nonce before a version is rolled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum NonceStart {
+    /// At the first nonce.
+    #[default]
+    Zero,
+    /// At a random nonce, drawn anew for every job, so restarts don't redo the same work.
+    Random,
+    /// At this offset into the job's nonces.
+    Fixed(u32),
+    /// Only within share `index` of `count` equal shares of the job's nonces, from its
+    /// start: instances given different indexes never hash the same nonces.
+    Stride { index: u32, count: u32 },
+}
+
+impl NonceStart {
+    /// Why the setting can't be used, if it can't.
+    pub fn check(&self) -> Result<(), String> {
+        match *self {
+            NonceStart::Stride { index, count } if index >= count => Err(format!(
+                "stride index {} must be below its count {} (instances are numbered from 0)", index, count
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The nonces of a job allowing `nonces` to search, and the one to start at.
+    pub fn plan(&self, nonces: &RangeInclusive<u32>) -> (RangeInclusive<u32>, u32) {
+        let (first, last) = (u64::from(*nonces.start()), u64::from(*nonces.end()));
+        let span = last - first + 1;
+        match *self {
+            NonceStart::Zero => (nonces.clone(), *nonces.start()),
+            NonceStart::Random => {
+                let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+                let seed = pow::sha256d(&[nanos.to_le_bytes().as_slice(), &std::process::id().to_le_bytes()].concat());
+                let offset = u64::from_le_bytes(seed[..8].try_into().unwrap()) % span;
+                (nonces.clone(), (first + offset) as u32)
+            },
+            NonceStart::Fixed(offset) => (nonces.clone(), (first + u64::from(offset) % span) as u32),
+            NonceStart::Stride { index, count } => {
+                let count = u64::from(count.max(1));
+                let index = u64::from(index).min(count - 1);
+                let start = first + span * index / count;
+                let end = first + span * (index + 1) / count - 1;
+                (start as u32..=end as u32, start as u32)
+            },
+        }
+    }
+}
+
+/// A piece of the search space: a header version and a range of nonces to try with it.
+#[derive(Debug, Clone)]
+pub struct Slice {
+    pub version: u32,
+    pub nonces: RangeInclusive<u32>,
+}
+
+/// Hands out disjoint slices of one job, from the nonces the job allows: from `first` to
+/// their end, then from their start up to `first`. Once they are used up for a version,
+/// the next version is rolled within `version_mask` (BIP320).
+pub struct Scheduler {
+    base_version: u32,
+    version_mask: u32,
+    nonces: RangeInclusive<u32>,
+    first: u32,
+    /// Versions rolled so far, and how many of the version's nonces were handed out.
+    next: Mutex<(u32, u64)>,
+}
+
+impl Scheduler {
+    pub fn new(version: u32, version_mask: u32, nonces: RangeInclusive<u32>, first: u32) -> Self {
+        let first = first.clamp(*nonces.start(), *nonces.end());
+        Scheduler { base_version: version, version_mask, nonces, first, next: Mutex::new((0, 0)) }
+    }
+
+    /// The next `size` nonces, or fewer at the end of the nonces or of a version's nonces.
+    /// `None` once the whole search space has been handed out.
+    pub fn next_slice(&self, size: u32) -> Option<Slice> {
+        let (start, end) = (u64::from(*self.nonces.start()), u64::from(*self.nonces.end()));
+        let span = end - start + 1;
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        let (rolls, handed_out) = &mut *next;
+        if *handed_out >= span {
+            let rolled = pow::roll_version(self.base_version, self.version_mask, *rolls + 1)?;
+            println!("[Mining] Nonce space exhausted; rolling the version to {:08x}.", rolled);
+            *rolls += 1;
+            *handed_out = 0;
+        }
+        let version = pow::roll_version(self.base_version, self.version_mask, *rolls)?;
+        let slice_start = start + (u64::from(self.first) - start + *handed_out) % span;
+        let len = u64::from(size.max(1)).min(span - *handed_out).min(end - slice_start + 1);
+        *handed_out += len;
+        Some(Slice { version, nonces: slice_start as u32..=(slice_start + len - 1) as u32 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_and_fixed_starts_keep_every_nonce() {
+        assert_eq!(NonceStart::Zero.plan(&(0..=u32::MAX)), (0..=u32::MAX, 0));
+        assert_eq!(NonceStart::Zero.plan(&(100..=200)), (100..=200, 100));
+        assert_eq!(NonceStart::Fixed(5).plan(&(100..=200)), (100..=200, 105));
+        // Offsets past the end wrap around into the job's nonces.
+        assert_eq!(NonceStart::Fixed(101).plan(&(100..=200)), (100..=200, 100));
+        assert_eq!(NonceStart::Fixed(u32::MAX).plan(&(0..=u32::MAX)), (0..=u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn random_starts_stay_within_the_nonces() {
+        for _ in 0..100 {
+            let (nonces, start) = NonceStart::Random.plan(&(100..=200));
+            assert_eq!(nonces, 100..=200);
+            assert!(nonces.contains(&start));
+        }
+        assert_eq!(NonceStart::Random.plan(&(7..=7)), (7..=7, 7));
+    }
+
+    #[test]
+    fn strides_split_the_nonces_without_gaps_or_overlap() {
+        for (nonces, count) in [(0..=u32::MAX, 3), (100..=200, 4), (0..=9, 10), (5..=5, 1)] {
+            let mut next = u64::from(*nonces.start());
+            for index in 0..count {
+                let (stride, start) = NonceStart::Stride { index, count }.plan(&nonces);
+                assert_eq!(u64::from(*stride.start()), next, "stride {} of {} over {:?}", index, count, nonces);
+                assert_eq!(start, *stride.start());
+                next = u64::from(*stride.end()) + 1;
+            }
+            assert_eq!(next, u64::from(*nonces.end()) + 1);
+        }
+    }
+
+    #[test]
+    fn stride_index_must_be_below_its_count() {
+        assert!(NonceStart::Stride { index: 1, count: 2 }.check().is_ok());
+        assert!(NonceStart::Stride { index: 2, count: 2 }.check().is_err());
+        assert!(NonceStart::Stride { index: 0, count: 0 }.check().is_err());
+        assert!(NonceStart::Fixed(u32::MAX).check().is_ok());
+    }
+}
@@ -0,0 +1,73 @@
+use crate::control::{MinerControl, Profile};
+use crate::events::{EventBus, MinerEvent};
+use rhai::{AST, Engine, Scope};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+
+/// Loads a rhai script and subscribes it to the event bus.
+///
+/// The script may define `fn on_event(event)`; `event` is a map with an `event`
+/// field naming the event ("BlockFound", "ShareFound", ...) plus that event's fields.
+/// Scripts steer the miner through these functions:
+/// `pause()`, `resume()`, `throttle(percent)`, `is_paused()`, and `switch_profile(name)`,
+/// which switches to one of `profiles` and returns false for an unknown name, and
+/// `profile()`, the name of the active profile ("" before any switch).
+pub fn install(bus: &EventBus, control: &MinerControl, script_path: &str, profiles: &BTreeMap<String, Profile>) -> io::Result<()> {
+    let mut engine = Engine::new();
+
+    let c = control.clone();
+    engine.register_fn("pause", move || c.pause());
+    let c = control.clone();
+    engine.register_fn("resume", move || c.resume());
+    let c = control.clone();
+    engine.register_fn("throttle", move |percent: i64| c.set_throttle(percent.clamp(1, 100) as u8));
+    let c = control.clone();
+    engine.register_fn("is_paused", move || c.is_paused());
+    let (c, profiles) = (control.clone(), profiles.clone());
+    engine.register_fn("switch_profile", move |name: &str| match profiles.get(name) {
+        Some(profile) => {
+            println!("[Script] Switching to profile '{}'.", name);
+            c.switch_profile(name, profile);
+            true
+        },
+        None => {
+            eprintln!("[Script] No profile named '{}' in profiles.", name);
+            false
+        },
+    });
+    let c = control.clone();
+    engine.register_fn("profile", move || c.profile_name().unwrap_or_default());
+
+    let ast: AST = engine.compile_file(script_path.into())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Could not load script {}: {}", script_path, e)))?;
+
+    // Run top-level statements once (initial setup), keeping any globals they define.
+    let mut scope = Scope::new();
+    engine.run_ast_with_scope(&mut scope, &ast)
+        .map_err(|e| io::Error::other(format!("Script {} failed to initialize: {}", script_path, e)))?;
+
+    let has_handler = ast.iter_functions().any(|f| f.name == "on_event" && f.params.len() == 1);
+    if !has_handler {
+        println!("[Script] {} defines no on_event(event) function; it will only run once.", script_path);
+        return Ok(());
+    }
+
+    let state = Mutex::new((engine, ast, scope));
+    bus.subscribe(move |event: &MinerEvent| {
+        let mut guard = state.lock().unwrap_or_else(|e| e.into_inner());
+        let (engine, ast, scope) = &mut *guard;
+        let argument = match rhai::serde::to_dynamic(event) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("[Script] Could not convert event for the script: {}", e);
+                return;
+            },
+        };
+        if let Err(e) = engine.call_fn::<rhai::Dynamic>(scope, ast, "on_event", (argument,)) {
+            eprintln!("[Script] on_event failed: {}", e);
+        }
+    });
+    println!("[Script] Loaded {}.", script_path);
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! Running as a Windows service (the `service` subcommand): `service install` registers
+//! the miner with the service manager, to mine in the directory it was installed from,
+//! and `service uninstall` removes it. Started by the service manager, the miner runs as
+//! an embedded `Miner` that follows the service's stop, pause and continue controls, and
+//! reports to the Windows event log instead of a console.
+
+use crate::engine::Miner;
+use crate::events::MinerEvent;
+use crate::instance::InstanceLock;
+use crate::settings::MinerSettings;
+use std::ffi::OsString;
+use std::io;
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType,
+    ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+    RegisterEventSourceW, ReportEventW,
+};
+
+/// Name of the service, and of its event log source.
+pub const SERVICE_NAME: &str = "SoloLotteryMiner";
+
+/// How long the service manager should wait for the miner to stop.
+const STOP_WAIT_HINT: Duration = Duration::from_secs(30);
+
+fn service_error(e: windows_service::Error) -> io::Error {
+    io::Error::other(format!("Windows service manager: {}", e))
+}
+
+/// Handles `service install`, `service uninstall` and `service run --dir DIR`; the
+/// last is what the service manager starts.
+pub fn command(args: &[String]) -> io::Result<()> {
+    match args.first().map(String::as_str) {
+        Some("install") => install(),
+        Some("uninstall") => uninstall(),
+        Some("run") => {
+            let dir = args.iter().skip_while(|arg| *arg != "--dir").nth(1)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "service run needs --dir DIR"))?;
+            // Services start in the system directory; the config and stats live in DIR
+            std::env::set_current_dir(dir)?;
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(service_error)
+        },
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: service install | service uninstall")),
+    }
+}
+
+/// Registers the service, started automatically at boot, mining in the current directory.
+fn install() -> io::Result<()> {
+    let dir = std::env::current_dir()?;
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)
+        .map_err(service_error)?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Solo Lottery Miner"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![OsString::from("service"), OsString::from("run"), OsString::from("--dir"), dir.clone().into_os_string()],
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG).map_err(service_error)?;
+    service.set_description("Educational Bitcoin solo miner").map_err(service_error)?;
+    println!("[Service] Installed {} to mine in {}.", SERVICE_NAME, dir.display());
+    println!("[Service] Start it with `sc start {}` or from services.msc. The RPC password must come from bitcoin_conf or miner_config.json: a service can't prompt for it.", SERVICE_NAME);
+    Ok(())
+}
+
+/// Stops the service if it's running and removes it.
+fn uninstall() -> io::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).map_err(service_error)?;
+    let service = manager
+        .open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+        .map_err(service_error)?;
+    if service.query_status().map_err(service_error)?.current_state != ServiceState::Stopped {
+        service.stop().map_err(service_error)?;
+    }
+    service.delete().map_err(service_error)?;
+    println!("[Service] Removed {}.", SERVICE_NAME);
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    let log = EventLog::open();
+    if let Err(e) = run_service(&log) {
+        log.report(EVENTLOG_ERROR_TYPE, &format!("The miner stopped: {}", e));
+    }
+}
+
+/// Mines until the service manager says stop, pausing and resuming on request.
+fn run_service(log: &EventLog) -> io::Result<()> {
+    let (controls, requests) = mpsc::channel();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Pause | ServiceControl::Continue => {
+            let _ = controls.send(control);
+            ServiceControlHandlerResult::NoError
+        },
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status = service_control_handler::register(SERVICE_NAME, handler).map_err(service_error)?;
+
+    let result = mine(log, &status, &requests);
+    set_state(&status, ServiceState::Stopped, ServiceExitCode::Win32(if result.is_ok() { 0 } else { 1 }))?;
+    result
+}
+
+fn mine(log: &EventLog, status: &ServiceStatusHandle, requests: &mpsc::Receiver<ServiceControl>) -> io::Result<()> {
+    let _lock = InstanceLock::acquire()?;
+    let mut settings = MinerSettings::load()?;
+    settings.ensure_reward_address()?;
+    let events = log.clone();
+    let mut miner = Miner::builder()
+        .settings(settings)
+        .on_event(move |event| match event {
+            MinerEvent::BlockFound { job_id, hash, .. } => {
+                events.report(EVENTLOG_INFORMATION_TYPE, &format!("Block found on job {}: {}", job_id, hash));
+            },
+            MinerEvent::SubmitResult { job_id, hash, accepted: true, .. } => {
+                events.report(EVENTLOG_INFORMATION_TYPE, &format!("Block {} (job {}) accepted by the node.", hash, job_id));
+            },
+            MinerEvent::SubmitResult { job_id, hash, accepted: false, detail } => {
+                events.report(EVENTLOG_WARNING_TYPE, &format!("Block {} (job {}) rejected by the node: {}", hash, job_id, detail));
+            },
+            MinerEvent::Error { message } => events.report(EVENTLOG_WARNING_TYPE, message),
+            MinerEvent::TemplateReceived { .. } | MinerEvent::ShareFound { .. } => {},
+        })
+        .build()?;
+    miner.start()?;
+    set_state(status, ServiceState::Running, ServiceExitCode::Win32(0))?;
+    log.report(EVENTLOG_INFORMATION_TYPE, &format!("Mining in {}.", std::env::current_dir()?.display()));
+
+    loop {
+        match requests.recv() {
+            Ok(ServiceControl::Pause) => {
+                miner.pause();
+                set_state(status, ServiceState::Paused, ServiceExitCode::Win32(0))?;
+                log.report(EVENTLOG_INFORMATION_TYPE, "Mining paused.");
+            },
+            Ok(ServiceControl::Continue) => {
+                miner.resume();
+                set_state(status, ServiceState::Running, ServiceExitCode::Win32(0))?;
+                log.report(EVENTLOG_INFORMATION_TYPE, "Mining resumed.");
+            },
+            Ok(_) | Err(_) => break,
+        }
+    }
+    set_state(status, ServiceState::StopPending, ServiceExitCode::Win32(0))?;
+    miner.stop()?;
+    log.report(EVENTLOG_INFORMATION_TYPE, "Mining stopped.");
+    Ok(())
+}
+
+fn set_state(status: &ServiceStatusHandle, state: ServiceState, exit_code: ServiceExitCode) -> io::Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running | ServiceState::Paused => ServiceControlAccept::STOP | ServiceControlAccept::PAUSE_CONTINUE,
+        _ => ServiceControlAccept::empty(),
+    };
+    status
+        .set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: if state == ServiceState::StopPending { STOP_WAIT_HINT } else { Duration::default() },
+            process_id: None,
+        })
+        .map_err(service_error)
+}
+
+/// The service's event log source. The source isn't registered with a message file, so
+/// Event Viewer shows each entry's text with a note that its description is missing.
+#[derive(Clone)]
+struct EventLog(Arc<EventSource>);
+
+struct EventSource(HANDLE);
+
+// SAFETY: event log handles may be used from any thread.
+unsafe impl Send for EventSource {}
+unsafe impl Sync for EventSource {}
+
+impl EventLog {
+    fn open() -> Self {
+        let name = wide(SERVICE_NAME);
+        // SAFETY: `name` is a NUL-terminated UTF-16 string that outlives the call.
+        let handle = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+        EventLog(Arc::new(EventSource(handle)))
+    }
+
+    fn report(&self, kind: REPORT_EVENT_TYPE, message: &str) {
+        if self.0.0.is_null() {
+            return;
+        }
+        let text = wide(message);
+        let strings = [text.as_ptr()];
+        // SAFETY: the handle is open, and `strings` holds one NUL-terminated string that
+        // outlives the call.
+        unsafe {
+            ReportEventW(self.0.0, kind, 0, 0, std::ptr::null_mut(), 1, 0, strings.as_ptr(), std::ptr::null());
+        }
+    }
+}
+
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: the handle came from RegisterEventSourceW and is closed once.
+            unsafe {
+                DeregisterEventSource(self.0);
+            }
+        }
+    }
+}
+
+/// A NUL-terminated UTF-16 copy of `text`.
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
@@ -0,0 +1,353 @@
+//! Batch double SHA-256 for the nonce scan: several nonces per pass, one in each lane of
+//! a vector register, with AVX2 (8 lanes) on x86-64 or NEON (4 lanes) on AArch64. The
+//! vector unit is detected at runtime; without one, scans run on the scalar midstate path.
+//!
+//! A batch only computes the top 32 bits of each hash. Those decide nearly every
+//! comparison with the target, and the few nonces they don't rule out are hashed again
+//! on the scalar path, which has the final say.
+
+use crate::pow;
+use core::ops::RangeInclusive;
+
+/// SHA-256 round constants (FIPS 180-4, 4.2.2).
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Second-block padding for an 80-byte header, and first-block padding for a 32-byte digest.
+const HEADER_BITS: u32 = 80 * 8;
+const DIGEST_BITS: u32 = 32 * 8;
+
+/// The vector unit of this CPU, if the miner has a batch path for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorUnit {
+    Avx2,
+    Neon,
+}
+
+impl VectorUnit {
+    /// The unit to batch on, checked at runtime.
+    pub fn detect() -> Option<VectorUnit> {
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("avx2") {
+            return Some(VectorUnit::Avx2);
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Some(VectorUnit::Neon);
+        }
+        None
+    }
+
+}
+
+/// The nonce scan of `pow::scan_midstate`, batched across the lanes of the vector unit.
+/// Returns the same nonce and hash.
+pub fn scan_midstate(midstate: &[u32; 8], tail: &[u8; 12], nonces: RangeInclusive<u32>, target: &[u8; 32]) -> Option<(u32, [u8; 32])> {
+    let tail_words: [u32; 3] = core::array::from_fn(|i| u32::from_be_bytes(tail[i * 4..i * 4 + 4].try_into().unwrap()));
+    match VectorUnit::detect() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: AVX2 was detected on this CPU.
+        Some(VectorUnit::Avx2) => scan_batches(midstate, tail, nonces, target, |first| unsafe { avx2::top_words(midstate, &tail_words, first) }),
+        #[cfg(target_arch = "aarch64")]
+        // SAFETY: NEON was detected on this CPU.
+        Some(VectorUnit::Neon) => scan_batches(midstate, tail, nonces, target, |first| unsafe { neon::top_words(midstate, &tail_words, first) }),
+        _ => pow::scan_midstate(midstate, tail, nonces, target),
+    }
+}
+
+/// Runs `batch` over `nonces`, `LANES` at a time, checking every lane whose top word
+/// could meet `target` on the scalar path. Nonces left over at the end are hashed there too.
+fn scan_batches<const LANES: usize>(
+    midstate: &[u32; 8],
+    tail: &[u8; 12],
+    nonces: RangeInclusive<u32>,
+    target: &[u8; 32],
+    batch: impl Fn(u32) -> [u32; LANES],
+) -> Option<(u32, [u8; 32])> {
+    if nonces.is_empty() {
+        return None;
+    }
+    let target_top = u32::from_be_bytes(target[..4].try_into().unwrap());
+    let (mut first, last) = (*nonces.start(), *nonces.end());
+    while last - first >= LANES as u32 - 1 {
+        for (lane, top) in batch(first).into_iter().enumerate() {
+            if top <= target_top {
+                let nonce = first + lane as u32;
+                let hash = pow::sha256d_midstate(midstate, tail, nonce);
+                if pow::meets_target(&hash, target) {
+                    return Some((nonce, hash));
+                }
+            }
+        }
+        match (first + LANES as u32 - 1).checked_add(1) {
+            Some(next) if next <= last => first = next,
+            _ => return None,
+        }
+    }
+    pow::scan_midstate(midstate, tail, first..=last, target)
+}
+
+/// SHA-256 over the lanes of one vector type. Expects, in scope: `V`, `LANES`, `splat`,
+/// `add`, `xor`, `and`, `andnot` (`!a & b`), `rotr::<R, L>` (L = 32 - R), `shr::<N>`,
+/// `load` and `store`, and the target feature to compile them with.
+macro_rules! sha256_lanes {
+    ($feature:literal) => {
+        /// One SHA-256 compression of a 16-word block in each lane.
+        #[target_feature(enable = $feature)]
+        fn compress(state: &mut [V; 8], mut w: [V; 16]) {
+            let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+            for (i, k) in super::K.into_iter().enumerate() {
+                if i >= 16 {
+                    let (w2, w15) = (w[(i - 2) % 16], w[(i - 15) % 16]);
+                    let s0 = xor(xor(rotr::<7, 25>(w15), rotr::<18, 14>(w15)), shr::<3>(w15));
+                    let s1 = xor(xor(rotr::<17, 15>(w2), rotr::<19, 13>(w2)), shr::<10>(w2));
+                    w[i % 16] = add(add(w[i % 16], s0), add(w[(i - 7) % 16], s1));
+                }
+                let s1 = xor(xor(rotr::<6, 26>(e), rotr::<11, 21>(e)), rotr::<25, 7>(e));
+                let ch = xor(and(e, f), andnot(e, g));
+                let t1 = add(add(h, s1), add(add(ch, splat(k)), w[i % 16]));
+                let s0 = xor(xor(rotr::<2, 30>(a), rotr::<13, 19>(a)), rotr::<22, 10>(a));
+                let maj = xor(and(a, b), and(c, xor(a, b)));
+                h = g;
+                g = f;
+                f = e;
+                e = add(d, t1);
+                d = c;
+                c = b;
+                b = a;
+                a = add(t1, add(s0, maj));
+            }
+            for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+                *word = add(*word, value);
+            }
+        }
+
+        /// The top 32 bits (as compared with a target) of the block hash for `LANES`
+        /// consecutive nonces from `first`, given the header's midstate and tail words.
+        #[target_feature(enable = $feature)]
+        pub fn top_words(midstate: &[u32; 8], tail: &[u32; 3], first: u32) -> [u32; LANES] {
+            // The nonce is little-endian in the header, but read as a big-endian word
+            let nonces: [u32; LANES] = core::array::from_fn(|lane| first.wrapping_add(lane as u32).swap_bytes());
+            let mut block = [splat(0); 16];
+            for (word, value) in block.iter_mut().zip(tail) {
+                *word = splat(*value);
+            }
+            block[3] = load(nonces);
+            block[4] = splat(0x8000_0000);
+            block[15] = splat(super::HEADER_BITS);
+            let mut state = [splat(0); 8];
+            for (word, value) in state.iter_mut().zip(midstate) {
+                *word = splat(*value);
+            }
+            compress(&mut state, block);
+
+            let mut block = [splat(0); 16];
+            block[..8].copy_from_slice(&state);
+            block[8] = splat(0x8000_0000);
+            block[15] = splat(super::DIGEST_BITS);
+            let mut digest = [splat(0); 8];
+            for (word, value) in digest.iter_mut().zip(pow::SHA256_IV) {
+                *word = splat(value);
+            }
+            compress(&mut digest, block);
+            // The hash's most significant bytes are the last word, byte-reversed
+            store(digest[7]).map(u32::swap_bytes)
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use crate::pow;
+    use std::arch::x86_64::*;
+
+    type V = __m256i;
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "avx2")]
+    fn splat(x: u32) -> V {
+        _mm256_set1_epi32(x as i32)
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn add(a: V, b: V) -> V {
+        _mm256_add_epi32(a, b)
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn xor(a: V, b: V) -> V {
+        _mm256_xor_si256(a, b)
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn and(a: V, b: V) -> V {
+        _mm256_and_si256(a, b)
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn andnot(a: V, b: V) -> V {
+        _mm256_andnot_si256(a, b)
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn rotr<const R: i32, const L: i32>(x: V) -> V {
+        _mm256_or_si256(_mm256_srli_epi32::<R>(x), _mm256_slli_epi32::<L>(x))
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn shr<const N: i32>(x: V) -> V {
+        _mm256_srli_epi32::<N>(x)
+    }
+
+    fn load(words: [u32; LANES]) -> V {
+        // SAFETY: both are 32 bytes, and every bit pattern is valid for each.
+        unsafe { core::mem::transmute(words) }
+    }
+
+    fn store(v: V) -> [u32; LANES] {
+        // SAFETY: as in `load`.
+        unsafe { core::mem::transmute(v) }
+    }
+
+    sha256_lanes!("avx2");
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use crate::pow;
+    use std::arch::aarch64::*;
+
+    type V = uint32x4_t;
+    const LANES: usize = 4;
+
+    #[target_feature(enable = "neon")]
+    fn splat(x: u32) -> V {
+        vdupq_n_u32(x)
+    }
+
+    #[target_feature(enable = "neon")]
+    fn add(a: V, b: V) -> V {
+        vaddq_u32(a, b)
+    }
+
+    #[target_feature(enable = "neon")]
+    fn xor(a: V, b: V) -> V {
+        veorq_u32(a, b)
+    }
+
+    #[target_feature(enable = "neon")]
+    fn and(a: V, b: V) -> V {
+        vandq_u32(a, b)
+    }
+
+    #[target_feature(enable = "neon")]
+    fn andnot(a: V, b: V) -> V {
+        // Bit clear: b with the bits of a cleared
+        vbicq_u32(b, a)
+    }
+
+    #[target_feature(enable = "neon")]
+    fn rotr<const R: i32, const L: i32>(x: V) -> V {
+        vorrq_u32(vshrq_n_u32::<R>(x), vshlq_n_u32::<L>(x))
+    }
+
+    #[target_feature(enable = "neon")]
+    fn shr<const N: i32>(x: V) -> V {
+        vshrq_n_u32::<N>(x)
+    }
+
+    fn load(words: [u32; LANES]) -> V {
+        // SAFETY: both are 16 bytes, and every bit pattern is valid for each.
+        unsafe { core::mem::transmute(words) }
+    }
+
+    fn store(v: V) -> [u32; LANES] {
+        // SAFETY: as in `load`.
+        unsafe { core::mem::transmute(v) }
+    }
+
+    sha256_lanes!("neon");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> ([u32; 8], [u8; 12]) {
+        let header = pow::build_header(0x2000_0000, &[7u8; 32], &[9u8; 32], 1_700_000_000, 0x1d00ffff, 0);
+        (pow::midstate(&header), header[64..76].try_into().unwrap())
+    }
+
+    /// The big-endian target a hash (internal order) exactly meets.
+    fn target_of(hash: [u8; 32]) -> [u8; 32] {
+        let mut target = hash;
+        target.reverse();
+        target
+    }
+
+    #[test]
+    fn top_words_match_the_scalar_hash() {
+        let (midstate, tail) = job();
+        let tail_words: [u32; 3] = core::array::from_fn(|i| u32::from_be_bytes(tail[i * 4..i * 4 + 4].try_into().unwrap()));
+        let top = |nonce: u32| u32::from_be_bytes(target_of(pow::sha256d_midstate(&midstate, &tail, nonce))[..4].try_into().unwrap());
+        for first in [0, 1000, u32::MAX - 7] {
+            match VectorUnit::detect() {
+                #[cfg(target_arch = "x86_64")]
+                // SAFETY: AVX2 was detected on this CPU.
+                Some(VectorUnit::Avx2) => {
+                    let words = unsafe { avx2::top_words(&midstate, &tail_words, first) };
+                    assert_eq!(words, core::array::from_fn(|lane| top(first.wrapping_add(lane as u32))));
+                },
+                #[cfg(target_arch = "aarch64")]
+                // SAFETY: NEON was detected on this CPU.
+                Some(VectorUnit::Neon) => {
+                    let words = unsafe { neon::top_words(&midstate, &tail_words, first) };
+                    assert_eq!(words, core::array::from_fn(|lane| top(first.wrapping_add(lane as u32))));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    #[test]
+    fn scan_midstate_matches_the_scalar_scan() {
+        let (midstate, tail) = job();
+        let easy = pow::compact_to_target(0x207fffff);
+        // Met by nonce 37 exactly (the top words tie) and by few others, if any, below it.
+        let tight = target_of(pow::sha256d_midstate(&midstate, &tail, 37));
+        let ranges = [0..=0, 0..=6, 0..=100, 3..=40, 37..=37, 38..=45, RangeInclusive::new(5, 4), u32::MAX - 10..=u32::MAX];
+        for target in [easy, tight, pow::DIFF1_TARGET.to_be_bytes(), [0u8; 32]] {
+            for nonces in ranges.clone() {
+                assert_eq!(
+                    scan_midstate(&midstate, &tail, nonces.clone(), &target),
+                    pow::scan_midstate(&midstate, &tail, nonces.clone(), &target),
+                    "nonces {:?}", nonces
+                );
+            }
+        }
+        assert!(scan_midstate(&midstate, &tail, 0..=100, &tight).is_some_and(|(nonce, _)| nonce <= 37));
+    }
+
+    #[test]
+    fn scan_batches_checks_every_lane_and_the_leftovers() {
+        // A fake batch whose top words rule nothing out leaves every decision to the
+        // scalar path, so the result must be the scalar scan's.
+        let (midstate, tail) = job();
+        let tight = target_of(pow::sha256d_midstate(&midstate, &tail, 37));
+        for nonces in [0..=100, 30..=38, 36..=37, 37..=37, u32::MAX - 6..=u32::MAX] {
+            assert_eq!(
+                scan_batches::<4>(&midstate, &tail, nonces.clone(), &tight, |_| [0; 4]),
+                pow::scan_midstate(&midstate, &tail, nonces.clone(), &tight),
+                "nonces {:?}", nonces
+            );
+        }
+    }
+}
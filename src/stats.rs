@@ -0,0 +1,311 @@
+use crate::difficulty::RetargetEstimate;
+use crate::power::PowerSource;
+use bitcoin::pow::Target;
+use serde::{Deserialize, Serialize};
+use std::{fs, io};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Where hash and share totals are accumulated across sessions.
+const LIFETIME_STATS_FILE: &str = "miner_stats.json";
+
+/// Probability that a single hash meets difficulty 1: 0xffff * 2^208 / 2^256.
+const DIFF1_SHARE_PROBABILITY: f64 = 65535.0 / 281_474_976_710_656.0; // 0xffff / 2^48
+
+/// The job currently being mined, as shown in stats.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    /// ID of the job, as in the logs and submission records.
+    pub job_id: String,
+    /// Hash of the block this job builds on.
+    pub prev_block_hash: String,
+    /// Compact difficulty target of the job.
+    pub nbits: u32,
+    /// Number of transactions in the block, including the coinbase.
+    pub transactions: usize,
+    /// Fees collected by the block's transactions, in satoshis.
+    pub fees_sats: u64,
+    /// Unix time the job started.
+    pub started_at: u64,
+}
+
+/// The session's best share: the lowest hash found, with the header that proves it.
+#[derive(Debug, Clone, Serialize)]
+pub struct BestShare {
+    /// ID of the job it was found on.
+    pub job_id: String,
+    /// The 80-byte header, in hex.
+    pub header: String,
+    /// Its double SHA-256, in the usual (reversed) display order.
+    pub hash: String,
+    /// Difficulty the hash meets: the difficulty-1 target divided by the hash.
+    pub difficulty: f64,
+    /// Unix time the share was found.
+    pub found_at: u64,
+}
+
+/// A point-in-time view of everything the miner reports.
+/// Console output, APIs, and notifications should all read from this.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MinerStats {
+    /// Unix time the session started.
+    pub session_started_at: u64,
+    /// Hashes computed this session.
+    pub hashes_total: u64,
+    /// Exponentially weighted moving average of the hash rate, in H/s.
+    pub hashrate_current: f64,
+    /// Average hash rate over the whole session, in H/s.
+    pub hashrate_average: f64,
+    /// Combined hash rate of the external workers, in H/s (not included in `hashrate_current`).
+    pub worker_hashrate: f64,
+    /// Moving average of the machine's power draw while mining, in W, if a power source is configured.
+    pub power_watts: Option<f64>,
+    /// Energy per unit of work at the current hash rate and power draw, in J/GH.
+    pub joules_per_gigahash: Option<f64>,
+    /// Energy used while mining this session, in kWh.
+    pub energy_kwh: Option<f64>,
+    /// Hashes that met difficulty 1 (the classic "share"), counted as a progress measure.
+    pub shares: u64,
+    /// Shares statistically expected this session given the hashes performed.
+    pub expected_shares: f64,
+    /// Shares found relative to expected this session, in percent (100 = average luck).
+    pub luck_percent: Option<f64>,
+    /// The lowest hash found this session, if any share was.
+    pub best_share: Option<BestShare>,
+    /// Hashes computed across all sessions, including this one.
+    pub lifetime_hashes: u64,
+    /// Shares found across all sessions, including this one.
+    pub lifetime_shares: u64,
+    /// Lifetime luck, in percent.
+    pub lifetime_luck_percent: Option<f64>,
+    /// The job currently being mined, if any.
+    pub job: Option<JobInfo>,
+    /// Outcome of the most recent block submission, if any.
+    pub last_submit_result: Option<String>,
+    /// Projection of the next difficulty adjustment, if one could be made.
+    pub retarget: Option<RetargetEstimate>,
+}
+
+/// Hash and share totals persisted between sessions.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct LifetimeTotals {
+    hashes: u64,
+    shares: u64,
+}
+
+/// Difficulty a hash (in display order) meets: how many times harder than difficulty 1
+/// it was to find.
+pub fn share_difficulty(hash: &[u8; 32]) -> f64 {
+    Target::from_be_bytes(*hash).difficulty_float()
+}
+
+/// Luck in percent: shares found over shares expected for `hashes` attempts.
+/// Undefined until at least one share's worth of work has been done.
+fn luck_percent(shares: u64, hashes: u64) -> Option<f64> {
+    let expected = hashes as f64 * DIFF1_SHARE_PROBABILITY;
+    (expected >= 1.0).then(|| shares as f64 / expected * 100.0)
+}
+
+/// Exponentially weighted moving average of the hash rate.
+///
+/// Each batch of hashes is blended in with a weight that depends on how long the
+/// batch took relative to the half-life, so the estimate is independent of batch
+/// size and carries over smoothly across template switches and thread changes.
+#[derive(Debug, Clone)]
+pub struct HashrateEstimator {
+    half_life: Duration,
+    rate: Option<f64>,
+}
+
+impl HashrateEstimator {
+    /// Creates an estimator with no samples yet.
+    pub fn new(half_life: Duration) -> Self {
+        HashrateEstimator { half_life: half_life.max(Duration::from_millis(1)), rate: None }
+    }
+
+    /// Blends in `hashes` computed over `elapsed` of active mining time.
+    pub fn record(&mut self, hashes: u64, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return;
+        }
+        let sample = hashes as f64 / secs;
+        // Weight of the new sample: 1 - 0.5^(elapsed / half_life)
+        let alpha = 1.0 - 0.5f64.powf(secs / self.half_life.as_secs_f64());
+        self.rate = Some(match self.rate {
+            Some(rate) => alpha * sample + (1.0 - alpha) * rate,
+            None => sample,
+        });
+    }
+
+    /// The current estimate in H/s (0 before any samples).
+    pub fn rate(&self) -> f64 {
+        self.rate.unwrap_or(0.0)
+    }
+}
+
+/// Everything behind the stats lock.
+#[derive(Debug)]
+struct Inner {
+    stats: MinerStats,
+    estimator: HashrateEstimator,
+    /// Totals from previous sessions, as loaded at startup.
+    previous: LifetimeTotals,
+    /// Where the lifetime totals are kept.
+    lifetime_file: String,
+    power: Option<PowerSource>,
+    /// Averages the power draw; fed millijoules, its rate is in milliwatts.
+    power_estimator: HashrateEstimator,
+}
+
+impl Inner {
+    /// Recomputes the figures derived from hashes and shares.
+    fn refresh_derived(&mut self) {
+        let s = &mut self.stats;
+        s.expected_shares = s.hashes_total as f64 * DIFF1_SHARE_PROBABILITY;
+        s.luck_percent = luck_percent(s.shares, s.hashes_total);
+        s.lifetime_hashes = self.previous.hashes + s.hashes_total;
+        s.lifetime_shares = self.previous.shares + s.shares;
+        s.lifetime_luck_percent = luck_percent(s.lifetime_shares, s.lifetime_hashes);
+    }
+}
+
+/// Shared, thread-safe handle to the miner's statistics.
+/// Cloning the handle shares the same underlying stats.
+#[derive(Debug, Clone)]
+pub struct StatsHandle {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl StatsHandle {
+    /// Starts a new stats session whose hash rate average has the given half-life.
+    /// Lifetime totals are loaded from the stats file if it exists.
+    pub fn new(hashrate_half_life: Duration) -> Self {
+        Self::with_lifetime_file(hashrate_half_life, LIFETIME_STATS_FILE.to_string())
+    }
+
+    /// Like `new`, for one of the extra chains: its lifetime totals are kept apart,
+    /// in a stats file named after it.
+    pub fn for_chain(hashrate_half_life: Duration, name: &str) -> Self {
+        let file = LIFETIME_STATS_FILE.replace(".json", &format!("_{}.json", name));
+        Self::with_lifetime_file(hashrate_half_life, file)
+    }
+
+    fn with_lifetime_file(hashrate_half_life: Duration, lifetime_file: String) -> Self {
+        let previous = fs::read_to_string(&lifetime_file).ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+        let mut inner = Inner {
+            stats: MinerStats {
+                session_started_at: unix_now(),
+                ..MinerStats::default()
+            },
+            estimator: HashrateEstimator::new(hashrate_half_life),
+            previous,
+            lifetime_file,
+            power: None,
+            power_estimator: HashrateEstimator::new(hashrate_half_life),
+        };
+        inner.refresh_derived();
+        StatsHandle { inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a consistent copy of the current stats.
+    pub fn snapshot(&self) -> MinerStats {
+        self.lock().stats.clone()
+    }
+
+    /// Runs `f` on the current stats without copying them.
+    /// The stats are locked meanwhile, so `f` should be quick.
+    pub fn with<R>(&self, f: impl FnOnce(&MinerStats) -> R) -> R {
+        f(&self.lock().stats)
+    }
+
+    /// Applies an update to the stats atomically.
+    pub fn update(&self, f: impl FnOnce(&mut MinerStats)) {
+        f(&mut self.lock().stats);
+    }
+
+    /// Starts accounting the energy used while mining, and the efficiency, from `source`.
+    pub fn set_power_source(&self, source: PowerSource) {
+        let mut inner = self.lock();
+        inner.power = Some(source);
+        inner.stats.energy_kwh = Some(0.0);
+    }
+
+    /// Adds `hashes` computed over `elapsed` of active mining time to the totals and averages.
+    pub fn record_hashes(&self, hashes: u64, elapsed: Duration) {
+        let mut inner = self.lock();
+        inner.estimator.record(hashes, elapsed);
+        let rate = inner.estimator.rate();
+        if let Some(joules) = inner.power.as_mut().and_then(|power| power.energy(elapsed)) {
+            inner.power_estimator.record((joules * 1000.0) as u64, elapsed);
+            let watts = inner.power_estimator.rate() / 1000.0;
+            let s = &mut inner.stats;
+            s.energy_kwh = Some(s.energy_kwh.unwrap_or(0.0) + joules / 3.6e6);
+            s.power_watts = Some(watts);
+            s.joules_per_gigahash = (rate > 0.0).then(|| watts / (rate / 1e9));
+        }
+        let s = &mut inner.stats;
+        s.hashes_total += hashes;
+        s.hashrate_current = rate;
+        let session = unix_now().saturating_sub(s.session_started_at).max(1);
+        s.hashrate_average = s.hashes_total as f64 / session as f64;
+        inner.refresh_derived();
+    }
+
+    /// Adds hashes reported by an external worker to the totals. Workers measure their
+    /// own rate, so the local moving average is left alone.
+    pub fn record_worker_hashes(&self, hashes: u64) {
+        let mut inner = self.lock();
+        let s = &mut inner.stats;
+        s.hashes_total += hashes;
+        let session = unix_now().saturating_sub(s.session_started_at).max(1);
+        s.hashrate_average = s.hashes_total as f64 / session as f64;
+        inner.refresh_derived();
+    }
+
+    /// Counts a difficulty-1 share found on job `job_id` with `header`, whose hash in
+    /// display order is `hash`. Returns whether it's the session's new best share.
+    pub fn record_share(&self, job_id: &str, header: &[u8; 80], hash: &[u8; 32]) -> bool {
+        let mut inner = self.lock();
+        inner.stats.shares += 1;
+        inner.refresh_derived();
+        let difficulty = share_difficulty(hash);
+        let best = inner.stats.best_share.as_ref().is_none_or(|best| difficulty > best.difficulty);
+        if best {
+            inner.stats.best_share = Some(BestShare {
+                job_id: job_id.to_string(),
+                header: hex::encode(header),
+                hash: hex::encode(hash),
+                difficulty,
+                found_at: unix_now(),
+            });
+        }
+        best
+    }
+
+    /// Writes the lifetime hash and share totals to the stats file.
+    pub fn save_lifetime(&self) -> io::Result<()> {
+        let (totals, file) = {
+            let inner = self.lock();
+            (LifetimeTotals { hashes: inner.stats.lifetime_hashes, shares: inner.stats.lifetime_shares }, inner.lifetime_file.clone())
+        };
+        fs::write(file, serde_json::to_string_pretty(&totals)?)
+    }
+}
+
+impl Default for StatsHandle {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30))
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
@@ -0,0 +1,442 @@
+//! A minimal Stratum V1 client, for mining on a pool's work: subscribe, authorize,
+//! follow `mining.notify` jobs and the share difficulty, and submit shares.
+//! The `version-rolling` extension (BIP310) is negotiated so the miner may roll
+//! the BIP320 version bits the pool grants.
+
+use crate::{merkle, pow};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+/// How long the pool may take to answer a request or send its first job.
+const POOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for an answer to `mining.configure`: pools that don't know the
+/// method may ignore it altogether.
+const CONFIGURE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fewest version bits worth rolling; each one doubles the search space of a job.
+const VERSION_ROLLING_MIN_BITS: u32 = 2;
+
+/// A job as sent by `mining.notify`.
+#[derive(Debug, Clone)]
+struct PoolJob {
+    job_id: String,
+    /// Previous block hash, internal byte order.
+    prev_hash: [u8; 32],
+    coinb1: Vec<u8>,
+    coinb2: Vec<u8>,
+    merkle_branch: Vec<[u8; 32]>,
+    version: u32,
+    nbits: u32,
+    ntime: u32,
+}
+
+/// Work from a pool job, ready to hash: the coinbase around our extranonce2 and the
+/// merkle branch, from which the pool assembles the block once a share meets its target.
+#[derive(Debug, Clone)]
+pub struct StratumWork {
+    /// Pool address, for messages.
+    pub pool: String,
+    pub job_id: String,
+    /// coinb1 followed by the pool's extranonce1.
+    pub coinbase_prefix: Vec<u8>,
+    pub extranonce2: Vec<u8>,
+    pub coinbase_suffix: Vec<u8>,
+    /// Hashes (internal byte order) combined with the coinbase txid to reach the merkle root.
+    pub merkle_branch: Vec<[u8; 32]>,
+    /// Version bits the pool lets us roll; 0 without the version-rolling extension.
+    pub version_mask: u32,
+    /// Raised when this work should be dropped: the pool sent a new job, or lost the connection.
+    pub stale: Arc<AtomicBool>,
+}
+
+impl StratumWork {
+    /// The merkle root (internal byte order) of the block this work builds.
+    pub fn merkle_root(&self) -> [u8; 32] {
+        let coinbase = [self.coinbase_prefix.as_slice(), &self.extranonce2, &self.coinbase_suffix].concat();
+        merkle::root_from_branch(pow::sha256d(&coinbase), &self.merkle_branch)
+    }
+}
+
+/// Header fields of a pool job, in the form `MinerSettings` keeps them.
+pub struct JobHeader {
+    pub version: u32,
+    /// Previous block hash, display (big-endian) hex.
+    pub prev_block_hash: String,
+    pub nbits: u32,
+    pub ntime: u32,
+}
+
+/// The work being mined, to fill in share submissions.
+struct CurrentWork {
+    job_id: String,
+    extranonce2: Vec<u8>,
+    version_mask: u32,
+    stale: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    extranonce1: Vec<u8>,
+    extranonce2_size: usize,
+    /// Version bits granted through the version-rolling extension.
+    version_mask: u32,
+    /// Share target, big-endian.
+    share_target: [u8; 32],
+    job: Option<PoolJob>,
+    current: Option<CurrentWork>,
+    /// Ids of share submissions awaiting an answer.
+    pending_shares: HashSet<u64>,
+    accepted: u64,
+    rejected: u64,
+    disconnected: bool,
+}
+
+struct Shared {
+    url: String,
+    user: String,
+    writer: Mutex<TcpStream>,
+    state: Mutex<PoolState>,
+    /// Signalled when a job arrives or the connection drops.
+    job_arrived: Condvar,
+    next_id: AtomicU64,
+    /// Set when we close the connection ourselves, so losing it isn't reported.
+    closed: AtomicBool,
+}
+
+/// A connection to a Stratum pool. Dropping it closes the connection.
+pub struct StratumClient {
+    shared: Arc<Shared>,
+    responses: mpsc::Receiver<Value>,
+    next_extranonce2: u64,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The share target for a pool difficulty: the difficulty-1 target divided by it.
+pub fn difficulty_to_target(difficulty: f64) -> [u8; 32] {
+    let mut target = [0u8; 32];
+    if difficulty.is_nan() || difficulty <= 1.0 {
+        target[4..6].copy_from_slice(&[0xff, 0xff]);
+        return target;
+    }
+    // 0xffff * 2^208 / difficulty, computed as a 64-bit mantissa shifted by 160 bits
+    let mantissa = (0xffff as f64 / difficulty * 2f64.powi(48)) as u64;
+    target[4..12].copy_from_slice(&mantissa.to_be_bytes());
+    target
+}
+
+impl StratumClient {
+    /// Connects to a pool ("host:port", optionally prefixed by "stratum+tcp://"),
+    /// subscribes and authorizes `user`.
+    pub fn connect(url: &str, user: &str, password: &str) -> io::Result<StratumClient> {
+        let address = url.strip_prefix("stratum+tcp://").unwrap_or(url);
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let shared = Arc::new(Shared {
+            url: url.to_string(),
+            user: user.to_string(),
+            writer: Mutex::new(stream),
+            state: Mutex::new(PoolState { share_target: difficulty_to_target(1.0), ..PoolState::default() }),
+            job_arrived: Condvar::new(),
+            next_id: AtomicU64::new(1),
+            closed: AtomicBool::new(false),
+        });
+        let (responses_tx, responses) = mpsc::channel();
+        {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                if let Err(e) = read_pool(reader, &shared, &responses_tx)
+                    && !shared.closed.load(Ordering::Relaxed)
+                {
+                    eprintln!("[Pool] Lost the connection to {}: {}", shared.url, e);
+                }
+                let mut state = lock(&shared.state);
+                state.disconnected = true;
+                if let Some(current) = &state.current {
+                    current.stale.store(true, Ordering::Relaxed);
+                }
+                shared.job_arrived.notify_all();
+            });
+        }
+        let mut client = StratumClient { shared, responses, next_extranonce2: 0 };
+
+        client.configure_version_rolling();
+        let subscription = client.call("mining.subscribe", json!([format!("solo-miner/{}", env!("CARGO_PKG_VERSION"))]))?;
+        let extranonce1 = subscription.get(1).and_then(Value::as_str).and_then(|h| hex::decode(h).ok())
+            .ok_or_else(|| invalid(format!("Pool {} sent no extranonce1", url)))?;
+        let extranonce2_size = subscription.get(2).and_then(Value::as_u64)
+            .ok_or_else(|| invalid(format!("Pool {} sent no extranonce2 size", url)))? as usize;
+        {
+            let mut state = lock(&client.shared.state);
+            state.extranonce1 = extranonce1;
+            state.extranonce2_size = extranonce2_size;
+        }
+        if client.call("mining.authorize", json!([user, password]))? != Value::Bool(true) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Pool {} refused worker '{}'", url, user)));
+        }
+        println!("[Pool] Connected to {} as '{}'.", url, user);
+        Ok(client)
+    }
+
+    /// Asks for the version-rolling extension. Mining goes on without it when the pool
+    /// refuses or doesn't answer.
+    fn configure_version_rolling(&mut self) {
+        let params = json!([["version-rolling"], {
+            "version-rolling.mask": format!("{:08x}", pow::BIP320_VERSION_MASK),
+            "version-rolling.min-bit-count": VERSION_ROLLING_MIN_BITS,
+        }]);
+        let granted = match self.call_with_timeout("mining.configure", params, CONFIGURE_TIMEOUT) {
+            Ok(result) if result["version-rolling"] == Value::Bool(true) => result["version-rolling.mask"].as_str()
+                .and_then(|mask| u32::from_str_radix(mask, 16).ok())
+                .unwrap_or_default(),
+            Ok(_) => 0,
+            Err(e) => {
+                println!("[Pool] No version rolling: {}", e);
+                return;
+            },
+        };
+        // Only ever roll the bits BIP320 leaves to miners, whatever the pool grants
+        let mask = granted & pow::BIP320_VERSION_MASK;
+        if mask.count_ones() < VERSION_ROLLING_MIN_BITS {
+            println!("[Pool] No version rolling: the pool granted mask {:08x}.", granted);
+            return;
+        }
+        println!("[Pool] Version rolling enabled with mask {:08x}.", mask);
+        lock(&self.shared.state).version_mask = mask;
+    }
+
+    /// Sends a request and waits for its result.
+    fn call(&mut self, method: &str, params: Value) -> io::Result<Value> {
+        self.call_with_timeout(method, params, POOL_TIMEOUT)
+    }
+
+    fn call_with_timeout(&mut self, method: &str, params: Value, timeout: Duration) -> io::Result<Value> {
+        let id = self.shared.send(method, params)?;
+        loop {
+            let response = self.responses.recv_timeout(timeout)
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("Pool {} did not answer {}", self.shared.url, method)))?;
+            if response["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if !response["error"].is_null() {
+                return Err(io::Error::other(format!("Pool {} answered {} with an error: {}", self.shared.url, method, response["error"])));
+            }
+            return Ok(response["result"].clone());
+        }
+    }
+
+    /// Waits for the pool's latest job and turns it into work with a fresh extranonce2.
+    /// The header fields go into `MinerSettings` alongside the work.
+    pub fn next_work(&mut self) -> io::Result<(JobHeader, StratumWork)> {
+        let mut state = lock(&self.shared.state);
+        while state.job.is_none() && !state.disconnected {
+            let (next, timeout) = self.shared.job_arrived.wait_timeout(state, POOL_TIMEOUT).unwrap_or_else(|e| e.into_inner());
+            state = next;
+            if timeout.timed_out() && state.job.is_none() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, format!("Pool {} sent no job", self.shared.url)));
+            }
+        }
+        if state.disconnected {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, format!("Lost the connection to pool {}", self.shared.url)));
+        }
+        let job = state.job.clone().unwrap();
+
+        self.next_extranonce2 = self.next_extranonce2.wrapping_add(1);
+        let extranonce2 = self.next_extranonce2.to_le_bytes().into_iter().chain(std::iter::repeat(0)).take(state.extranonce2_size).collect::<Vec<_>>();
+        let stale = Arc::new(AtomicBool::new(false));
+        state.current = Some(CurrentWork {
+            job_id: job.job_id.clone(),
+            extranonce2: extranonce2.clone(),
+            version_mask: state.version_mask,
+            stale: Arc::clone(&stale),
+        });
+
+        let mut display_prev_hash = job.prev_hash;
+        display_prev_hash.reverse();
+        let header = JobHeader { version: job.version, prev_block_hash: hex::encode(display_prev_hash), nbits: job.nbits, ntime: job.ntime };
+        let work = StratumWork {
+            pool: self.shared.url.clone(),
+            job_id: job.job_id,
+            coinbase_prefix: [job.coinb1, state.extranonce1.clone()].concat(),
+            extranonce2,
+            coinbase_suffix: job.coinb2,
+            merkle_branch: job.merkle_branch,
+            version_mask: state.version_mask,
+            stale,
+        };
+        Ok((header, work))
+    }
+
+    /// A handle for other threads: event subscribers submitting shares, or whatever
+    /// decides the work should be dropped.
+    pub fn handle(&self) -> StratumHandle {
+        StratumHandle { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl Drop for StratumClient {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        let _ = lock(&self.shared.writer).shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// Shares a pool connection with other threads; does nothing once the connection is gone.
+#[derive(Clone)]
+pub struct StratumHandle {
+    shared: Arc<Shared>,
+}
+
+impl StratumHandle {
+    /// Submits a share for the current work if its hash (display hex) meets the share target.
+    pub fn submit_share(&self, hash: &str, nonce: u32, ntime: u32, version: u32) {
+        self.shared.submit_share(hash, nonce, ntime, version);
+    }
+
+    /// Drops the work being mined, e.g. because the miner switches back to its node.
+    pub fn mark_stale(&self) {
+        if let Some(current) = &lock(&self.shared.state).current {
+            current.stale.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Shared {
+    fn send(&self, method: &str, params: Value) -> io::Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut line = json!({"id": id, "method": method, "params": params}).to_string();
+        line.push('\n');
+        lock(&self.writer).write_all(line.as_bytes())?;
+        Ok(id)
+    }
+
+    fn submit_share(&self, hash: &str, nonce: u32, ntime: u32, version: u32) {
+        let params = {
+            let state = lock(&self.state);
+            let Some(current) = &state.current else { return };
+            let meets_target = hex::decode(hash).is_ok_and(|h| h.as_slice() <= state.share_target.as_slice());
+            if state.disconnected || !meets_target {
+                return;
+            }
+            let mut params = json!([self.user, current.job_id, hex::encode(&current.extranonce2), format!("{:08x}", ntime), format!("{:08x}", nonce)]);
+            // With version rolling, the rolled bits go in a sixth field
+            if current.version_mask != 0 {
+                params.as_array_mut().unwrap().push(json!(format!("{:08x}", version & current.version_mask)));
+            }
+            params
+        };
+        match self.send("mining.submit", params) {
+            Ok(id) => {
+                lock(&self.state).pending_shares.insert(id);
+            },
+            Err(e) => eprintln!("[Pool] Could not submit a share to {}: {}", self.url, e),
+        }
+    }
+}
+
+/// Follows the pool's notifications, and passes answers to requests on to `responses`.
+fn read_pool(reader: BufReader<TcpStream>, shared: &Shared, responses: &mpsc::Sender<Value>) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Value = serde_json::from_str(&line).map_err(|e| invalid(format!("Pool sent invalid JSON: {}", e)))?;
+        match message["method"].as_str() {
+            Some("mining.notify") => {
+                let job = parse_notify(&message["params"])?;
+                let mut state = lock(&shared.state);
+                // Every new job supersedes the work being mined, so fees and ntime stay current
+                if let Some(current) = &state.current {
+                    current.stale.store(true, Ordering::Relaxed);
+                }
+                state.job = Some(job);
+                shared.job_arrived.notify_all();
+            },
+            Some("mining.set_version_mask") => {
+                let granted = message["params"][0].as_str().and_then(|mask| u32::from_str_radix(mask, 16).ok()).unwrap_or_default();
+                let mut state = lock(&shared.state);
+                if state.version_mask == 0 {
+                    continue;
+                }
+                state.version_mask = granted & pow::BIP320_VERSION_MASK;
+                println!("[Pool] Version rolling mask changed to {:08x}.", state.version_mask);
+                // Work rolling bits outside the new mask would be rejected
+                if let Some(current) = &state.current {
+                    current.stale.store(true, Ordering::Relaxed);
+                }
+            },
+            Some("mining.set_difficulty") => {
+                let difficulty = message["params"][0].as_f64().unwrap_or(1.0);
+                println!("[Pool] Share difficulty set to {}.", difficulty);
+                lock(&shared.state).share_target = difficulty_to_target(difficulty);
+            },
+            Some(_) => {},
+            None => {
+                let id = message["id"].as_u64().unwrap_or_default();
+                let mut state = lock(&shared.state);
+                if state.pending_shares.remove(&id) {
+                    if message["result"] == Value::Bool(true) {
+                        state.accepted += 1;
+                        println!("[Pool] Share accepted ({} accepted, {} rejected).", state.accepted, state.rejected);
+                    } else {
+                        state.rejected += 1;
+                        eprintln!("[Pool] Share rejected: {} ({} accepted, {} rejected).", message["error"], state.accepted, state.rejected);
+                    }
+                    continue;
+                }
+                drop(state);
+                if responses.send(message).is_err() {
+                    return Ok(());
+                }
+            },
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+}
+
+/// Parses `mining.notify` params: job id, prevhash, coinb1, coinb2, merkle branch,
+/// version, nbits, ntime, clean jobs.
+fn parse_notify(params: &Value) -> io::Result<PoolJob> {
+    let field = |i: usize| params.get(i).and_then(Value::as_str).ok_or_else(|| invalid(format!("mining.notify field {} is missing", i)));
+    let bytes = |i: usize| field(i).and_then(|h| hex::decode(h).map_err(|e| invalid(format!("mining.notify field {}: {}", i, e))));
+    let word = |i: usize| field(i).and_then(|h| u32::from_str_radix(h, 16).map_err(|e| invalid(format!("mining.notify field {}: {}", i, e))));
+
+    // The previous block hash comes as eight 4-byte words, each byte-swapped
+    let mut prev_hash: [u8; 32] = bytes(1)?.try_into().map_err(|_| invalid("mining.notify prevhash is not 32 bytes".to_string()))?;
+    for chunk in prev_hash.chunks_exact_mut(4) {
+        chunk.reverse();
+    }
+    let merkle_branch = params.get(4).and_then(Value::as_array).ok_or_else(|| invalid("mining.notify has no merkle branch".to_string()))?
+        .iter()
+        .map(|h| {
+            let mut hash = [0u8; 32];
+            hex::decode_to_slice(h.as_str().unwrap_or_default(), &mut hash).map(|_| hash)
+                .map_err(|e| invalid(format!("mining.notify merkle branch: {}", e)))
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(PoolJob {
+        job_id: field(0)?.to_string(),
+        prev_hash,
+        coinb1: bytes(2)?,
+        coinb2: bytes(3)?,
+        merkle_branch,
+        version: word(5)?,
+        nbits: word(6)?,
+        ntime: word(7)?,
+    })
+}
@@ -0,0 +1,361 @@
+use crate::settings::MinerSettings;
+use crate::{halving, p2p};
+use serde::Serialize;
+use std::{fmt, fs, io};
+use std::io::Write;
+use std::time::SystemTime;
+
+/// Append-only log with one JSON record per block submission attempt.
+const AUDIT_LOG_FILE: &str = "submissions.log";
+
+/// Why the node refused a block, parsed from the string result of submitblock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitRejection {
+    /// The node already has this exact block and considers it valid.
+    Duplicate,
+    /// The node already has this block and knows it to be invalid.
+    DuplicateInvalid,
+    /// The node already has this block but hasn't fully validated it.
+    DuplicateInconclusive,
+    /// The block is valid so far but not on the node's best chain.
+    Inconclusive,
+    /// The header hash is above the target.
+    HighHash,
+    /// nBits doesn't match what the chain requires at this height.
+    BadDifficulty,
+    /// The timestamp is not after the median of the last 11 blocks.
+    TimeTooOld,
+    /// The timestamp is more than 2 hours in the node's future.
+    TimeTooNew,
+    /// The header's merkle root doesn't match the transactions.
+    BadMerkleRoot,
+    /// The coinbase doesn't start with the block height (BIP34).
+    BadCoinbaseHeight,
+    /// The coinbase scriptSig is shorter than 2 or longer than 100 bytes.
+    BadCoinbaseLength,
+    /// The coinbase pays out more than subsidy plus fees.
+    BadCoinbaseAmount,
+    /// The witness commitment or coinbase witness nonce is wrong or missing.
+    BadWitness,
+    /// The block exceeds the weight or size limit.
+    BadWeight,
+    /// The block exceeds the sigops limit.
+    BadSigops,
+    /// The parent block is unknown or invalid.
+    BadPrevBlock,
+    /// A transaction spends missing or already-spent outputs.
+    MissingInputs,
+    /// The block version is obsolete.
+    BadVersion,
+    /// Any reason this miner doesn't have a specific explanation for.
+    Other(String),
+}
+
+impl SubmitRejection {
+    /// Maps the raw submitblock result string to a rejection reason.
+    pub fn from_result(result: &str) -> Self {
+        match result {
+            "duplicate" => SubmitRejection::Duplicate,
+            "duplicate-invalid" => SubmitRejection::DuplicateInvalid,
+            "duplicate-inconclusive" => SubmitRejection::DuplicateInconclusive,
+            "inconclusive" => SubmitRejection::Inconclusive,
+            "high-hash" => SubmitRejection::HighHash,
+            "bad-diffbits" => SubmitRejection::BadDifficulty,
+            "time-too-old" => SubmitRejection::TimeTooOld,
+            "time-too-new" => SubmitRejection::TimeTooNew,
+            "bad-txnmrklroot" | "bad-txns-duplicate" => SubmitRejection::BadMerkleRoot,
+            "bad-cb-height" => SubmitRejection::BadCoinbaseHeight,
+            "bad-cb-length" => SubmitRejection::BadCoinbaseLength,
+            "bad-cb-amount" => SubmitRejection::BadCoinbaseAmount,
+            "bad-witness-nonce-size" | "bad-witness-merkle-match" | "unexpected-witness" => SubmitRejection::BadWitness,
+            "bad-blk-weight" | "bad-blk-length" => SubmitRejection::BadWeight,
+            "bad-blk-sigops" => SubmitRejection::BadSigops,
+            "bad-prevblk" | "prev-blk-not-found" => SubmitRejection::BadPrevBlock,
+            "bad-txns-inputs-missingorspent" => SubmitRejection::MissingInputs,
+            r if r.starts_with("bad-version") => SubmitRejection::BadVersion,
+            other => SubmitRejection::Other(other.to_string()),
+        }
+    }
+
+    /// True when the node ends up with our block on its best chain regardless,
+    /// i.e. the "rejection" is just the node telling us it already had it.
+    pub fn is_already_accepted(&self) -> bool {
+        matches!(self, SubmitRejection::Duplicate)
+    }
+
+    /// A plain-English explanation of what went wrong.
+    pub fn explanation(&self) -> &str {
+        match self {
+            SubmitRejection::Duplicate => "The node already has this block and considers it valid.",
+            SubmitRejection::DuplicateInvalid => "The node already has this block and previously found it invalid.",
+            SubmitRejection::DuplicateInconclusive => "The node already has this block but hasn't finished validating it.",
+            SubmitRejection::Inconclusive => "The block was stored but is not on the node's best chain (another block at this height won).",
+            SubmitRejection::HighHash => "The block hash does not meet the target: the proof of work is invalid.",
+            SubmitRejection::BadDifficulty => "The nBits field does not match the difficulty the chain requires.",
+            SubmitRejection::TimeTooOld => "The timestamp is not later than the median time of the previous 11 blocks.",
+            SubmitRejection::TimeTooNew => "The timestamp is more than two hours ahead of the node's clock.",
+            SubmitRejection::BadMerkleRoot => "The merkle root in the header does not match the block's transactions.",
+            SubmitRejection::BadCoinbaseHeight => "The coinbase scriptSig does not begin with the block height (BIP34).",
+            SubmitRejection::BadCoinbaseLength => "The coinbase scriptSig is not between 2 and 100 bytes.",
+            SubmitRejection::BadCoinbaseAmount => "The coinbase pays more than the block subsidy plus fees.",
+            SubmitRejection::BadWitness => "The witness commitment or coinbase witness nonce is wrong.",
+            SubmitRejection::BadWeight => "The block exceeds the consensus weight or size limit.",
+            SubmitRejection::BadSigops => "The block exceeds the consensus signature-operations limit.",
+            SubmitRejection::BadPrevBlock => "The previous block is unknown to the node or invalid.",
+            SubmitRejection::MissingInputs => "A transaction spends outputs that are missing or already spent.",
+            SubmitRejection::BadVersion => "The block version is too old for the current consensus rules.",
+            SubmitRejection::Other(_) => "The node rejected the block for a reason this miner doesn't recognize.",
+        }
+    }
+
+    /// The most likely fix, if there is one.
+    pub fn hint(&self) -> &str {
+        match self {
+            SubmitRejection::Duplicate | SubmitRejection::DuplicateInconclusive => "Nothing to do; wait for the node to process it.",
+            SubmitRejection::Inconclusive | SubmitRejection::BadPrevBlock =>
+                "The template was stale. Fetch templates more often so work stops as soon as the tip changes.",
+            SubmitRejection::HighHash | SubmitRejection::BadMerkleRoot | SubmitRejection::BadWitness | SubmitRejection::DuplicateInvalid =>
+                "This is a bug in block assembly; please report it with the saved block data.",
+            SubmitRejection::BadDifficulty => "Make sure nbits comes from a fresh getblocktemplate for this network.",
+            SubmitRejection::TimeTooOld | SubmitRejection::TimeTooNew => "Check the system clock (enable NTP) and respect the template's mintime.",
+            SubmitRejection::BadCoinbaseHeight => "Encode the template height as the first push of the coinbase scriptSig.",
+            SubmitRejection::BadCoinbaseLength => "Shorten the coinbase tag.",
+            SubmitRejection::BadCoinbaseAmount => "Pay out no more than the template's coinbasevalue.",
+            SubmitRejection::BadWeight | SubmitRejection::BadSigops => "Include fewer template transactions or shrink the coinbase.",
+            SubmitRejection::MissingInputs => "A template transaction conflicted with the chain; refetch the template.",
+            SubmitRejection::BadVersion => "Use the version from getblocktemplate instead of a fixed value.",
+            SubmitRejection::Other(_) => "Check the node's debug.log for details.",
+        }
+    }
+}
+
+impl fmt::Display for SubmitRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitRejection::Other(reason) => write!(f, "{} ({})", self.explanation(), reason),
+            _ => write!(f, "{}", self.explanation()),
+        }
+    }
+}
+
+impl std::error::Error for SubmitRejection {}
+
+/// Everything that can go wrong when submitting a block.
+#[derive(Debug)]
+pub enum SubmitError {
+    /// The node could not be reached or replied with something unexpected.
+    Rpc(io::Error),
+    /// The node received the block and refused it.
+    Rejected(SubmitRejection),
+    /// The block failed our own checks, so it was never sent to the node.
+    Invalid(String),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Rpc(e) => write!(f, "Block submission failed: {}", e),
+            SubmitError::Rejected(r) => write!(f, "Block submission rejected by node: {} Hint: {}", r, r.hint()),
+            SubmitError::Invalid(problem) => write!(f, "Block failed local validation: {}", problem),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+impl From<io::Error> for SubmitError {
+    fn from(e: io::Error) -> Self {
+        SubmitError::Rpc(e)
+    }
+}
+
+/// One endpoint's answer to a submission, as recorded in the audit log.
+#[derive(Serialize)]
+struct EndpointResponse {
+    endpoint: String,
+    response: String,
+    timestamp: u64,
+}
+
+/// A single line of the submission audit log.
+#[derive(Serialize)]
+struct AuditRecord {
+    job_id: String,
+    template_id: String,
+    block_hash: String,
+    /// The 80-byte header, in hex: the version, time and nonce the block was found with.
+    header: String,
+    /// First and last nonce of the slice the block was found in, when known.
+    nonce_range: Option<(u32, u32)>,
+    hex_file: Option<String>,
+    found_at: u64,
+    responses: Vec<EndpointResponse>,
+    outcome: String,
+}
+
+/// Submits a found block and records everything about the attempt.
+///
+/// The block hex is saved to `block_<hash>.hex` first, so it survives a crash or
+/// a failed RPC call and can be resubmitted by hand. After the node accepts the
+/// block it's announced to the configured compact-block peers. A block failing
+/// `check_found_block` is saved but not submitted. Every endpoint's
+/// response and the final outcome are appended to the audit log, under the job ID, with
+/// the header and `nonce_range` (the slice the block was found in).
+pub fn submit_found_block(
+    settings: &MinerSettings,
+    block: &bitcoin::Block,
+    block_hex: &str,
+    nonce_range: Option<(u32, u32)>,
+) -> Result<(), SubmitError> {
+    let found_at = unix_now();
+    let block_hash = block.block_hash().to_string();
+    let template_id = settings.workid.clone()
+        .unwrap_or_else(|| format!("{}@{}", settings.prev_block_hash, settings.timestamp));
+
+    let hex_path = format!("block_{}.hex", block_hash);
+    let hex_file = match fs::write(&hex_path, block_hex) {
+        Ok(()) => {
+            println!("[Submit] Block hex for job {} saved to {}", settings.job_id, hex_path);
+            Some(hex_path)
+        },
+        Err(e) => {
+            eprintln!("[Submit] Warning: could not save block hex to {}: {}", hex_path, e);
+            None
+        },
+    };
+
+    let result = check_found_block(settings, block)
+        .map_err(SubmitError::Invalid)
+        .and_then(|()| submit_block_to_node(settings, block_hex));
+    let mut responses = vec![EndpointResponse {
+        endpoint: settings.rpc_url.clone(),
+        response: match &result {
+            Ok(()) => "accepted".to_string(),
+            Err(e) => e.to_string(),
+        },
+        timestamp: unix_now(),
+    }];
+
+    if result.is_ok() {
+        // Once our node accepted it, race it to other peers as a compact block
+        for (peer, peer_result) in p2p::announce_compact_block(&settings.compact_block_peers, block, settings.network()) {
+            responses.push(EndpointResponse {
+                endpoint: format!("p2p://{}", peer),
+                response: match peer_result {
+                    Ok(()) => "compact block sent".to_string(),
+                    Err(e) => format!("error: {}", e),
+                },
+                timestamp: unix_now(),
+            });
+        }
+    }
+
+    let record = AuditRecord {
+        job_id: settings.job_id.clone(),
+        template_id,
+        block_hash,
+        header: bitcoin::consensus::encode::serialize_hex(&block.header),
+        nonce_range,
+        hex_file,
+        found_at,
+        responses,
+        outcome: match &result {
+            Ok(()) => "accepted".to_string(),
+            Err(SubmitError::Rejected(r)) => format!("rejected: {:?}", r),
+            Err(SubmitError::Invalid(problem)) => format!("invalid: {}", problem),
+            Err(SubmitError::Rpc(_)) => "error".to_string(),
+        },
+    };
+    if let Err(e) = append_audit_record(&record) {
+        eprintln!("[Submit] Warning: could not write to {}: {}", AUDIT_LOG_FILE, e);
+    }
+
+    result
+}
+
+/// Checks a found block the way the node will before it's submitted: its merkle root
+/// and witness commitment against its transactions, its hash against the target its
+/// bits encode, its weight and size against the template's limits, and its coinbase against the subsidy plus the fees
+/// of the template transactions it includes. Describes the first problem found.
+pub fn check_found_block(settings: &MinerSettings, block: &bitcoin::Block) -> Result<(), String> {
+    if !block.check_merkle_root() {
+        let computed = block.compute_merkle_root().map_or("none".to_string(), |root| root.to_string());
+        return Err(format!("the header's merkle root {} doesn't match the {} computed from the transactions", block.header.merkle_root, computed));
+    }
+    if !block.check_witness_commitment() {
+        return Err("the coinbase witness commitment doesn't match the transactions' witnesses".to_string());
+    }
+    let target = block.header.target();
+    if block.header.validate_pow(target).is_err() {
+        return Err(format!(
+            "the hash {} is above the target {} its bits {:08x} encode",
+            block.block_hash(), hex::encode(target.to_be_bytes()), block.header.bits.to_consensus()
+        ));
+    }
+    // The template's limits: a server may set them below the consensus ones
+    let limits = &settings.block_limits;
+    let weight = block.weight().to_wu();
+    if weight > limits.weight {
+        return Err(format!("its weight {} exceeds the template's {} limit", weight, limits.weight));
+    }
+    let size = block.total_size() as u64;
+    if size > limits.size {
+        return Err(format!("its size of {} bytes exceeds the template's {}-byte limit", size, limits.size));
+    }
+
+    let coinbase = block.txdata.first().filter(|tx| tx.is_coinbase())
+        .ok_or_else(|| "the first transaction is not a coinbase".to_string())?;
+    let fees: u64 = block.txdata.iter().skip(1)
+        .map(|tx| tx.compute_txid().to_string())
+        .filter_map(|txid| settings.transactions.iter().find(|template_tx| template_tx.txid == txid))
+        .map(|template_tx| template_tx.fee)
+        .sum();
+    let subsidy = halving::subsidy_for_height_on(settings.height, settings.network());
+    let claimed: u64 = coinbase.output.iter().map(|output| output.value.to_sat()).sum();
+    if claimed > subsidy + fees {
+        return Err(format!(
+            "the coinbase pays {} sats, more than the {} sats of subsidy plus {} sats of fees allowed at height {}",
+            claimed, subsidy, fees, settings.height
+        ));
+    }
+    Ok(())
+}
+
+fn append_audit_record(record: &AuditRecord) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(AUDIT_LOG_FILE)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Submits the raw, serialized block to the Bitcoin node via the submitblock RPC.
+fn submit_block_to_node(
+    settings: &MinerSettings,
+    block_hex: &str
+) -> Result<(), SubmitError> {
+    println!("\n[RPC] Submitting found block to node...");
+
+    let client = settings.http_client()?;
+
+    // BIP23: if the template carried a workid, it must be passed back alongside the block.
+    let params = match &settings.workid {
+        Some(workid) => serde_json::json!([block_hex, {"workid": workid}]),
+        None => serde_json::json!([block_hex]),
+    };
+
+    // A null result means the block was accepted; a string explains the refusal.
+    let result = settings.rpc_call(&client, "submitblock", params)?;
+    if let Some(reason) = result.as_str() {
+        let rejection = SubmitRejection::from_result(reason);
+        if rejection.is_already_accepted() {
+            println!("✅ [RPC] Node already had this block: {}", rejection);
+            return Ok(());
+        }
+        return Err(SubmitError::Rejected(rejection));
+    }
+
+    println!("✅ [RPC] Block submitted successfully! Check your node logs.");
+    Ok(())
+}
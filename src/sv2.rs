@@ -0,0 +1,478 @@
+//! Stratum V2 transport: the binary data types, message frames, and the
+//! Noise_NX_Secp256k1+EllSwift_ChaChaPoly_SHA256 handshake every SV2 connection starts with.
+//!
+//! Only the initiator (client) side is implemented. The server proves its identity
+//! with a certificate signed by the pool's authority key, which must be configured.
+
+use bitcoin::hashes::{Hash, HashEngine, hmac, sha256};
+use bitcoin::secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1, SecretKey, XOnlyPublicKey, schnorr};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime};
+
+const PROTOCOL_NAME: &[u8] = b"Noise_NX_Secp256k1+EllSwift_ChaChaPoly_SHA256";
+
+/// Length of an ElligatorSwift-encoded public key.
+const ELLSWIFT_LEN: usize = 64;
+
+/// Length of a Poly1305 tag.
+const MAC_LEN: usize = 16;
+
+/// Length of the certificate the server sends during the handshake: version u16,
+/// valid_from u32, not_valid_after u32 and a BIP340 signature.
+const SIGNATURE_MESSAGE_LEN: usize = 74;
+
+/// Largest encrypted chunk, tag included; longer payloads are split.
+const MAX_CHUNK: usize = 65535;
+
+/// Length of a frame header: extension_type u16, msg_type u8, msg_length u24.
+const HEADER_LEN: usize = 6;
+
+/// Set in `extension_type` on messages addressed to a specific channel.
+pub const CHANNEL_BIT: u16 = 0x8000;
+
+/// SV2 protocol version spoken here.
+const SV2_VERSION: u16 = 2;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Subprotocols selected with SetupConnection.
+pub const MINING_PROTOCOL: u8 = 0;
+pub const JOB_DECLARATION_PROTOCOL: u8 = 1;
+
+/// Common message types.
+const SETUP_CONNECTION: u8 = 0x00;
+const SETUP_CONNECTION_SUCCESS: u8 = 0x01;
+const SETUP_CONNECTION_ERROR: u8 = 0x02;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Serializes SV2 data types. Integers are little-endian; variable-length fields
+/// carry a length prefix whose width is part of the type name (B0_255: one byte).
+#[derive(Default)]
+pub struct Encoder(Vec<u8>);
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn f32(&mut self, value: f32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// A 256-bit value, as the 32 bytes given.
+    pub fn u256(&mut self, value: &[u8; 32]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    /// Raw bytes without a length prefix.
+    pub fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    /// B0_255 (and STR0_255 for text).
+    pub fn b0_255(&mut self, bytes: &[u8]) -> io::Result<&mut Self> {
+        let len = u8::try_from(bytes.len()).map_err(|_| invalid(format!("{} bytes do not fit a B0_255 field", bytes.len())))?;
+        Ok(self.u8(len).raw(bytes))
+    }
+
+    /// B0_64K.
+    pub fn b0_64k(&mut self, bytes: &[u8]) -> io::Result<&mut Self> {
+        let len = u16::try_from(bytes.len()).map_err(|_| invalid(format!("{} bytes do not fit a B0_64K field", bytes.len())))?;
+        Ok(self.u16(len).raw(bytes))
+    }
+
+    /// B0_16M.
+    pub fn b0_16m(&mut self, bytes: &[u8]) -> io::Result<&mut Self> {
+        if bytes.len() >= 1 << 24 {
+            return Err(invalid(format!("{} bytes do not fit a B0_16M field", bytes.len())));
+        }
+        Ok(self.raw(&(bytes.len() as u32).to_le_bytes()[..3]).raw(bytes))
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// Parses SV2 data types from a message payload.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Decoder { data }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid(format!("SV2 message truncated: {} more byte(s) expected, {} left", len, self.data.len())));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    /// Bytes not read yet.
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? & 1 == 1)
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn u256(&mut self) -> io::Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into().unwrap())
+    }
+
+    pub fn b0_255(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    pub fn str0_255(&mut self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(self.b0_255()?).into_owned())
+    }
+
+    pub fn b0_64k(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    pub fn b0_16m(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.take(3)?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], 0]) as usize;
+        self.take(len)
+    }
+
+    /// SEQ0_64K[U16].
+    pub fn seq0_64k_u16(&mut self) -> io::Result<Vec<u16>> {
+        let count = self.u16()?;
+        (0..count).map(|_| self.u16()).collect()
+    }
+}
+
+/// One SV2 message.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Extension the message belongs to (0 for the core protocols), without `CHANNEL_BIT`.
+    pub extension_type: u16,
+    pub msg_type: u8,
+    pub payload: Vec<u8>,
+}
+
+/// One direction of an encrypted connection.
+struct CipherState {
+    cipher: ChaCha20Poly1305,
+    nonce: u64,
+}
+
+impl CipherState {
+    fn new(key: &[u8; 32]) -> Self {
+        CipherState { cipher: ChaCha20Poly1305::new(key.into()), nonce: 0 }
+    }
+
+    /// Noise nonces: 32 zero bits followed by the little-endian message counter.
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        nonce
+    }
+
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        // Encryption only fails on inputs far beyond the 64 KiB chunks used here
+        self.cipher.encrypt(&nonce.into(), Payload { msg: plaintext, aad: ad }).expect("ChaCha20-Poly1305 encryption")
+    }
+
+    fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.cipher.decrypt(&nonce.into(), Payload { msg: ciphertext, aad: ad })
+            .map_err(|_| invalid("SV2 message failed authentication; the connection is corrupted or tampered with".to_string()))
+    }
+}
+
+/// Symmetric state of the Noise handshake (Noise spec, section 5.2).
+struct SymmetricState {
+    ck: [u8; 32],
+    h: [u8; 32],
+    cipher: Option<CipherState>,
+}
+
+impl SymmetricState {
+    fn new() -> Self {
+        // The protocol name is longer than a hash, so it is hashed; the prologue is empty
+        let h = sha256::Hash::hash(PROTOCOL_NAME).to_byte_array();
+        let mut state = SymmetricState { ck: h, h, cipher: None };
+        state.mix_hash(&[]);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.h);
+        engine.input(data);
+        self.h = sha256::Hash::from_engine(engine).to_byte_array();
+    }
+
+    fn mix_key(&mut self, input_key_material: &[u8]) {
+        let (ck, key) = hkdf(&self.ck, input_key_material);
+        self.ck = ck;
+        self.cipher = Some(CipherState::new(&key));
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        let plaintext = match &mut self.cipher {
+            Some(cipher) => cipher.decrypt(&self.h, ciphertext)?,
+            None => ciphertext.to_vec(),
+        };
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    /// Derives the two transport keys: initiator to responder, then responder to initiator.
+    fn split(&self) -> (CipherState, CipherState) {
+        let (k1, k2) = hkdf(&self.ck, &[]);
+        (CipherState::new(&k1), CipherState::new(&k2))
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    for part in data {
+        engine.input(part);
+    }
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+/// Noise's two-output HKDF.
+fn hkdf(chaining_key: &[u8; 32], input_key_material: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let temp_key = hmac_sha256(chaining_key, &[input_key_material]);
+    let out1 = hmac_sha256(&temp_key, &[&[0x01]]);
+    let out2 = hmac_sha256(&temp_key, &[&out1, &[0x02]]);
+    (out1, out2)
+}
+
+/// Reads a pool authority public key as shared by SV2 pools: base58check of a
+/// 2-byte version and the x-only key (e.g. "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"),
+/// or the x-only key as 64 hex characters.
+pub fn parse_authority_key(text: &str) -> io::Result<XOnlyPublicKey> {
+    let bytes = match hex::decode(text) {
+        Ok(bytes) if bytes.len() == 32 => bytes,
+        _ => match bitcoin::base58::decode_check(text) {
+            Ok(bytes) if bytes.len() == 34 => bytes[2..].to_vec(),
+            _ => return Err(invalid(format!("'{}' is not an SV2 authority public key", text))),
+        },
+    };
+    XOnlyPublicKey::from_slice(&bytes).map_err(|e| invalid(format!("Invalid SV2 authority public key '{}': {}", text, e)))
+}
+
+/// Receiving half of an encrypted SV2 connection.
+pub struct Sv2Reader {
+    stream: BufReader<TcpStream>,
+    cipher: CipherState,
+}
+
+/// Sending half of an encrypted SV2 connection.
+pub struct Sv2Writer {
+    stream: TcpStream,
+    cipher: CipherState,
+}
+
+/// Connects to an SV2 server, runs the Noise handshake and checks that the server's
+/// static key is certified by `authority` and the certificate is currently valid.
+pub fn connect(address: &str, authority: &XOnlyPublicKey) -> io::Result<(Sv2Reader, Sv2Writer)> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let secp = Secp256k1::new();
+    let mut state = SymmetricState::new();
+
+    // -> e
+    let ephemeral = SecretKey::new(&mut secp256k1::rand::thread_rng());
+    let ephemeral_public = ElligatorSwift::from_seckey(&secp, ephemeral, None);
+    state.mix_hash(&ephemeral_public.to_array());
+    state.mix_hash(&[]);
+    stream.write_all(&ephemeral_public.to_array())?;
+
+    // <- e, ee, s, es, certificate
+    let mut reply = [0u8; ELLSWIFT_LEN + ELLSWIFT_LEN + MAC_LEN + SIGNATURE_MESSAGE_LEN + MAC_LEN];
+    stream.read_exact(&mut reply)?;
+    let (remote_ephemeral, rest) = reply.split_at(ELLSWIFT_LEN);
+    let (encrypted_static, encrypted_certificate) = rest.split_at(ELLSWIFT_LEN + MAC_LEN);
+    let remote_ephemeral = ElligatorSwift::from_array(remote_ephemeral.try_into().unwrap());
+    state.mix_hash(&remote_ephemeral.to_array());
+    let ecdh = |remote: ElligatorSwift| {
+        ElligatorSwift::shared_secret(ephemeral_public, remote, ephemeral, ElligatorSwiftParty::A, None).to_secret_bytes()
+    };
+    state.mix_key(&ecdh(remote_ephemeral));
+    let remote_static = state.decrypt_and_hash(encrypted_static)?;
+    let remote_static = ElligatorSwift::from_array(remote_static.try_into().unwrap());
+    state.mix_key(&ecdh(remote_static));
+    let certificate = state.decrypt_and_hash(encrypted_certificate)?;
+    let server_key = PublicKey::from_ellswift(remote_static).x_only_public_key().0;
+    verify_certificate(&secp, &certificate, &server_key, authority)
+        .map_err(|e| invalid(format!("SV2 server {} failed authentication: {}", address, e)))?;
+
+    stream.set_read_timeout(None)?;
+    let (send, receive) = state.split();
+    let reader = Sv2Reader { stream: BufReader::new(stream.try_clone()?), cipher: receive };
+    Ok((reader, Sv2Writer { stream, cipher: send }))
+}
+
+/// Checks the server certificate: the authority's signature over the validity period
+/// and the server's static key, and that the period includes now.
+fn verify_certificate(
+    secp: &Secp256k1<secp256k1::All>,
+    certificate: &[u8],
+    server_key: &XOnlyPublicKey,
+    authority: &XOnlyPublicKey,
+) -> Result<(), String> {
+    let mut d = Decoder::new(certificate);
+    let (version, valid_from, not_valid_after) = (|| Ok::<_, io::Error>((d.u16()?, d.u32()?, d.u32()?)))().map_err(|e| e.to_string())?;
+    let signature = schnorr::Signature::from_slice(d.data).map_err(|e| e.to_string())?;
+
+    let mut signed = Vec::with_capacity(42);
+    signed.extend_from_slice(&version.to_le_bytes());
+    signed.extend_from_slice(&valid_from.to_le_bytes());
+    signed.extend_from_slice(&not_valid_after.to_le_bytes());
+    signed.extend_from_slice(&server_key.serialize());
+    let digest = secp256k1::Message::from_digest(sha256::Hash::hash(&signed).to_byte_array());
+    secp.verify_schnorr(&signature, &digest, authority)
+        .map_err(|_| "its certificate is not signed by the configured authority key".to_string())?;
+
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+    if now < u64::from(valid_from) || now > u64::from(not_valid_after) {
+        return Err(format!("its certificate is only valid from {} to {} (Unix time)", valid_from, not_valid_after));
+    }
+    Ok(())
+}
+
+impl Sv2Writer {
+    /// Encrypts and sends one message. `channel` sets the channel bit, for messages
+    /// whose first field is a channel id.
+    pub fn send(&mut self, msg_type: u8, channel: bool, payload: &[u8]) -> io::Result<()> {
+        if payload.len() >= 1 << 24 {
+            return Err(invalid(format!("SV2 message of {} bytes is too large", payload.len())));
+        }
+        let extension_type = if channel { CHANNEL_BIT } else { 0 };
+        let mut header = [0u8; HEADER_LEN];
+        header[..2].copy_from_slice(&extension_type.to_le_bytes());
+        header[2] = msg_type;
+        header[3..].copy_from_slice(&(payload.len() as u32).to_le_bytes()[..3]);
+
+        let mut out = self.cipher.encrypt(&[], &header);
+        for chunk in payload.chunks(MAX_CHUNK - MAC_LEN) {
+            out.extend_from_slice(&self.cipher.encrypt(&[], chunk));
+        }
+        self.stream.write_all(&out)?;
+        self.stream.flush()
+    }
+}
+
+impl Sv2Reader {
+    /// Receives and decrypts the next message.
+    pub fn recv(&mut self) -> io::Result<Frame> {
+        let mut encrypted_header = [0u8; HEADER_LEN + MAC_LEN];
+        self.stream.read_exact(&mut encrypted_header)?;
+        let header = self.cipher.decrypt(&[], &encrypted_header)?;
+        let extension_type = u16::from_le_bytes([header[0], header[1]]) & !CHANNEL_BIT;
+        let len = u32::from_le_bytes([header[3], header[4], header[5], 0]) as usize;
+
+        let mut payload = Vec::with_capacity(len);
+        let mut left = len;
+        while left > 0 {
+            let chunk_len = left.min(MAX_CHUNK - MAC_LEN);
+            let mut chunk = vec![0u8; chunk_len + MAC_LEN];
+            self.stream.read_exact(&mut chunk)?;
+            payload.extend_from_slice(&self.cipher.decrypt(&[], &chunk)?);
+            left -= chunk_len;
+        }
+        Ok(Frame { extension_type, msg_type: header[2], payload })
+    }
+}
+
+/// Opens a subprotocol on a fresh connection with SetupConnection and returns the
+/// feature flags the server agreed to.
+pub fn setup_connection(reader: &mut Sv2Reader, writer: &mut Sv2Writer, protocol: u8, flags: u32, address: &str) -> io::Result<u32> {
+    let (host, port) = address.rsplit_once(':').unwrap_or((address, "0"));
+    let payload = Encoder::new()
+        .u8(protocol)
+        .u16(SV2_VERSION)
+        .u16(SV2_VERSION)
+        .u32(flags)
+        .b0_255(host.as_bytes())?
+        .u16(port.parse().unwrap_or(0))
+        .b0_255(b"solo-miner")?
+        .b0_255(b"")?
+        .b0_255(env!("CARGO_PKG_VERSION").as_bytes())?
+        .b0_255(b"")?
+        .finish();
+    writer.send(SETUP_CONNECTION, false, &payload)?;
+
+    let reply = reader.recv()?;
+    let mut d = Decoder::new(&reply.payload);
+    match reply.msg_type {
+        SETUP_CONNECTION_SUCCESS => {
+            let _used_version = d.u16()?;
+            d.u32()
+        },
+        SETUP_CONNECTION_ERROR => {
+            let _flags = d.u32()?;
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("SV2 server {} refused the connection: {}", address, d.str0_255()?)))
+        },
+        other => Err(invalid(format!("SV2 server {} answered SetupConnection with message type {:#04x}", address, other))),
+    }
+}
@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Fields Bitcoin Core includes in every getblocktemplate response.
+#[cfg(feature = "rpc")]
+const REQUIRED_FIELDS: &[&str] = &[
+    "capabilities", "version", "rules", "vbavailable", "vbrequired", "previousblockhash", "transactions",
+    "coinbaseaux", "longpollid", "target", "mintime", "mutable", "noncerange",
+    "sigoplimit", "sizelimit", "curtime", "bits", "height",
+];
+
+/// Fields that depend on the network, the active rules or the server:
+/// pre-segwit nodes have no weight limit, only signet has a challenge, only BIP23 pools send
+/// a workid, an expiry or a latest timestamp, and a server handing out its own coinbase
+/// (`coinbasetxn`) may leave out `coinbasevalue`. One of those two is checked for after parsing.
+#[cfg(feature = "rpc")]
+const OPTIONAL_FIELDS: &[&str] = &[
+    "weightlimit", "default_witness_commitment", "signet_challenge", "workid", "expires", "maxtime",
+    "coinbasevalue", "coinbasetxn",
+];
+
+/// Fields of every entry of `transactions`.
+#[cfg(feature = "rpc")]
+const TRANSACTION_FIELDS: &[&str] = &["data", "txid", "hash", "depends", "fee", "sigops", "weight"];
+
+/// How strictly getblocktemplate responses are parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateParsing {
+    /// Reject templates with unknown or missing fields, to spot a misbehaving server early.
+    Strict,
+    /// Ignore unknown fields and fill in missing optional ones, to keep mining on any compatible server.
+    #[default]
+    Lenient,
+}
+
+/// A transaction offered by getblocktemplate for inclusion in the block.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateTransaction {
+    /// Raw transaction, hex-encoded (including witness data).
+    pub data: String,
+    /// Transaction id (big-endian hex, as displayed).
+    pub txid: String,
+    /// Witness transaction id (big-endian hex), equal to `txid` without witness data.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// 1-based indexes of other template transactions this one spends from.
+    #[serde(default)]
+    pub depends: Vec<usize>,
+    /// Fee paid by this transaction in satoshis.
+    #[serde(default)]
+    pub fee: u64,
+    /// Sigops cost of this transaction as computed by the node (already scaled for segwit).
+    pub sigops: Option<u64>,
+    /// Weight of this transaction, on segwit-aware nodes.
+    #[serde(default)]
+    pub weight: Option<u64>,
+}
+
+/// The coinbase transaction a BIP22 server offers. Described like the template's other
+/// transactions, but only its raw data is needed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateCoinbase {
+    /// Raw transaction, hex-encoded.
+    pub data: String,
+}
+
+/// A complete getblocktemplate response (BIP22/BIP23/BIP145 plus Bitcoin Core extensions).
+/// Only `previousblockhash`, `bits` and `coinbasevalue` or `coinbasetxn` are needed to
+/// mine; in lenient mode every other field falls back to its default when absent.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockTemplate {
+    /// Optional BIP22 features the server supports (e.g., "proposal").
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Preferred block version, including version bits of deployments being signalled.
+    #[serde(default)]
+    pub version: u32,
+    /// Active rules; a '!' prefix marks a rule the miner must understand.
+    #[serde(default)]
+    pub rules: Vec<String>,
+    /// Pending deployments the server can signal, with their version bit.
+    #[serde(default)]
+    pub vbavailable: BTreeMap<String, u32>,
+    /// Version bits the server requires to be set.
+    #[serde(default)]
+    pub vbrequired: u32,
+    /// Hash of the current tip (big-endian hex, as displayed).
+    pub previousblockhash: String,
+    /// Transactions to include after the coinbase, in order.
+    #[serde(default)]
+    pub transactions: Vec<TemplateTransaction>,
+    /// Data the coinbase scriptSig should carry.
+    #[serde(default)]
+    pub coinbaseaux: BTreeMap<String, String>,
+    /// Maximum coinbase output value: subsidy plus all fees, in satoshis. Servers that
+    /// hand out their own coinbase (`coinbasetxn`) may leave it out.
+    #[serde(default)]
+    pub coinbasevalue: Option<u64>,
+    /// BIP22 coinbase transaction to build on, from pool-like servers.
+    #[serde(default)]
+    pub coinbasetxn: Option<TemplateCoinbase>,
+    /// Id to pass back for long polling.
+    #[serde(default)]
+    pub longpollid: Option<String>,
+    /// Full target (big-endian hex) the block hash must not exceed.
+    #[serde(default)]
+    pub target: String,
+    /// Earliest timestamp the block may carry.
+    #[serde(default)]
+    pub mintime: u64,
+    /// Parts of the template the miner may change (e.g., "time", "transactions").
+    #[serde(default)]
+    pub mutable: Vec<String>,
+    /// Range of valid nonces, as hex ("00000000ffffffff").
+    #[serde(default)]
+    pub noncerange: String,
+    /// Maximum sigops cost of the block.
+    #[serde(default)]
+    pub sigoplimit: u64,
+    /// Maximum serialized size of the block.
+    #[serde(default)]
+    pub sizelimit: u64,
+    /// Maximum weight of the block, on segwit-aware servers.
+    #[serde(default)]
+    pub weightlimit: Option<u64>,
+    /// Server's current time, a good header timestamp.
+    #[serde(default)]
+    pub curtime: u64,
+    /// Compact target (nBits) as hex.
+    pub bits: String,
+    /// Height of the block being built.
+    #[serde(default)]
+    pub height: u64,
+    /// Witness commitment scriptPubKey for the coinbase, when segwit is active.
+    #[serde(default)]
+    pub default_witness_commitment: Option<String>,
+    /// Signet block signing challenge, on signet only.
+    #[serde(default)]
+    pub signet_challenge: Option<String>,
+    /// BIP23 work id, to be echoed back on submitblock.
+    #[serde(default)]
+    pub workid: Option<String>,
+    /// BIP23 lifetime of the template in seconds, after which the server may reject it.
+    #[serde(default)]
+    pub expires: Option<u64>,
+    /// BIP23 latest timestamp the block may carry.
+    #[serde(default)]
+    pub maxtime: Option<u64>,
+}
+
+/// The changes to its template the server allows (BIP23 `mutable`), among those the
+/// miner makes: rolling the header time forward, removing transactions, and building
+/// on a tip other than the template's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mutations {
+    pub time: bool,
+    pub transactions: bool,
+    pub prevblock: bool,
+}
+
+impl Mutations {
+    /// Every change allowed, as Bitcoin Core does.
+    pub const ALL: Mutations = Mutations { time: true, transactions: true, prevblock: true };
+
+    /// Reads a template's `mutable` list. Anything not listed is not allowed.
+    pub fn parse(mutable: &[String]) -> Mutations {
+        let listed = |names: &[&str]| mutable.iter().any(|m| names.contains(&m.as_str()));
+        Mutations {
+            time: listed(&["time", "time/increment"]),
+            transactions: listed(&["transactions", "transactions/remove"]),
+            prevblock: listed(&["prevblock"]),
+        }
+    }
+}
+
+#[cfg(feature = "rpc")]
+impl BlockTemplate {
+    /// Parses a getblocktemplate `result`. Strict mode first checks the shape of the
+    /// response, so a server returning extra or fewer fields than Bitcoin Core does is
+    /// reported by name instead of silently mined on.
+    pub fn parse(result: serde_json::Value, mode: TemplateParsing) -> Result<BlockTemplate, String> {
+        if mode == TemplateParsing::Strict {
+            check_fields(&result, REQUIRED_FIELDS, OPTIONAL_FIELDS, "template")?;
+            if let Some(transactions) = result.get("transactions").and_then(|t| t.as_array()) {
+                for (i, tx) in transactions.iter().enumerate() {
+                    check_fields(tx, TRANSACTION_FIELDS, &[], &format!("transaction {}", i + 1))?;
+                }
+            }
+        }
+        let template: BlockTemplate = serde_json::from_value(result).map_err(|e| e.to_string())?;
+        if template.coinbasevalue.is_none() && template.coinbasetxn.is_none() {
+            return Err("missing field `coinbasevalue`, and no `coinbasetxn` either".to_string());
+        }
+        Ok(template)
+    }
+}
+
+/// Checks that `value` is an object with every `required` field and nothing beyond
+/// `required` and `optional`.
+#[cfg(feature = "rpc")]
+fn check_fields(value: &serde_json::Value, required: &[&str], optional: &[&str], what: &str) -> Result<(), String> {
+    let object = value.as_object().ok_or_else(|| format!("{} is not a JSON object", what))?;
+    if let Some(key) = object.keys().find(|k| !required.contains(&k.as_str()) && !optional.contains(&k.as_str())) {
+        return Err(format!("unexpected field '{}' in {} (strict template_parsing)", key, what));
+    }
+    if let Some(key) = required.iter().find(|k| !object.contains_key(**k)) {
+        return Err(format!("missing field '{}' in {} (strict template_parsing)", key, what));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "rpc"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A template with exactly the fields Bitcoin Core sends.
+    fn core_template() -> serde_json::Value {
+        json!({
+            "capabilities": ["proposal"], "version": 0x2000_0000, "rules": ["csv", "!segwit", "taproot"],
+            "vbavailable": {}, "vbrequired": 0,
+            "previousblockhash": "0000000000000000000000000000000000000000000000000000000000000000",
+            "transactions": [{
+                "data": "00", "txid": "11", "hash": "11", "depends": [], "fee": 100, "sigops": 4, "weight": 400,
+            }],
+            "coinbaseaux": {}, "coinbasevalue": 5_000_000_000u64, "longpollid": "0", "target": "7fffff",
+            "mintime": 0, "mutable": ["time", "transactions", "prevblock"], "noncerange": "00000000ffffffff",
+            "sigoplimit": 80000, "sizelimit": 4000000, "weightlimit": 4000000, "curtime": 0, "bits": "207fffff",
+            "height": 1, "default_witness_commitment": "6a24aa21a9ed",
+        })
+    }
+
+    fn without(mut template: serde_json::Value, field: &str) -> serde_json::Value {
+        template.as_object_mut().unwrap().remove(field);
+        template
+    }
+
+    #[test]
+    fn strict_accepts_what_core_sends() {
+        let template = BlockTemplate::parse(core_template(), TemplateParsing::Strict).unwrap();
+        assert_eq!(template.coinbasevalue, Some(5_000_000_000));
+        assert_eq!(template.transactions.len(), 1);
+        // Core on a network without segwit, or signet, changes only optional fields.
+        assert!(BlockTemplate::parse(without(core_template(), "weightlimit"), TemplateParsing::Strict).is_ok());
+        let mut signet = core_template();
+        signet["signet_challenge"] = json!("51");
+        assert!(BlockTemplate::parse(signet, TemplateParsing::Strict).is_ok());
+    }
+
+    #[test]
+    fn strict_rejects_missing_and_unknown_fields() {
+        let e = BlockTemplate::parse(without(core_template(), "sigoplimit"), TemplateParsing::Strict).unwrap_err();
+        assert!(e.contains("missing field 'sigoplimit'"), "{}", e);
+        let mut extra = core_template();
+        extra["surprise"] = json!(1);
+        let e = BlockTemplate::parse(extra, TemplateParsing::Strict).unwrap_err();
+        assert!(e.contains("unexpected field 'surprise'"), "{}", e);
+        let mut tx_extra = core_template();
+        tx_extra["transactions"][0]["surprise"] = json!(1);
+        let e = BlockTemplate::parse(tx_extra, TemplateParsing::Strict).unwrap_err();
+        assert!(e.contains("in transaction 1"), "{}", e);
+
+        // Lenient parsing lets all of these through.
+        let mut sparse = without(core_template(), "sigoplimit");
+        sparse["surprise"] = json!(1);
+        assert!(BlockTemplate::parse(sparse, TemplateParsing::Lenient).is_ok());
+    }
+
+    #[test]
+    fn coinbasetxn_stands_in_for_coinbasevalue() {
+        let mut template = without(core_template(), "coinbasevalue");
+        template["coinbasetxn"] = json!({"data": "00"});
+        for mode in [TemplateParsing::Strict, TemplateParsing::Lenient] {
+            let parsed = BlockTemplate::parse(template.clone(), mode).unwrap();
+            assert_eq!(parsed.coinbasevalue, None);
+            assert_eq!(parsed.coinbasetxn.unwrap().data, "00");
+        }
+    }
+
+    #[test]
+    fn coinbasevalue_or_coinbasetxn_is_required() {
+        for mode in [TemplateParsing::Strict, TemplateParsing::Lenient] {
+            let e = BlockTemplate::parse(without(core_template(), "coinbasevalue"), mode).unwrap_err();
+            assert!(e.contains("coinbasevalue"), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Step-by-step SHA-256d of a block header (the `trace-hash` subcommand): padding,
+//! message schedules, the state after every compression and the final hash, so the
+//! proof of work can be followed by hand. Compression is done here in plain Rust
+//! and checked against `pow`, which hashes with sha2.
+
+use crate::pow;
+
+/// SHA-256 round constants (FIPS 180-4, 4.2.2).
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Everything one compression computes.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    /// The expanded message schedule: W0 to W15 are the block's words, the rest derived.
+    pub schedule: [u32; 64],
+    /// Working variables a to h after each round.
+    pub rounds: [[u32; 8]; 64],
+    /// The chaining state after the compression (the rounds' result added to the input state).
+    pub state: [u32; 8],
+}
+
+/// Compresses one 64-byte block into `state`, keeping every intermediate value.
+pub fn compress(state: &[u32; 8], block: &[u8; 64]) -> Compression {
+    let mut w = [0u32; 64];
+    for (word, chunk) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes(chunk.try_into().expect("4-byte chunk"));
+    }
+    for t in 16..64 {
+        let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+        let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+        w[t] = w[t - 16].wrapping_add(s0).wrapping_add(w[t - 7]).wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    let mut rounds = [[0u32; 8]; 64];
+    for t in 0..64 {
+        let [a, b, c, d, e, f, g, h] = v;
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[t]).wrapping_add(w[t]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        v = [t1.wrapping_add(t2), a, b, c, d.wrapping_add(t1), e, f, g];
+        rounds[t] = v;
+    }
+    let mut out = *state;
+    for (word, add) in out.iter_mut().zip(v) {
+        *word = word.wrapping_add(add);
+    }
+    Compression { schedule: w, rounds, state: out }
+}
+
+/// The message padded to whole 64-byte blocks: a 1 bit, zeros, then the bit length.
+pub fn pad(message: &[u8]) -> Vec<[u8; 64]> {
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+    padded.chunks_exact(64).map(|chunk| chunk.try_into().expect("64-byte block")).collect()
+}
+
+/// Prints the double SHA-256 of `header` step by step, with every round's working
+/// variables if `show_rounds`. Returns the hash in internal byte order.
+pub fn print_trace(header: &[u8; 80], show_rounds: bool) -> [u8; 32] {
+    let word = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().expect("4 bytes"));
+    let reversed = |bytes: &[u8]| hex::encode(bytes.iter().rev().copied().collect::<Vec<u8>>());
+    println!("\n--- Header ({} bytes) ---", header.len());
+    println!("  version      {:08x}", word(0));
+    println!("  prev block   {}", reversed(&header[4..36]));
+    println!("  merkle root  {}", reversed(&header[36..68]));
+    println!("  time         {}", word(68));
+    println!("  nBits        {:08x}", word(72));
+    println!("  nonce        {} ({:08x})", word(76), word(76));
+    println!("  Fields are little-endian on the wire; hashes are shown in the usual reversed order.");
+
+    println!("\n--- First SHA-256: 80 bytes, padded to 2 blocks ---");
+    let mut state = pow::SHA256_IV;
+    print_state("Initial hash value (IV)", &state);
+    for (i, block) in pad(header).iter().enumerate() {
+        state = print_compression(&format!("Block {}", i + 1), &state, block, show_rounds);
+        if i == 0 {
+            println!("  This is the midstate: the first 64 bytes don't contain the nonce, so miners compute it once per job.");
+            debug_assert_eq!(state, pow::midstate(header));
+        }
+    }
+    let first = words_to_bytes(&state);
+    println!("\nFirst hash: {}", hex::encode(first));
+
+    println!("\n--- Second SHA-256: 32 bytes, padded to 1 block ---");
+    let mut state = pow::SHA256_IV;
+    for block in pad(&first) {
+        state = print_compression("Block 1", &state, &block, show_rounds);
+    }
+    let hash = words_to_bytes(&state);
+    println!("\nSecond hash (internal order): {}", hex::encode(hash));
+    println!("Block hash (reversed, as shown by explorers): {}", reversed(&hash));
+
+    match pow::target_from_nbits(word(72)) {
+        Some(target) => {
+            println!("Target from nBits:                           {}", hex::encode(target.to_be_bytes()));
+            if pow::hash_value(&hash) <= target {
+                println!("The hash is at or below the target: this header is a valid proof of work.");
+            } else {
+                println!("The hash is above the target: not a valid block (that's the lottery).");
+            }
+        },
+        None => println!("nBits {:08x} is not a valid compact target (negative or over 256 bits): no hash meets it.", word(72)),
+    }
+    let check = pow::sha256d(header);
+    println!("Cross-check with sha2: {}", if check == hash { "matches" } else { "MISMATCH" });
+    hash
+}
+
+fn print_compression(label: &str, state: &[u32; 8], block: &[u8; 64], show_rounds: bool) -> [u32; 8] {
+    let compression = compress(state, block);
+    println!("\n{}: {}", label, hex::encode(block));
+    println!("  Message schedule:");
+    for (row, words) in compression.schedule.chunks(8).enumerate() {
+        let words: Vec<String> = words.iter().map(|w| format!("{:08x}", w)).collect();
+        println!("    W{:<2}..W{:<2} {}", row * 8, row * 8 + 7, words.join(" "));
+    }
+    if show_rounds {
+        println!("  Rounds (a b c d e f g h):");
+        for (t, v) in compression.rounds.iter().enumerate() {
+            let words: Vec<String> = v.iter().map(|w| format!("{:08x}", w)).collect();
+            println!("    {:>2} {}", t, words.join(" "));
+        }
+    }
+    print_state("  State after compression", &compression.state);
+    compression.state
+}
+
+fn print_state(label: &str, state: &[u32; 8]) {
+    let words: Vec<String> = state.iter().map(|w| format!("{:08x}", w)).collect();
+    println!("{}: {}", label, words.join(" "));
+}
+
+fn words_to_bytes(state: &[u32; 8]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    bytes
+}
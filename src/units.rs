@@ -0,0 +1,74 @@
+/// Formats a hash rate with an automatically scaled SI unit (H/s, kH/s, MH/s, ...).
+/// Every reporter (console, stats, notifications) should go through this so the
+/// same rate always reads the same way.
+pub fn format_hashrate(hashes_per_sec: f64) -> String {
+    const UNITS: [&str; 7] = ["H/s", "kH/s", "MH/s", "GH/s", "TH/s", "PH/s", "EH/s"];
+
+    let mut value = hashes_per_sec;
+    let mut unit = 0;
+    while value.abs() >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.3} {}", value, UNITS[unit])
+}
+
+/// Formats a duration in seconds with the largest fitting unit (s, min, h, days, years).
+pub fn format_duration(secs: f64) -> String {
+    const UNITS: [(&str, f64); 5] = [("years", 365.25 * 86400.0), ("days", 86400.0), ("h", 3600.0), ("min", 60.0), ("s", 1.0)];
+
+    for (unit, size) in UNITS {
+        if secs.abs() >= size {
+            return format!("{:.1} {}", secs / size, unit);
+        }
+    }
+    format!("{:.1} s", secs)
+}
+
+/// Formats a Unix timestamp as a UTC date and time (YYYY-MM-DD HH:MM UTC).
+pub fn format_utc(unix: u64) -> String {
+    let (year, month, day) = civil_from_days((unix / 86400) as i64);
+    let secs_of_day = unix % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, secs_of_day / 3600, secs_of_day % 3600 / 60)
+}
+
+/// Formats a Unix timestamp as an RFC 3339 UTC timestamp with seconds, for logs.
+pub fn format_timestamp(unix: u64) -> String {
+    let (year, month, day) = civil_from_days((unix / 86400) as i64);
+    let secs_of_day = unix % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs_of_day / 3600, secs_of_day % 3600 / 60, secs_of_day % 60
+    )
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date
+/// (Howard Hinnant's civil-from-days algorithm).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Formats an amount in satoshis as BTC, trimming trailing zeros (e.g. 3.125).
+pub fn format_btc(sats: u64) -> String {
+    let formatted = format!("{}.{:08}", sats / 100_000_000, sats % 100_000_000);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// How messages name a network.
+pub fn format_network(network: bitcoin::Network) -> &'static str {
+    match network {
+        bitcoin::Network::Bitcoin => "mainnet",
+        bitcoin::Network::Testnet => "testnet3",
+        bitcoin::Network::Testnet4 => "testnet4",
+        bitcoin::Network::Signet => "signet",
+        bitcoin::Network::Regtest => "regtest",
+    }
+}
@@ -0,0 +1,113 @@
+use crate::assembly::AssembledBlock;
+use crate::miner::{COINBASE_SCRIPT_SIG_MAX, COINBASE_SCRIPT_SIG_MIN};
+use crate::settings::MinerSettings;
+use std::collections::HashMap;
+use std::{io, time};
+use bitcoin::hashes::Hash;
+
+/// Checks an assembled block against the consensus rules before any hashing starts:
+/// coinbase shape and value, merkle root, witness commitment, and every input script
+/// through libbitcoinconsensus, the script interpreter Bitcoin Core itself uses.
+/// Proof of work is not checked; the header is verified with nonce 0.
+///
+/// Outputs spent from earlier transactions in the block are taken from the block; all
+/// others must be confirmed and are fetched from the node with gettxout.
+pub fn check_block(settings: &MinerSettings, assembled: &AssembledBlock, merkle_root: &[u8; 32]) -> io::Result<()> {
+    let start = time::Instant::now();
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("Assembled block fails consensus checks: {}", msg));
+
+    let block = bitcoin::Block {
+        header: bitcoin::block::Header {
+            version: bitcoin::block::Version::from_consensus(settings.version as i32),
+            prev_blockhash: bitcoin::BlockHash::all_zeros(),
+            merkle_root: bitcoin::TxMerkleNode::from_byte_array(*merkle_root),
+            time: settings.timestamp,
+            bits: bitcoin::CompactTarget::from_consensus(settings.nbits),
+            nonce: 0,
+        },
+        txdata: assembled.txdata.clone(),
+    };
+
+    // 1. Exactly one coinbase, first, with a well-formed scriptSig and no more than it may claim.
+    let coinbase = match block.txdata.first() {
+        Some(tx) if tx.is_coinbase() => tx,
+        _ => return Err(invalid("the first transaction is not a coinbase".to_string())),
+    };
+    if let Some(tx) = block.txdata.iter().skip(1).find(|tx| tx.is_coinbase()) {
+        return Err(invalid(format!("transaction {} is a second coinbase", tx.compute_txid())));
+    }
+    let script_sig_len = coinbase.input[0].script_sig.len();
+    if !(COINBASE_SCRIPT_SIG_MIN..=COINBASE_SCRIPT_SIG_MAX).contains(&script_sig_len) {
+        return Err(invalid(format!(
+            "the coinbase scriptSig is {} bytes, outside {}..={}", script_sig_len, COINBASE_SCRIPT_SIG_MIN, COINBASE_SCRIPT_SIG_MAX
+        )));
+    }
+    let claimed: u64 = coinbase.output.iter().map(|o| o.value.to_sat()).sum();
+    if claimed > settings.block_reward_sats {
+        return Err(invalid(format!(
+            "the coinbase pays {} sats, more than the {} sats of subsidy and fees available", claimed, settings.block_reward_sats
+        )));
+    }
+
+    // 2. Commitments to the transactions.
+    if !block.check_merkle_root() {
+        return Err(invalid("the merkle root does not match the transactions".to_string()));
+    }
+    if !block.check_witness_commitment() {
+        return Err(invalid("the coinbase witness commitment does not match the witness data".to_string()));
+    }
+
+    // 3. Every input script, in block order so parents are seen before their children.
+    let client = settings.http_client()?;
+    let mut created: HashMap<bitcoin::OutPoint, bitcoin::TxOut> = HashMap::new();
+    let mut inputs = 0;
+    for tx in block.txdata.iter().skip(1) {
+        let mut lookup_error = None;
+        let result = tx.verify(|outpoint| {
+            created.remove(outpoint).or_else(|| match fetch_confirmed_output(settings, &client, outpoint) {
+                Ok(output) => output,
+                Err(e) => {
+                    lookup_error = Some(e);
+                    None
+                },
+            })
+        });
+        if let Some(e) = lookup_error {
+            return Err(e);
+        }
+        result.map_err(|e| invalid(format!("transaction {}: {}", tx.compute_txid(), e)))?;
+        inputs += tx.input.len();
+
+        let txid = tx.compute_txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            created.insert(bitcoin::OutPoint { txid, vout: vout as u32 }, output.clone());
+        }
+    }
+
+    println!(
+        "[Consensus] Block passed libbitcoinconsensus checks: {} transaction(s), {} input script(s) in {:.1?}.",
+        block.txdata.len(), inputs, start.elapsed()
+    );
+    Ok(())
+}
+
+/// Looks up an unspent confirmed output with gettxout. Outputs already spent in the
+/// mempool are deliberately included, since the template's own transactions spend them.
+fn fetch_confirmed_output(
+    settings: &MinerSettings,
+    client: &reqwest::blocking::Client,
+    outpoint: &bitcoin::OutPoint,
+) -> io::Result<Option<bitcoin::TxOut>> {
+    let result = settings.rpc_call(client, "gettxout", serde_json::json!([outpoint.txid.to_string(), outpoint.vout, false]))?;
+    if result.is_null() {
+        return Ok(None);
+    }
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("gettxout returned no {} for {}", what, outpoint));
+    let value = result["value"].as_f64()
+        .and_then(|btc| bitcoin::Amount::from_btc(btc).ok())
+        .ok_or_else(|| invalid("value"))?;
+    let script_pubkey = result["scriptPubKey"]["hex"].as_str()
+        .and_then(|h| hex::decode(h).ok())
+        .ok_or_else(|| invalid("scriptPubKey"))?;
+    Ok(Some(bitcoin::TxOut { value, script_pubkey: bitcoin::ScriptBuf::from_bytes(script_pubkey) }))
+}
@@ -0,0 +1,64 @@
+use crate::settings::MinerSettings;
+use serde::Deserialize;
+use std::io;
+
+/// Coinbase outputs can only be spent once they are this many blocks deep.
+const COINBASE_MATURITY: u64 = 100;
+
+/// The subset of gettransaction we use.
+#[derive(Deserialize)]
+struct WalletTransaction {
+    amount: f64,
+    confirmations: i64,
+    blockheight: Option<u64>,
+    #[serde(default)]
+    details: Vec<WalletTransactionDetail>,
+}
+
+#[derive(Deserialize)]
+struct WalletTransactionDetail {
+    category: String,
+}
+
+/// After the node accepts our block, asks its wallet whether the coinbase reward
+/// shows up and tells the user when it becomes spendable.
+///
+/// If the reward address isn't watched by the node's wallet (or the node runs
+/// without one), the maturity height is still computed from the block itself.
+pub fn report_coinbase(settings: &MinerSettings, block: &bitcoin::Block) {
+    if let Err(e) = try_report_coinbase(settings, block) {
+        eprintln!("[Wallet] Could not check the coinbase reward: {}", e);
+    }
+}
+
+fn try_report_coinbase(settings: &MinerSettings, block: &bitcoin::Block) -> io::Result<()> {
+    let client = settings.http_client()?;
+    let txid = block.txdata[0].compute_txid().to_string();
+    let block_hash = block.block_hash().to_string();
+
+    let height = match settings.rpc_call(&client, "getblockheader", serde_json::json!([block_hash, true])) {
+        Ok(header) => header.get("height").and_then(|h| h.as_u64()),
+        Err(_) => None,
+    };
+
+    match settings.rpc_call(&client, "gettransaction", serde_json::json!([txid])) {
+        Ok(result) => {
+            let tx: WalletTransaction = serde_json::from_value(result)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected gettransaction result: {}", e)))?;
+            let category = tx.details.first().map(|d| d.category.as_str()).unwrap_or("unknown");
+            println!("\n[Wallet] ✅ Your wallet sees the reward: {:.8} BTC in coinbase {} ({}, {} confirmation(s)).",
+                tx.amount, txid, category, tx.confirmations);
+            if let Some(h) = tx.blockheight.or(height) {
+                println!("[Wallet] It becomes spendable at height {} (after {} confirmations).", h + COINBASE_MATURITY, COINBASE_MATURITY);
+            }
+        },
+        Err(e) => {
+            println!("\n[Wallet] The node's wallet doesn't know coinbase {} ({}).", txid, e);
+            println!("[Wallet] That's expected if {} belongs to another wallet; check it there.", settings.reward_address);
+            if let Some(h) = height {
+                println!("[Wallet] The reward becomes spendable at height {} (after {} confirmations).", h + COINBASE_MATURITY, COINBASE_MATURITY);
+            }
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! Watch-only mode (`--watch`): follows the node's templates and the blocks the
+//! network finds, building the coinbase, merkle root and header the miner would hash,
+//! without ever hashing. Useful to check a configuration end to end, or to follow
+//! along on a machine that shouldn't burn CPU.
+
+use crate::audit::{self, TemplateSnapshot};
+use crate::chain::{self, TipChange};
+use crate::events::{EventBus, MinerEvent};
+use crate::miner;
+use crate::pow;
+use crate::settings::MinerSettings;
+use crate::stats::{self, JobInfo, StatsHandle};
+use crate::units::format_btc;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the node is asked whether the network found a block.
+const TIP_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Runs until the node can't be reached: fetches a template, builds the job from it,
+/// and waits for the network to find the block, refreshing the template meanwhile.
+pub fn run(settings: &mut MinerSettings, stats: &StatsHandle, events: &EventBus) -> io::Result<()> {
+    println!("\n[Watch] Watch-only mode: every template is assembled, but never hashed.");
+    let client = settings.http_client()?;
+    // The template is refetched while the tip stays the same, to follow new transactions
+    // and fees as they arrive
+    let refresh = (settings.template_refresh_secs > 0).then(|| Duration::from_secs(settings.template_refresh_secs));
+    loop {
+        settings.update_from_node()?;
+        let tip = settings.prev_block_hash.clone();
+        let mut job = build_job(settings, stats, events)?;
+        let mut fetched = Instant::now();
+        loop {
+            thread::sleep(TIP_POLL_INTERVAL);
+            match chain::check_tip(settings, &client, &tip) {
+                Ok(None) => {},
+                Ok(Some(change)) => {
+                    report_tip_change(settings, &client, change, &job);
+                    break;
+                },
+                Err(e) => eprintln!("[Watch] Could not check the chain tip: {}", e),
+            }
+            if refresh.is_some_and(|refresh| fetched.elapsed() >= refresh) {
+                settings.update_from_node()?;
+                job = build_job(settings, stats, events)?;
+                fetched = Instant::now();
+            }
+        }
+    }
+}
+
+/// Assembles the template in `settings` exactly as for mining, and prints the coinbase,
+/// merkle root and header the miner would hash.
+fn build_job(settings: &MinerSettings, stats: &StatsHandle, events: &EventBus) -> io::Result<TemplateSnapshot> {
+    let (merkle_root, block) = miner::assemble_job(settings)?;
+    let mut prev_hash = [0u8; 32];
+    hex::decode_to_slice(&settings.prev_block_hash, &mut prev_hash)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid prev_block_hash: {}", e)))?;
+    prev_hash.reverse();
+    let header = pow::build_header(settings.version, &prev_hash, &merkle_root, settings.timestamp, settings.nbits, 0);
+
+    let coinbase = &block.txdata[0];
+    let payout: u64 = coinbase.output.iter().map(|output| output.value.to_sat()).sum();
+    let mut merkle_root_display = merkle_root;
+    merkle_root_display.reverse();
+    println!("[Watch] Height {}: coinbase {} pays {} BTC in {} output(s).", settings.height, coinbase.compute_txid(), format_btc(payout), coinbase.output.len());
+    println!("[Watch] Merkle root: {}", hex::encode(merkle_root_display));
+    println!("[Watch] Header (nonce 0): {}", hex::encode(header));
+
+    let transactions = block.txdata.len();
+    stats.update(|s| s.job = Some(JobInfo {
+        job_id: settings.job_id.clone(),
+        prev_block_hash: settings.prev_block_hash.clone(),
+        nbits: settings.nbits,
+        transactions,
+        fees_sats: block.fees,
+        started_at: stats::unix_now(),
+    }));
+    events.publish(MinerEvent::TemplateReceived {
+        job_id: settings.job_id.clone(),
+        prev_block_hash: settings.prev_block_hash.clone(),
+        nbits: settings.nbits,
+        transactions,
+        fees_sats: block.fees,
+    });
+    Ok(TemplateSnapshot::new(settings, &block))
+}
+
+/// Logs a block found by the network next to the job we had for that height, in full
+/// with `audit_blocks`.
+fn report_tip_change(settings: &MinerSettings, client: &reqwest::blocking::Client, change: TipChange, job: &TemplateSnapshot) {
+    match change {
+        TipChange::Extended { new_tip, height } => {
+            println!("\n[Watch] The network found block {} at height {}.", new_tip, height);
+            if settings.audit_blocks {
+                if let Err(e) = audit::audit_height(settings, client, job) {
+                    eprintln!("[Audit] Could not audit the block at height {}: {}", job.height, e);
+                }
+                return;
+            }
+            // getblockstats needs no index for blocks in the active chain
+            let found = settings.rpc_call(client, "getblockstats", serde_json::json!([new_tip, ["txs", "totalfee"]]));
+            let theirs = match &found {
+                Ok(block) => format!("{} transaction(s) and {} sats in fees", block["txs"], block["totalfee"]),
+                Err(_) => "unknown contents".to_string(),
+            };
+            println!(
+                "[Watch] It has {}; our job for height {} had {} transaction(s) and {} sats in fees.",
+                theirs, job.height, job.txids.len() + 1, job.fees_sats
+            );
+        },
+        TipChange::Reorg { new_tip, depth } => {
+            println!("\n[Watch] Reorganization: {} block(s) disconnected; new tip {}.", depth, new_tip);
+        },
+    }
+}
@@ -0,0 +1,421 @@
+//! Host side of the worker protocol, which lets small boards (ESP32, RP2040, ...)
+//! running a tiny SHA-256d loop hash for this miner over a serial line or TCP.
+//!
+//! Every message is a frame `A5 | type | len | payload[len] | xor`, where `xor` is
+//! the XOR of the type, length and payload bytes. Integers are little-endian.
+//! A receiver that loses sync skips bytes until the next `A5` with a valid checksum.
+//!
+//! | type | direction | payload |
+//! |------|-----------|---------|
+//! | `01` JOB | host → worker | job id u8, first nonce u32, last nonce u32, midstate 8×u32, header bytes 64..76, target (32 bytes, big-endian) |
+//! | `02` STOP | host → worker | — |
+//! | `03` PING | host → worker | — |
+//! | `81` HELLO | worker → host | protocol version u8, hash rate u32 (H/s), name (ASCII) |
+//! | `82` NONCE | worker → host | job id u8, nonce u32 |
+//! | `83` DONE | worker → host | job id u8, hashes u32 |
+//!
+//! A worker answers PING with HELLO, and for a JOB hashes every nonce of the range
+//! (see `pow::scan_midstate`), sending NONCE for each hash at or below the target and
+//! DONE at the end. A new JOB or a STOP abandons the current one.
+
+use crate::pow;
+use crate::stats::StatsHandle;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAGIC: u8 = 0xa5;
+
+/// Version sent in HELLO by workers speaking this protocol.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Nonces handed to a worker at a time: a few seconds to minutes of work for a microcontroller.
+const SLICE_SIZE: u64 = 1 << 22;
+
+/// Serial speed used when a worker spec doesn't give one.
+const DEFAULT_BAUD: u32 = 115_200;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// A message of the worker protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Job { job_id: u8, nonces: RangeInclusive<u32>, midstate: [u32; 8], tail: [u8; 12], target: [u8; 32] },
+    Stop,
+    Ping,
+    Hello { version: u8, hashrate: u32, name: String },
+    Nonce { job_id: u8, nonce: u32 },
+    Done { job_id: u8, hashes: u32 },
+}
+
+impl Message {
+    /// Serializes the message into one frame.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let kind = match self {
+            Message::Job { job_id, nonces, midstate, tail, target } => {
+                payload.push(*job_id);
+                payload.extend_from_slice(&nonces.start().to_le_bytes());
+                payload.extend_from_slice(&nonces.end().to_le_bytes());
+                for word in midstate {
+                    payload.extend_from_slice(&word.to_le_bytes());
+                }
+                payload.extend_from_slice(tail);
+                payload.extend_from_slice(target);
+                0x01
+            },
+            Message::Stop => 0x02,
+            Message::Ping => 0x03,
+            Message::Hello { version, hashrate, name } => {
+                payload.push(*version);
+                payload.extend_from_slice(&hashrate.to_le_bytes());
+                payload.extend(name.bytes().filter(u8::is_ascii).take(u8::MAX as usize - 5));
+                0x81
+            },
+            Message::Nonce { job_id, nonce } => {
+                payload.push(*job_id);
+                payload.extend_from_slice(&nonce.to_le_bytes());
+                0x82
+            },
+            Message::Done { job_id, hashes } => {
+                payload.push(*job_id);
+                payload.extend_from_slice(&hashes.to_le_bytes());
+                0x83
+            },
+        };
+        let mut frame = vec![MAGIC, kind, payload.len() as u8];
+        frame.extend_from_slice(&payload);
+        frame.push(checksum(kind, &payload));
+        frame
+    }
+
+    /// Reads the next valid frame, skipping noise, corrupted frames and unknown types.
+    pub fn read(reader: &mut impl Read) -> io::Result<Message> {
+        let mut byte = [0u8; 1];
+        loop {
+            reader.read_exact(&mut byte)?;
+            if byte[0] != MAGIC {
+                continue;
+            }
+            let mut head = [0u8; 2];
+            reader.read_exact(&mut head)?;
+            let [kind, len] = head;
+            let mut payload = vec![0u8; len as usize + 1];
+            reader.read_exact(&mut payload)?;
+            let sum = payload.pop().unwrap_or_default();
+            if sum != checksum(kind, &payload) {
+                continue;
+            }
+            if let Some(message) = Message::decode(kind, &payload) {
+                return Ok(message);
+            }
+        }
+    }
+
+    fn decode(kind: u8, p: &[u8]) -> Option<Message> {
+        let u32_at = |i: usize| p.get(i..i + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+        match kind {
+            0x01 if p.len() == 85 => {
+                let mut midstate = [0u32; 8];
+                for (i, word) in midstate.iter_mut().enumerate() {
+                    *word = u32_at(9 + 4 * i)?;
+                }
+                Some(Message::Job {
+                    job_id: p[0],
+                    nonces: u32_at(1)?..=u32_at(5)?,
+                    midstate,
+                    tail: p[41..53].try_into().ok()?,
+                    target: p[53..85].try_into().ok()?,
+                })
+            },
+            0x02 => Some(Message::Stop),
+            0x03 => Some(Message::Ping),
+            0x81 if p.len() >= 5 => Some(Message::Hello {
+                version: p[0],
+                hashrate: u32_at(1)?,
+                name: String::from_utf8_lossy(&p[5..]).into_owned(),
+            }),
+            0x82 if p.len() == 5 => Some(Message::Nonce { job_id: p[0], nonce: u32_at(1)? }),
+            0x83 if p.len() == 5 => Some(Message::Done { job_id: p[0], hashes: u32_at(1)? }),
+            _ => None,
+        }
+    }
+}
+
+fn checksum(kind: u8, payload: &[u8]) -> u8 {
+    payload.iter().fold(kind ^ payload.len() as u8, |acc, b| acc ^ b)
+}
+
+/// A header found by a worker to meet the job target, to be checked by the host.
+pub struct Candidate {
+    /// Name the worker gave in HELLO (or its address).
+    pub worker: String,
+    /// The job header with the worker's nonce filled in.
+    pub header: [u8; 80],
+}
+
+/// The job the workers are currently hashing.
+struct PoolJob {
+    id: u8,
+    header: [u8; 80],
+    target: [u8; 32],
+    /// Workers take slices from the top half of the job's nonces, downwards, down to this
+    /// floor; the CPU loop starts at the bottom.
+    floor: u64,
+    /// Nonces below this bound (and above the floor) are not handed out yet.
+    next_top: u64,
+}
+
+/// One connected worker, as seen by the pool.
+#[derive(Default)]
+struct WorkerSlot {
+    writer: Option<Box<dyn Write + Send>>,
+    /// When the current slice was sent, to measure the worker's speed.
+    slice_sent: Option<Instant>,
+    hashrate: f64,
+}
+
+struct Shared {
+    job: Mutex<Option<PoolJob>>,
+    slots: Mutex<Vec<WorkerSlot>>,
+    stats: StatsHandle,
+}
+
+/// Connections to the configured workers, kept open (and reopened) across templates.
+pub struct WorkerPool {
+    shared: Arc<Shared>,
+    candidates: mpsc::Receiver<Candidate>,
+    next_job_id: AtomicU8,
+}
+
+/// Stops the workers when the job it was returned for ends.
+pub struct JobGuard<'a> {
+    pool: &'a WorkerPool,
+}
+
+impl WorkerPool {
+    /// Starts a connection thread per worker spec: "host:port" for TCP, or a serial
+    /// device such as "/dev/ttyUSB0", optionally with a speed ("/dev/ttyACM0@921600").
+    pub fn start(specs: &[String], stats: StatsHandle) -> WorkerPool {
+        let shared = Arc::new(Shared {
+            job: Mutex::new(None),
+            slots: Mutex::new(specs.iter().map(|_| WorkerSlot::default()).collect()),
+            stats,
+        });
+        let (tx, candidates) = mpsc::channel();
+        for (index, spec) in specs.iter().enumerate() {
+            let (shared, tx, spec) = (Arc::clone(&shared), tx.clone(), spec.clone());
+            thread::spawn(move || run_worker(&shared, index, &spec, &tx));
+        }
+        WorkerPool { shared, candidates, next_job_id: AtomicU8::new(0) }
+    }
+
+    /// Hands out slices of a new job built on `header` (whose nonce is ignored) to every
+    /// connected worker, replacing the previous job. Workers get the top half of `nonces`
+    /// and keep receiving slices as they finish them until the returned guard is dropped.
+    pub fn start_job(&self, header: &[u8; 80], target: &[u8; 32], nonces: &RangeInclusive<u32>) -> JobGuard<'_> {
+        let id = self.next_job_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        // Drop candidates of the previous job that were not collected
+        while self.candidates.try_recv().is_ok() {}
+        let (first, last) = (u64::from(*nonces.start()), u64::from(*nonces.end()));
+        *lock(&self.shared.job) = Some(PoolJob {
+            id,
+            header: *header,
+            target: *target,
+            floor: first + (last - first) / 2 + 1,
+            next_top: last + 1,
+        });
+        let count = lock(&self.shared.slots).len();
+        for index in 0..count {
+            send_slice(&self.shared, index);
+        }
+        JobGuard { pool: self }
+    }
+
+    /// Candidates reported since the last call.
+    pub fn candidates(&self) -> impl Iterator<Item = Candidate> + '_ {
+        self.candidates.try_iter()
+    }
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        *lock(&self.pool.shared.job) = None;
+        for slot in lock(&self.pool.shared.slots).iter_mut() {
+            if let Some(writer) = &mut slot.writer {
+                let _ = writer.write_all(&Message::Stop.encode()).and_then(|_| writer.flush());
+            }
+            slot.slice_sent = None;
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Sends the next free slice of the current job to worker `index`, if there is a job,
+/// slices are left and the worker is connected.
+fn send_slice(shared: &Shared, index: usize) {
+    let mut job = lock(&shared.job);
+    let mut slots = lock(&shared.slots);
+    let (Some(job), Some(slot)) = (job.as_mut(), slots.get_mut(index)) else { return };
+    let Some(writer) = &mut slot.writer else { return };
+    if job.next_top <= job.floor {
+        return;
+    }
+    let first = job.next_top.saturating_sub(SLICE_SIZE).max(job.floor);
+    let nonces = first as u32..=(job.next_top - 1) as u32;
+    job.next_top = first;
+
+    let message = Message::Job {
+        job_id: job.id,
+        nonces,
+        midstate: pow::midstate(&job.header),
+        tail: job.header[64..76].try_into().unwrap(),
+        target: job.target,
+    };
+    // A broken connection is noticed and reported by the worker's reader thread.
+    if writer.write_all(&message.encode()).and_then(|_| writer.flush()).is_ok() {
+        slot.slice_sent = Some(Instant::now());
+    }
+}
+
+/// Connects to a worker, and keeps reconnecting, for the life of the process.
+fn run_worker(shared: &Shared, index: usize, spec: &str, candidates: &mpsc::Sender<Candidate>) {
+    loop {
+        match serve(shared, index, spec, candidates) {
+            Ok(()) => return,
+            Err(e) => {
+                let mut slots = lock(&shared.slots);
+                slots[index] = WorkerSlot::default();
+                let total = slots.iter().map(|s| s.hashrate).sum();
+                shared.stats.update(|s| s.worker_hashrate = total);
+                drop(slots);
+                eprintln!("[Workers] {}: {}; retrying in {:?}.", spec, e, RECONNECT_DELAY);
+                thread::sleep(RECONNECT_DELAY);
+            },
+        }
+    }
+}
+
+/// Handles one connection: handshake, then relays the worker's reports until it fails.
+/// Returns Ok only when the miner no longer listens for candidates.
+fn serve(shared: &Shared, index: usize, spec: &str, candidates: &mpsc::Sender<Candidate>) -> io::Result<()> {
+    let (reader, mut writer) = open(spec)?;
+    let mut reader = BufReader::new(reader);
+    writer.write_all(&Message::Ping.encode())?;
+    writer.flush()?;
+    let name = loop {
+        match Message::read(&mut reader)? {
+            Message::Hello { version, hashrate, name } if version == PROTOCOL_VERSION => {
+                let name = if name.is_empty() { spec.to_string() } else { format!("{} ({})", name, spec) };
+                println!("[Workers] {} connected, about {} H/s.", name, hashrate);
+                break name;
+            },
+            Message::Hello { version, .. } => return Err(io::Error::new(io::ErrorKind::Unsupported, format!(
+                "worker speaks protocol version {}, this miner version {}", version, PROTOCOL_VERSION
+            ))),
+            _ => {},
+        }
+    };
+    lock(&shared.slots)[index].writer = Some(writer);
+    send_slice(shared, index);
+
+    loop {
+        match Message::read(&mut reader)? {
+            Message::Nonce { job_id, nonce } => {
+                let header = match lock(&shared.job).as_ref() {
+                    Some(job) if job.id == job_id => job.header,
+                    _ => continue,
+                };
+                let mut header = header;
+                header[76..].copy_from_slice(&nonce.to_le_bytes());
+                if candidates.send(Candidate { worker: name.clone(), header }).is_err() {
+                    return Ok(());
+                }
+            },
+            Message::Done { job_id, hashes } => {
+                shared.stats.record_worker_hashes(u64::from(hashes));
+                let current = lock(&shared.job).as_ref().is_some_and(|job| job.id == job_id);
+                if !current {
+                    continue;
+                }
+                {
+                    let mut slots = lock(&shared.slots);
+                    if let Some(sent) = slots[index].slice_sent.take() {
+                        slots[index].hashrate = hashes as f64 / sent.elapsed().as_secs_f64().max(0.001);
+                    }
+                    let total = slots.iter().map(|s| s.hashrate).sum();
+                    shared.stats.update(|s| s.worker_hashrate = total);
+                }
+                send_slice(shared, index);
+            },
+            _ => {},
+        }
+    }
+}
+
+type Link = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+/// Opens a worker connection: TCP for "host:port", otherwise a serial device.
+fn open(spec: &str) -> io::Result<Link> {
+    let is_device = spec.starts_with('/') || spec.to_ascii_uppercase().starts_with("COM");
+    if !is_device {
+        let stream = TcpStream::connect(spec)?;
+        stream.set_nodelay(true)?;
+        return Ok((Box::new(stream.try_clone()?), Box::new(stream)));
+    }
+    let (path, baud) = match spec.rsplit_once('@') {
+        Some((path, baud)) => (path, baud.parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid serial speed in worker '{}'", spec))
+        })?),
+        None => (spec, DEFAULT_BAUD),
+    };
+    let port = OpenOptions::new().read(true).write(true).open(path)?;
+    configure_serial(&port, baud)?;
+    Ok((Box::new(port.try_clone()?), Box::new(port)))
+}
+
+/// Puts a serial port into raw 8N1 mode at `baud`.
+#[cfg(unix)]
+fn configure_serial(port: &File, baud: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let speed = match baud {
+        9_600 => libc::B9600,
+        19_200 => libc::B19200,
+        38_400 => libc::B38400,
+        57_600 => libc::B57600,
+        115_200 => libc::B115200,
+        230_400 => libc::B230400,
+        460_800 => libc::B460800,
+        921_600 => libc::B921600,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported serial speed {}", baud))),
+    };
+    // SAFETY: termios is plain data, filled in by tcgetattr before use, and the fd outlives the calls.
+    unsafe {
+        let mut tty: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(port.as_raw_fd(), &mut tty) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut tty);
+        libc::cfsetispeed(&mut tty, speed);
+        libc::cfsetospeed(&mut tty, speed);
+        if libc::tcsetattr(port.as_raw_fd(), libc::TCSANOW, &tty) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn configure_serial(_port: &File, _baud: u32) -> io::Result<()> {
+    // Set the port up with `mode COMx BAUD=115200 DATA=8 PARITY=N STOP=1` beforehand.
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! New-block notifications from Bitcoin Core over ZeroMQ (`zmqpubhashblock` or
+//! `zmqpubrawblock`), so work on a stale tip stops the moment a block arrives rather
+//! than at the next poll.
+//!
+//! Only what a subscriber needs of ZMTP 3.0 is implemented: the greeting, the NULL
+//! security handshake, subscriptions and reading messages. A notification is three
+//! frames: the topic, the block hash (or the raw block) and a sequence number.
+
+use crate::pow::sha256d;
+use crate::reconnect::Backoff;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for the publisher to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a quiet connection checks whether it should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Largest frame accepted: a raw block with some room to spare.
+const MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// Frame flags: more frames follow, an 8-byte size, a command rather than a message.
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Topics Bitcoin Core publishes new blocks under.
+const HASHBLOCK: &[u8] = b"hashblock";
+const RAWBLOCK: &[u8] = b"rawblock";
+
+/// The "host:port" of a `tcp://host:port` endpoint, as set with `zmqpubhashblock`.
+pub fn parse_endpoint(endpoint: &str) -> io::Result<&str> {
+    endpoint.strip_prefix("tcp://").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!(
+        "'{}' is not a tcp:// endpoint, e.g. tcp://127.0.0.1:28332", endpoint
+    )))
+}
+
+/// Calls `on_block` with the hash (hex, as displayed) of every new block published at
+/// `endpoint`, from a background thread that reconnects whenever the connection drops.
+/// Stops once `stop` is raised or `on_block` returns false.
+pub fn watch_blocks(endpoint: &str, stop: Arc<AtomicBool>, mut on_block: impl FnMut(String) -> bool + Send + 'static) -> io::Result<()> {
+    let endpoint = endpoint.to_string();
+    thread::Builder::new().name("zmq".to_string()).spawn(move || {
+        let mut outage: Option<Backoff> = None;
+        while !stop.load(Ordering::Relaxed) {
+            match listen(&endpoint, &stop, &mut outage, &mut on_block) {
+                Ok(()) => break,
+                Err(_) if stop.load(Ordering::Relaxed) => break,
+                Err(e) => {
+                    let delay = outage.get_or_insert_with(Backoff::new).next_delay();
+                    eprintln!("[ZMQ] {}: {}. Reconnecting in {}s.", endpoint, e, delay.as_secs());
+                    thread::sleep(delay);
+                },
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// One connection's worth of `watch_blocks`: returns once it should stop, or fails when
+/// the connection does.
+fn listen(endpoint: &str, stop: &AtomicBool, outage: &mut Option<Backoff>, on_block: &mut impl FnMut(String) -> bool) -> io::Result<()> {
+    let mut subscriber = Subscriber::connect(endpoint, stop)?;
+    if outage.take().is_some() {
+        println!("[ZMQ] Reconnected to {}.", endpoint);
+    }
+    // The same block may come under both topics
+    let mut last = None;
+    while let Some(hash) = subscriber.next_block(stop)? {
+        if last.as_ref() != Some(&hash) {
+            last = Some(hash.clone());
+            if !on_block(hash) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A ZMTP 3.0 SUB connection to a publisher, subscribed to Bitcoin Core's block topics.
+struct Subscriber {
+    stream: TcpStream,
+    /// Bytes read but not yet parsed into frames.
+    buffer: Vec<u8>,
+}
+
+/// One frame of a message or command.
+struct Frame {
+    more: bool,
+    command: bool,
+    body: Vec<u8>,
+}
+
+impl Subscriber {
+    fn connect(endpoint: &str, stop: &AtomicBool) -> io::Result<Subscriber> {
+        let address = parse_endpoint(endpoint)?;
+        let addr = address.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Could not resolve '{}'", address)))?;
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+        let mut subscriber = Subscriber { stream, buffer: Vec::new() };
+
+        // 1. Greetings: signature, version 3.0, the NULL mechanism, and we're the client
+        let mut greeting = [0u8; 64];
+        greeting[0] = 0xff;
+        greeting[9] = 0x7f;
+        greeting[10] = 3;
+        greeting[12..16].copy_from_slice(b"NULL");
+        subscriber.stream.write_all(&greeting)?;
+        if !subscriber.fill(greeting.len(), stop)? {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Stopped during the ZMQ handshake"));
+        }
+        let theirs: Vec<u8> = subscriber.buffer.drain(..greeting.len()).collect();
+        if theirs[0] != 0xff || theirs[9] != 0x7f {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a ZMQ publisher"));
+        }
+        if theirs[10] < 3 || &theirs[12..16] != b"NULL" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "The publisher speaks ZMTP {}.{} with {}; only ZMTP 3 without security is supported",
+                theirs[10], theirs[11], String::from_utf8_lossy(&theirs[12..32]).trim_end_matches('\0')
+            )));
+        }
+
+        // 2. NULL handshake: each side sends READY with its socket type
+        let mut ready = command_name(b"READY");
+        ready.push(b"Socket-Type".len() as u8);
+        ready.extend_from_slice(b"Socket-Type");
+        ready.extend_from_slice(&(b"SUB".len() as u32).to_be_bytes());
+        ready.extend_from_slice(b"SUB");
+        subscriber.send(FLAG_COMMAND, &ready)?;
+        match subscriber.next_frame(stop)? {
+            Some(frame) if frame.command && frame.body.starts_with(&command_name(b"READY")) => {},
+            Some(frame) if frame.command && frame.body.starts_with(&command_name(b"ERROR")) => {
+                let reason = frame.body.get(7..).map_or(String::new(), |reason| String::from_utf8_lossy(reason).into_owned());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("The publisher refused the connection: {}", reason)));
+            },
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected READY from the publisher")),
+            None => return Err(io::Error::new(io::ErrorKind::Interrupted, "Stopped during the ZMQ handshake")),
+        }
+
+        // 3. Subscribe: in ZMTP 3.0, a message of 1 followed by the topic
+        for topic in [HASHBLOCK, RAWBLOCK] {
+            subscriber.send(0, &[&[1u8], topic].concat())?;
+        }
+        Ok(subscriber)
+    }
+
+    /// The hash (hex, as displayed) of the next block published, or `None` once `stop`
+    /// is raised.
+    fn next_block(&mut self, stop: &AtomicBool) -> io::Result<Option<String>> {
+        loop {
+            let mut parts = Vec::new();
+            loop {
+                let Some(frame) = self.next_frame(stop)? else { return Ok(None) };
+                if frame.command {
+                    continue;
+                }
+                let more = frame.more;
+                parts.push(frame.body);
+                if !more {
+                    break;
+                }
+            }
+            match parts.as_slice() {
+                // Bitcoin Core publishes the hash byte-reversed, as it's displayed
+                [topic, hash, ..] if topic == HASHBLOCK && hash.len() == 32 => return Ok(Some(hex::encode(hash))),
+                [topic, block, ..] if topic == RAWBLOCK && block.len() >= 80 => {
+                    let mut hash = sha256d(&block[..80]);
+                    hash.reverse();
+                    return Ok(Some(hex::encode(hash)));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn send(&mut self, flags: u8, body: &[u8]) -> io::Result<()> {
+        let mut frame = match u8::try_from(body.len()) {
+            Ok(size) => vec![flags, size],
+            Err(_) => [&[flags | FLAG_LONG][..], &(body.len() as u64).to_be_bytes()].concat(),
+        };
+        frame.extend_from_slice(body);
+        self.stream.write_all(&frame)
+    }
+
+    /// The next frame, or `None` once `stop` is raised.
+    fn next_frame(&mut self, stop: &AtomicBool) -> io::Result<Option<Frame>> {
+        if !self.fill(2, stop)? {
+            return Ok(None);
+        }
+        let flags = self.buffer[0];
+        let (header, size) = if flags & FLAG_LONG != 0 {
+            if !self.fill(9, stop)? {
+                return Ok(None);
+            }
+            (9, u64::from_be_bytes(self.buffer[1..9].try_into().unwrap()))
+        } else {
+            (2, u64::from(self.buffer[1]))
+        };
+        let size = usize::try_from(size).ok().filter(|size| *size <= MAX_FRAME_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("ZMQ frame of {} bytes is too large", size)))?;
+        if !self.fill(header + size, stop)? {
+            return Ok(None);
+        }
+        let body = self.buffer[header..header + size].to_vec();
+        self.buffer.drain(..header + size);
+        Ok(Some(Frame { more: flags & FLAG_MORE != 0, command: flags & FLAG_COMMAND != 0, body }))
+    }
+
+    /// Reads until at least `len` bytes are buffered. Returns false if `stop` is raised
+    /// first; reads time out regularly so it's noticed.
+    fn fill(&mut self, len: usize, stop: &AtomicBool) -> io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        while self.buffer.len() < len {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The publisher closed the connection")),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The start of a command's body: its name, prefixed by the name's length.
+fn command_name(name: &[u8]) -> Vec<u8> {
+    [&[name.len() as u8][..], name].concat()
+}